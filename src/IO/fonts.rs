@@ -0,0 +1,301 @@
+use std::path::Path;
+use floem_renderer::text::{fontdb, FONT_SYSTEM};
+use lightningcss::properties::font::FontFamily;
+use lightningcss::rules::CssRule;
+use lightningcss::rules::font_face::{FontFaceProperty, Source};
+use lightningcss::stylesheet::StyleSheet;
+use lightningcss::traits::ToCss;
+use rbook::Epub;
+use roxmltree::Document;
+use rustc_data_structures::fx::FxHashMap;
+use sha1::{Digest, Sha1};
+
+use crate::book_elem::resolve_path;
+use crate::IO::woff::decode_font;
+
+const IDPF_ALGORITHM: &str  = "http://www.idpf.org/2008/embedding";
+const ADOBE_ALGORITHM: &str = "http://ns.adobe.com/pdf/enc#RC";
+const IDPF_HEADER_LEN: usize    = 1040;
+const ADOBE_HEADER_LEN: usize   = 1024;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Obfuscation { Idpf, Adobe }
+
+/// Fonts embedded in the book, registered with the shared font system until dropped.
+#[derive(Default)]
+pub struct EmbeddedFonts {
+    face_ids: Vec<fontdb::ID>,
+    /// Lowercased `@font-face` family name to the family name stored in the font file
+    families: FxHashMap<String, String>,
+}
+
+impl EmbeddedFonts {
+    pub fn family_name(&self, css_family: &str) -> Option<&str> {
+        self.families.get(&css_family.to_lowercase()).map(|name| name.as_str())
+    }
+}
+
+impl Drop for EmbeddedFonts {
+    fn drop(&mut self) {
+        if self.face_ids.is_empty() { return }
+        let mut font_system = FONT_SYSTEM.lock();
+        for id in self.face_ids.drain(..) {
+            font_system.db_mut().remove_face(id);
+        }
+    }
+}
+
+/// Loads every font referenced by an `@font-face` rule, removing publisher obfuscation first.
+/// `css_paths` are the manifest paths of `style_sheets`, used to resolve relative `src` urls.
+pub fn load_embedded_fonts(epub: &Epub, css_paths: &[String], style_sheets: &[StyleSheet]) -> EmbeddedFonts {
+    let encryption      = read_encryption(epub);
+    let mut face_ids    = Vec::new();
+    let faces           = css_paths.iter().zip(style_sheets).flat_map(|(css_path, style_sheet)| font_faces(css_path, style_sheet));
+    let families        = resolve_families(faces, |font_path| load_font(epub, font_path, encryption.get(font_path).copied(), &mut face_ids));
+    EmbeddedFonts { face_ids, families }
+}
+
+/// The family and resolved `src` url paths of each `@font-face` rule, `local()` sources left out
+fn font_faces(css_path: &str, style_sheet: &StyleSheet) -> Vec<(String, Vec<String>)> {
+    let mut faces = Vec::new();
+    for rule in style_sheet.rules.0.iter() {
+        let CssRule::FontFace(font_face) = rule else { continue };
+        let mut family  = None;
+        let mut urls    = Vec::new();
+        for property in &font_face.properties {
+            match property {
+                FontFaceProperty::FontFamily(FontFamily::FamilyName(name)) => {
+                    family = name.to_css_string(Default::default()).ok().map(|name| unquote(&name));
+                }
+                FontFaceProperty::Source(sources) => {
+                    urls.extend(sources.iter().filter_map(|source| match source {
+                        Source::Url(url_source) => Some(url_source.url.url.to_string()),
+                        Source::Local(_) => None,
+                    }));
+                }
+                _ => ()
+            }
+        }
+        let Some(family) = family else { continue };
+        let font_paths = urls.iter()
+            .map(|url| resolve_path(css_path, &percent_decode(url.split(['#', '?']).next().unwrap_or_default())))
+            .collect();
+        faces.push((family, font_paths));
+    }
+    faces
+}
+
+/// Maps each lowercased family to the font family of its first source that loads, later sources are fallbacks.
+/// The first rule naming a family wins, and each font file is loaded once however many rules share it.
+fn resolve_families(faces: impl IntoIterator<Item = (String, Vec<String>)>, mut load: impl FnMut(&str) -> Option<String>) -> FxHashMap<String, String> {
+    let mut families = FxHashMap::default();
+    let mut loaded: FxHashMap<String, Option<String>> = FxHashMap::default();
+    for (family, font_paths) in faces {
+        for font_path in font_paths {
+            let real_family = match loaded.get(&font_path) {
+                Some(real_family) => real_family.clone(),
+                None => {
+                    let real_family = load(&font_path);
+                    loaded.insert(font_path, real_family.clone());
+                    real_family
+                }
+            };
+            if let Some(real_family) = real_family {
+                families.entry(family.to_lowercase()).or_insert(real_family);
+                break
+            }
+        }
+    }
+    families
+}
+
+fn load_font(epub: &Epub, font_path: &str, obfuscation: Option<Obfuscation>, face_ids: &mut Vec<fontdb::ID>) -> Option<String> {
+    let mut data = epub.read_bytes_file(font_path).ok()?;
+    if let Some(obfuscation) = obfuscation {
+        deobfuscate(&mut data, obfuscation, epub);
+    }
+    let data = decode_font(data)?;
+
+    let mut font_system = FONT_SYSTEM.lock();
+    let db              = font_system.db_mut();
+    let ids             = db.load_font_source(fontdb::Source::Binary(std::sync::Arc::new(data)));
+    let family          = ids.first()
+        .and_then(|id| db.face(*id))
+        .and_then(|face| face.families.first())
+        .map(|(name, _)| name.clone());
+    face_ids.extend(ids);
+    family
+}
+
+/// Maps manifest paths of obfuscated resources to their algorithm, read from `META-INF/encryption.xml`
+pub fn read_encryption(epub: &Epub) -> FxHashMap<String, Obfuscation> {
+    let mut encryption = FxHashMap::default();
+    let Ok(xml) = epub.read_file("META-INF/encryption.xml") else { return encryption };
+    let Ok(document) = Document::parse(&xml) else { return encryption };
+    let root_dir = epub.root_file_directory();
+
+    for encrypted_data in document.descendants().filter(|node| node.has_tag_name("EncryptedData")) {
+        let algorithm = encrypted_data.descendants()
+            .find(|node| node.has_tag_name("EncryptionMethod"))
+            .and_then(|node| node.attribute("Algorithm"));
+        let obfuscation = match algorithm {
+            Some(IDPF_ALGORITHM)    => Obfuscation::Idpf,
+            Some(ADOBE_ALGORITHM)   => Obfuscation::Adobe,
+            _                       => continue,
+        };
+        let uri = encrypted_data.descendants()
+            .find(|node| node.has_tag_name("CipherReference"))
+            .and_then(|node| node.attribute("URI"));
+        if let Some(uri) = uri {
+            let uri     = percent_decode(uri);
+            let path    = Path::new(&uri).strip_prefix(root_dir).map(|p| p.to_str().unwrap().to_string()).unwrap_or(uri);
+            encryption.insert(path, obfuscation);
+        }
+    }
+    encryption
+}
+
+pub fn deobfuscate(data: &mut [u8], obfuscation: Obfuscation, epub: &Epub) {
+    let metadata    = epub.metadata();
+    let unique      = metadata.unique_identifier().map(|identifier| identifier.value());
+    let key = match obfuscation {
+        Obfuscation::Idpf   => unique.map(idpf_key).unwrap_or_default(),
+        Obfuscation::Adobe  => adobe_key(metadata.get("identifier").iter().map(|identifier| identifier.value()).chain(unique)),
+    };
+    xor_header(data, &key, obfuscation);
+}
+
+// XORs the obfuscated header with the key, which undoes the obfuscation as it is its own inverse
+fn xor_header(data: &mut [u8], key: &[u8], obfuscation: Obfuscation) {
+    if key.is_empty() { return }
+    let header_len = match obfuscation {
+        Obfuscation::Idpf   => IDPF_HEADER_LEN,
+        Obfuscation::Adobe  => ADOBE_HEADER_LEN,
+    };
+    for (i, byte) in data.iter_mut().take(header_len).enumerate() {
+        *byte ^= key[i % key.len()];
+    }
+}
+
+// SHA-1 of the package unique identifier with all whitespace removed
+fn idpf_key(identifier: &str) -> Vec<u8> {
+    let identifier: String = identifier.chars()
+        .filter(|ch| !matches!(ch, ' ' | '\t' | '\r' | '\n'))
+        .collect();
+    Sha1::digest(identifier.as_bytes()).to_vec()
+}
+
+// The 16 bytes of the first urn:uuid identifier
+fn adobe_key<'a>(identifiers: impl IntoIterator<Item = &'a str>) -> Vec<u8> {
+    let uuid = identifiers.into_iter()
+        .find(|value| value.trim().starts_with("urn:uuid:"))
+        .map(|value| value.trim().trim_start_matches("urn:uuid:"));
+    let Some(uuid) = uuid else { return Vec::new() };
+    let hex: Vec<u8> = uuid.bytes().filter(|byte| byte.is_ascii_hexdigit()).collect();
+    if hex.len() != 32 { return Vec::new() }
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+        .collect()
+}
+
+fn unquote(name: &str) -> String {
+    name.trim().trim_matches(|ch| ch == '"' || ch == '\'').to_string()
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Ok(byte) = u8::from_str_radix(std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default(), 16) {
+                decoded.push(byte);
+                i += 3;
+                continue
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use lightningcss::stylesheet::{ParserOptions, StyleSheet};
+    use super::*;
+
+    #[test]
+    fn idpf_key_ignores_whitespace() {
+        let key = idpf_key(" urn:uuid:B1E6A2F4-1234\n\t");
+        let expected = "6d20d95ddc28d1455b29da4ae2592643c9f8cd7f";
+        let hex: String = key.iter().map(|byte| format!("{byte:02x}")).collect();
+        assert_eq!(hex, expected);
+    }
+
+    #[test]
+    fn adobe_key_uses_first_uuid() {
+        let key = adobe_key(["isbn:9780000000000", " urn:uuid:01234567-89ab-CDEF-0123-456789abcdef ", "urn:uuid:ffffffff-ffff-ffff-ffff-ffffffffffff"]);
+        assert_eq!(key, [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef]);
+        assert!(adobe_key(["isbn:9780000000000"]).is_empty());
+        assert!(adobe_key(["urn:uuid:0123"]).is_empty());
+    }
+
+    #[test]
+    fn xor_header_only_touches_header() {
+        let original: Vec<u8> = (0..2000).map(|i| (i % 251) as u8).collect();
+        let key = [0xAA, 0x55, 0x0F];
+        let mut data = original.clone();
+        xor_header(&mut data, &key, Obfuscation::Adobe);
+        assert_eq!(data[0], original[0] ^ 0xAA);
+        assert_eq!(data[1023], original[1023] ^ key[1023 % 3]);
+        assert_eq!(data[ADOBE_HEADER_LEN..], original[ADOBE_HEADER_LEN..]);
+        xor_header(&mut data, &key, Obfuscation::Adobe);
+        assert_eq!(data, original);
+
+        xor_header(&mut data, &key, Obfuscation::Idpf);
+        assert_ne!(data[1039], original[1039]);
+        assert_eq!(data[IDPF_HEADER_LEN..], original[IDPF_HEADER_LEN..]);
+    }
+
+    #[test]
+    fn font_face_sources_resolve_against_style_sheet() {
+        let css = r#"
+            @font-face {
+                font-family: "Book Serif";
+                src: local("Book Serif"), url("../fonts/Book%20Serif.woff2#face") format("woff2"), url(../fonts/serif.ttf);
+            }
+            @font-face { src: url(../fonts/nameless.ttf); }
+            p { font-family: "Book Serif"; }
+        "#;
+        let style_sheet = StyleSheet::parse(css, ParserOptions::default()).unwrap();
+        let faces = font_faces("OEBPS/css/style.css", &style_sheet);
+        assert_eq!(faces, vec![("Book Serif".to_string(), vec!["OEBPS/fonts/Book Serif.woff2".to_string(), "OEBPS/fonts/serif.ttf".to_string()])]);
+    }
+
+    #[test]
+    fn families_fall_back_to_later_sources() {
+        let faces = vec![
+            ("Book Serif".to_string(), vec!["broken.woff".to_string(), "serif.ttf".to_string()]),
+            ("book serif".to_string(), vec!["other.ttf".to_string()]),
+            ("Caption".to_string(), vec!["broken.woff".to_string()]),
+            ("Small Caps".to_string(), vec!["serif.ttf".to_string()]),
+        ];
+        let mut loads = Vec::new();
+        let families = resolve_families(faces, |font_path| {
+            loads.push(font_path.to_string());
+            match font_path {
+                "broken.woff"   => None,
+                "serif.ttf"     => Some("Serif Regular".to_string()),
+                _               => Some("Other".to_string()),
+            }
+        });
+        assert_eq!(families.get("book serif").map(String::as_str), Some("Serif Regular"));
+        assert_eq!(families.get("small caps").map(String::as_str), Some("Serif Regular"));
+        assert!(!families.contains_key("caption"));
+        assert_eq!(loads, ["broken.woff", "serif.ttf", "other.ttf"]);
+
+        let fonts = EmbeddedFonts { face_ids: Vec::new(), families };
+        assert_eq!(fonts.family_name("BOOK SERIF"), Some("Serif Regular"));
+    }
+}
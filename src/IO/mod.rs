@@ -1,4 +1,7 @@
 pub mod epub;
+pub mod fonts;
 pub mod home;
 pub mod library;
-mod util;
\ No newline at end of file
+pub mod svg;
+mod util;
+mod woff;
\ No newline at end of file
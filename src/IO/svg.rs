@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use floem_renderer::usvg::{Error, ImageHrefResolver, ImageKind, Options, Tree};
+use roxmltree::{Node, NodeType};
+use sha2::{Digest, Sha256};
+
+use crate::book_elem::{resolve_path, SvgImage};
+
+const SVG_NAMESPACE: &str   = "http://www.w3.org/2000/svg";
+pub const XLINK_NAMESPACE: &str = "http://www.w3.org/1999/xlink";
+const XML_NAMESPACE: &str   = "http://www.w3.org/XML/1998/namespace";
+
+/// Parses an SVG resource, `<image>` references resolve against the book's raster images relative to `svg_path`
+pub fn load_svg(data: &[u8], svg_path: &str, images: &HashMap<String, Arc<Vec<u8>>>) -> Result<SvgImage, Error> {
+    let base_path   = svg_path.to_string();
+    let mut options = Options::default();
+    options.image_href_resolver = ImageHrefResolver {
+        resolve_data: ImageHrefResolver::default_data_resolver(),
+        resolve_string: Box::new(move |href: &str, _: &Options| {
+            let data = images.get(&resolve_path(&base_path, href))?.clone();
+            image_kind(href, data)
+        }),
+    };
+    let tree = Tree::from_data(data, &options)?;
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    Ok(SvgImage { tree, hash: hasher.finalize().to_vec() })
+}
+
+/// Parses an `<svg>` element of a page, written out again from its parsed nodes so the namespaces it inherited from the page are declared
+pub fn load_inline_svg(node: Node, html_path: &str, images: &HashMap<String, Arc<Vec<u8>>>) -> Result<SvgImage, Error> {
+    let mut source = String::new();
+    write_node(node, node.tag_name().namespace(), true, &mut source);
+    load_svg(source.as_bytes(), html_path, images)
+}
+
+// Only elements of the root's namespace, SVG unless the page left it undeclared, and their plain, xlink and xml attributes are written
+fn write_node(node: Node, namespace: Option<&str>, root: bool, out: &mut String) {
+    match node.node_type() {
+        NodeType::Text => escape(node.text().unwrap_or_default(), out),
+        NodeType::Element if node.tag_name().namespace() == namespace => {
+            let name = node.tag_name().name();
+            out.push('<');
+            out.push_str(name);
+            if root {
+                out.push_str(&format!(" xmlns=\"{SVG_NAMESPACE}\" xmlns:xlink=\"{XLINK_NAMESPACE}\""));
+            }
+            for attribute in node.attributes() {
+                let prefix = match attribute.namespace() {
+                    None                    => "",
+                    Some(XLINK_NAMESPACE)   => "xlink:",
+                    Some(XML_NAMESPACE)     => "xml:",
+                    Some(_)                 => continue,
+                };
+                out.push_str(&format!(" {prefix}{}=\"", attribute.name()));
+                escape(attribute.value(), out);
+                out.push('"');
+            }
+            out.push('>');
+            for child in node.children() {
+                write_node(child, namespace, false, out);
+            }
+            out.push_str(&format!("</{name}>"));
+        }
+        _ => {}
+    }
+}
+
+fn escape(text: &str, out: &mut String) {
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _   => out.push(ch),
+        }
+    }
+}
+
+fn image_kind(href: &str, data: Arc<Vec<u8>>) -> Option<ImageKind> {
+    match href.rsplit('.').next()?.to_lowercase().as_str() {
+        "jpg" | "jpeg"  => Some(ImageKind::JPEG(data)),
+        "png"           => Some(ImageKind::PNG(data)),
+        "gif"           => Some(ImageKind::GIF(data)),
+        "webp"          => Some(ImageKind::WEBP(data)),
+        _               => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use roxmltree::Document;
+
+    fn inline_source(page: &str) -> String {
+        let document = Document::parse(page).unwrap();
+        let svg = document.descendants().find(|node| node.has_tag_name("svg")).unwrap();
+        let mut source = String::new();
+        write_node(svg, svg.tag_name().namespace(), true, &mut source);
+        source
+    }
+
+    #[test]
+    fn attribute_values_keep_their_angle_brackets() {
+        let page = r#"<html xmlns="http://www.w3.org/1999/xhtml"><body><svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><text data-note="a > b">x &amp; y</text></svg></body></html>"#;
+        let source = inline_source(page);
+        assert!(source.contains(r#"data-note="a &gt; b""#));
+        assert!(source.contains("x &amp; y"));
+        assert!(Tree::from_str(&source, &Options::default()).is_ok());
+    }
+
+    #[test]
+    fn self_closing_svg_inherits_the_page_namespace() {
+        let page = r#"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:xlink="http://www.w3.org/1999/xlink"><body><svg width="4" height="4"/><p/></body></html>"#;
+        let source = inline_source(page);
+        assert_eq!(source, format!(r#"<svg xmlns="{SVG_NAMESPACE}" xmlns:xlink="{XLINK_NAMESPACE}" width="4" height="4"></svg>"#));
+        let svg = load_svg(source.as_bytes(), "page.xhtml", &HashMap::new()).unwrap();
+        assert_eq!(svg.tree.size().width(), 4.);
+    }
+}
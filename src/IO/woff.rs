@@ -0,0 +1,537 @@
+use std::io::Read;
+use brotli_decompressor::Decompressor;
+use flate2::read::ZlibDecoder;
+
+const WOFF_SIGNATURE: u32   = 0x774F_4646; // "wOFF"
+const WOFF2_SIGNATURE: u32  = 0x774F_4632; // "wOF2"
+const TTC_FLAVOR: u32       = 0x7474_6366; // "ttcf"
+
+const GLYF: u32 = 0x676C_7966;
+const LOCA: u32 = 0x6C6F_6361;
+const HMTX: u32 = 0x686D_7478;
+const HHEA: u32 = 0x6868_6561;
+const HEAD: u32 = 0x6865_6164;
+
+// Offset of indexToLocFormat in the `head` table
+const INDEX_TO_LOC_FORMAT: usize = 50;
+
+// Tags addressed by index in the WOFF2 table directory
+const KNOWN_TAGS: [&[u8; 4]; 63] = [
+    b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"OS/2", b"post",
+    b"cvt ", b"fpgm", b"glyf", b"loca", b"prep", b"CFF ", b"VORG", b"EBDT",
+    b"EBLC", b"gasp", b"hdmx", b"kern", b"LTSH", b"PCLT", b"VDMX", b"vhea",
+    b"vmtx", b"BASE", b"GDEF", b"GPOS", b"GSUB", b"EBSC", b"JSTF", b"MATH",
+    b"CBDT", b"CBLC", b"COLR", b"CPAL", b"SVG ", b"sbix", b"acnt", b"avar",
+    b"bdat", b"bloc", b"bsln", b"cvar", b"fdsc", b"feat", b"fmtx", b"fvar",
+    b"gvar", b"hsty", b"just", b"lcar", b"mort", b"morx", b"opbd", b"prop",
+    b"trak", b"Zapf", b"Silf", b"Glat", b"Gloc", b"Feat", b"Sill",
+];
+
+/// Returns the font as a plain TrueType/OpenType file, unwrapping WOFF and WOFF2 containers.
+/// Fonts that are already sfnt are returned unchanged.
+pub fn decode_font(data: Vec<u8>) -> Option<Vec<u8>> {
+    match read_u32(&data, 0)? {
+        WOFF_SIGNATURE  => decode_woff(&data),
+        WOFF2_SIGNATURE => decode_woff2(&data),
+        _               => Some(data),
+    }
+}
+
+fn decode_woff(data: &[u8]) -> Option<Vec<u8>> {
+    let flavor      = read_u32(data, 4)?;
+    let num_tables  = read_u16(data, 12)? as usize;
+    let mut tables  = Vec::with_capacity(num_tables);
+    for i in 0..num_tables {
+        let entry       = 44 + i * 20;
+        let tag         = read_u32(data, entry)?;
+        let offset      = read_u32(data, entry + 4)? as usize;
+        let comp_length = read_u32(data, entry + 8)? as usize;
+        let orig_length = read_u32(data, entry + 12)? as usize;
+        let compressed  = data.get(offset..offset + comp_length)?;
+        let table = if comp_length < orig_length {
+            let mut table = Vec::with_capacity(orig_length);
+            ZlibDecoder::new(compressed).read_to_end(&mut table).ok()?;
+            table
+        } else {
+            compressed.to_vec()
+        };
+        tables.push((tag, table));
+    }
+    Some(build_sfnt(flavor, tables))
+}
+
+struct Woff2Table {
+    tag: u32,
+    orig_length: usize,
+    length: usize,
+    transformed: bool,
+}
+
+fn decode_woff2(data: &[u8]) -> Option<Vec<u8>> {
+    let flavor          = read_u32(data, 4)?;
+    let num_tables      = read_u16(data, 12)? as usize;
+    let compressed_size = read_u32(data, 20)? as usize;
+    // Font collections are not supported
+    if flavor == TTC_FLAVOR { return None }
+
+    let mut pos = 48;
+    let mut directory = Vec::with_capacity(num_tables);
+    for _ in 0..num_tables {
+        let flags = *data.get(pos)?;
+        pos += 1;
+        let tag = match flags & 0x3f {
+            0x3f => { pos += 4; read_u32(data, pos - 4)? }
+            index => u32::from_be_bytes(*KNOWN_TAGS[index as usize]),
+        };
+        let version         = flags >> 6;
+        let orig_length     = read_base128(data, &mut pos)? as usize;
+        let transformed     = if tag == GLYF || tag == LOCA { version == 0 } else { version != 0 };
+        let length          = if transformed { read_base128(data, &mut pos)? as usize } else { orig_length };
+        directory.push(Woff2Table { tag, orig_length, length, transformed });
+    }
+
+    let mut stream = Vec::new();
+    Decompressor::new(data.get(pos..pos + compressed_size)?, 4096).read_to_end(&mut stream).ok()?;
+
+    let mut table_data: Vec<(u32, &[u8])> = Vec::with_capacity(num_tables);
+    let mut offset = 0;
+    for table in &directory {
+        table_data.push((table.tag, stream.get(offset..offset + table.length)?));
+        offset += table.length;
+    }
+    let find = |tag: u32| table_data.iter().find(|(t, _)| *t == tag).map(|(_, data)| *data);
+
+    let mut tables: Vec<(u32, Vec<u8>)> = Vec::with_capacity(num_tables);
+    let mut x_mins: Vec<i16> = Vec::new();
+    let mut long_loca = false;
+    if directory.iter().any(|t| t.tag == GLYF && t.transformed) {
+        let (glyf, loca, mins, widened) = reconstruct_glyf(find(GLYF)?)?;
+        let loca_length = directory.iter().find(|t| t.tag == LOCA).map(|t| t.orig_length).unwrap_or(loca.len());
+        // A widened loca has twice the bytes of the short one it replaces
+        if loca.len() != if widened { loca_length * 2 } else { loca_length } { return None }
+        x_mins = mins;
+        long_loca = widened;
+        tables.push((GLYF, glyf));
+        tables.push((LOCA, loca));
+    }
+    for table in &directory {
+        if tables.iter().any(|(tag, _)| *tag == table.tag) { continue }
+        let data = find(table.tag)?;
+        if table.tag == HMTX && table.transformed {
+            let num_h_metrics = read_u16(find(HHEA)?, 34)? as usize;
+            tables.push((HMTX, reconstruct_hmtx(data, num_h_metrics, &x_mins)?));
+            continue
+        }
+        tables.push((table.tag, data.to_vec()));
+    }
+    if long_loca {
+        let (_, head) = tables.iter_mut().find(|(tag, _)| *tag == HEAD)?;
+        head.get_mut(INDEX_TO_LOC_FORMAT..INDEX_TO_LOC_FORMAT + 2)?.copy_from_slice(&1u16.to_be_bytes());
+    }
+    Some(build_sfnt(flavor, tables))
+}
+
+/// Rebuilds the `glyf` and `loca` tables from the WOFF2 transformed glyf stream.
+/// Also returns each glyph's xMin, which the transformed `hmtx` table needs, and whether `loca` had to be widened to the long format.
+fn reconstruct_glyf(data: &[u8]) -> Option<(Vec<u8>, Vec<u8>, Vec<i16>, bool)> {
+    let option_flags    = read_u16(data, 2)?;
+    let num_glyphs      = read_u16(data, 4)? as usize;
+    let index_format    = read_u16(data, 6)?;
+    let mut offset      = 36;
+    let mut streams: Vec<&[u8]> = Vec::with_capacity(7);
+    for i in 0..7 {
+        let size = read_u32(data, 8 + i * 4)? as usize;
+        streams.push(data.get(offset..offset + size)?);
+        offset += size;
+    }
+    let overlap_bitmap = if option_flags & 1 != 0 { data.get(offset..offset + (num_glyphs + 7) / 8) } else { None };
+    let (n_contours, n_points, flags, glyphs, composites, bboxes, instructions) =
+        (streams[0], streams[1], streams[2], streams[3], streams[4], streams[5], streams[6]);
+
+    let bbox_bitmap_len = 4 * ((num_glyphs + 31) / 32);
+    let bbox_bitmap     = bboxes.get(..bbox_bitmap_len)?;
+    let (mut contour_pos, mut points_pos, mut flag_pos, mut glyph_pos) = (0, 0, 0, 0);
+    let (mut composite_pos, mut bbox_pos, mut instruction_pos) = (0, bbox_bitmap_len, 0);
+
+    let mut glyf    = Vec::with_capacity(data.len() * 2);
+    let mut offsets = Vec::with_capacity(num_glyphs + 1);
+    let mut x_mins  = vec![0i16; num_glyphs];
+    for glyph_index in 0..num_glyphs {
+        offsets.push(glyf.len());
+        let contour_count   = read_u16(n_contours, contour_pos)? as i16;
+        contour_pos         += 2;
+        let has_bbox        = bbox_bitmap[glyph_index / 8] & (0x80 >> (glyph_index % 8)) != 0;
+        if contour_count == 0 { continue }
+
+        if contour_count < 0 {
+            let start = composite_pos;
+            let mut has_instructions = false;
+            loop {
+                let component_flags = read_u16(composites, composite_pos)?;
+                let mut size = 4 + if component_flags & 0x0001 != 0 { 4 } else { 2 };
+                if component_flags & 0x0008 != 0 { size += 2 }
+                else if component_flags & 0x0040 != 0 { size += 4 }
+                else if component_flags & 0x0080 != 0 { size += 8 }
+                has_instructions |= component_flags & 0x0100 != 0;
+                composite_pos += size;
+                if component_flags & 0x0020 == 0 { break }
+            }
+            let bbox = bboxes.get(bbox_pos..bbox_pos + 8)?;
+            bbox_pos += 8;
+            x_mins[glyph_index] = read_u16(bbox, 0)? as i16;
+            glyf.extend_from_slice(&(-1i16).to_be_bytes());
+            glyf.extend_from_slice(bbox);
+            glyf.extend_from_slice(composites.get(start..composite_pos)?);
+            if has_instructions {
+                let length = read_255_u16(glyphs, &mut glyph_pos)? as usize;
+                glyf.extend_from_slice(&(length as u16).to_be_bytes());
+                glyf.extend_from_slice(instructions.get(instruction_pos..instruction_pos + length)?);
+                instruction_pos += length;
+            }
+        }
+        else {
+            let mut end_points  = Vec::with_capacity(contour_count as usize);
+            let mut total       = 0usize;
+            for _ in 0..contour_count {
+                total += read_255_u16(n_points, &mut points_pos)? as usize;
+                end_points.push((total - 1) as u16);
+            }
+            let mut points: Vec<(i32, i32, bool)> = Vec::with_capacity(total);
+            let (mut x, mut y) = (0i32, 0i32);
+            for _ in 0..total {
+                let flag        = *flags.get(flag_pos)?;
+                flag_pos        += 1;
+                let on_curve    = flag & 0x80 == 0;
+                let (dx, dy, used) = decode_triplet(flag & 0x7f, glyphs.get(glyph_pos..)?)?;
+                glyph_pos       += used;
+                x += dx;
+                y += dy;
+                points.push((x, y, on_curve));
+            }
+            let instruction_length = read_255_u16(glyphs, &mut glyph_pos)? as usize;
+
+            let bbox = if has_bbox {
+                let bbox = bboxes.get(bbox_pos..bbox_pos + 8)?;
+                bbox_pos += 8;
+                bbox.to_vec()
+            } else {
+                let x_min = points.iter().map(|p| p.0).min().unwrap_or(0) as i16;
+                let y_min = points.iter().map(|p| p.1).min().unwrap_or(0) as i16;
+                let x_max = points.iter().map(|p| p.0).max().unwrap_or(0) as i16;
+                let y_max = points.iter().map(|p| p.1).max().unwrap_or(0) as i16;
+                [x_min, y_min, x_max, y_max].iter().flat_map(|v| v.to_be_bytes()).collect()
+            };
+            x_mins[glyph_index] = read_u16(&bbox, 0)? as i16;
+
+            glyf.extend_from_slice(&contour_count.to_be_bytes());
+            glyf.extend_from_slice(&bbox);
+            for end_point in end_points { glyf.extend_from_slice(&end_point.to_be_bytes()); }
+            glyf.extend_from_slice(&(instruction_length as u16).to_be_bytes());
+            glyf.extend_from_slice(instructions.get(instruction_pos..instruction_pos + instruction_length)?);
+            instruction_pos += instruction_length;
+
+            // Coordinates are written uncompressed as 16-bit deltas, which is valid if not minimal
+            let overlap = overlap_bitmap.map_or(false, |bitmap| bitmap[glyph_index / 8] & (0x80 >> (glyph_index % 8)) != 0);
+            for (i, point) in points.iter().enumerate() {
+                let mut flag = if point.2 { 0x01 } else { 0x00 };
+                if i == 0 && overlap { flag |= 0x40 }
+                glyf.push(flag);
+            }
+            let mut prev = 0;
+            for point in &points { glyf.extend_from_slice(&((point.0 - prev) as i16).to_be_bytes()); prev = point.0; }
+            prev = 0;
+            for point in &points { glyf.extend_from_slice(&((point.1 - prev) as i16).to_be_bytes()); prev = point.1; }
+        }
+        while glyf.len() % 4 != 0 { glyf.push(0) }
+    }
+    offsets.push(glyf.len());
+
+    let (loca, widened) = build_loca(&offsets, index_format);
+    Some((glyf, loca, x_mins, widened))
+}
+
+/// Writes `loca` in the font's index format, or in the long one when the glyphs no longer fit short offsets.
+/// Glyphs are rebuilt with uncompressed coordinates, so `glyf` can outgrow what the short format addresses.
+fn build_loca(offsets: &[usize], index_format: u16) -> (Vec<u8>, bool) {
+    let fits_short = offsets.last().map_or(true, |last| last / 2 <= u16::MAX as usize);
+    match index_format {
+        0 if fits_short => (offsets.iter().flat_map(|offset| ((offset / 2) as u16).to_be_bytes()).collect(), false),
+        format => (offsets.iter().flat_map(|offset| (*offset as u32).to_be_bytes()).collect(), format == 0),
+    }
+}
+
+fn reconstruct_hmtx(data: &[u8], num_h_metrics: usize, x_mins: &[i16]) -> Option<Vec<u8>> {
+    let flags           = *data.first()?;
+    let num_glyphs      = x_mins.len();
+    if num_h_metrics > num_glyphs { return None }
+    let mut pos         = 1;
+    let mut advances    = Vec::with_capacity(num_h_metrics);
+    for _ in 0..num_h_metrics { advances.push(read_u16(data, pos)?); pos += 2; }
+    let mut lsbs = Vec::with_capacity(num_glyphs);
+    for i in 0..num_h_metrics {
+        if flags & 0x01 != 0 { lsbs.push(*x_mins.get(i)?) } else { lsbs.push(read_u16(data, pos)? as i16); pos += 2; }
+    }
+    for i in num_h_metrics..num_glyphs {
+        if flags & 0x02 != 0 { lsbs.push(*x_mins.get(i)?) } else { lsbs.push(read_u16(data, pos)? as i16); pos += 2; }
+    }
+
+    let mut hmtx = Vec::with_capacity(num_h_metrics * 4 + (num_glyphs - num_h_metrics) * 2);
+    for (i, lsb) in lsbs.iter().enumerate() {
+        if i < num_h_metrics { hmtx.extend_from_slice(&advances[i].to_be_bytes()); }
+        hmtx.extend_from_slice(&lsb.to_be_bytes());
+    }
+    Some(hmtx)
+}
+
+fn decode_triplet(flag: u8, data: &[u8]) -> Option<(i32, i32, usize)> {
+    fn with_sign(flag: u8, value: i32) -> i32 { if flag & 1 != 0 { value } else { -value } }
+    let b = |i: usize| data.get(i).map(|b| *b as i32);
+    let flag_value = flag as i32;
+    Some(match flag {
+        0..=9       => (0, with_sign(flag, ((flag_value & 14) << 7) + b(0)?), 1),
+        10..=19     => (with_sign(flag, (((flag_value - 10) & 14) << 7) + b(0)?), 0, 1),
+        20..=83     => {
+            let b0 = flag_value - 20;
+            let b1 = b(0)?;
+            (with_sign(flag, 1 + (b0 & 0x30) + (b1 >> 4)), with_sign(flag >> 1, 1 + ((b0 & 0x0c) << 2) + (b1 & 0x0f)), 1)
+        }
+        84..=119    => {
+            let b0 = flag_value - 84;
+            (with_sign(flag, 1 + ((b0 / 12) << 8) + b(0)?), with_sign(flag >> 1, 1 + (((b0 % 12) >> 2) << 8) + b(1)?), 2)
+        }
+        120..=123   => {
+            let b1 = b(1)?;
+            (with_sign(flag, (b(0)? << 4) + (b1 >> 4)), with_sign(flag >> 1, ((b1 & 0x0f) << 8) + b(2)?), 3)
+        }
+        _           => (with_sign(flag, (b(0)? << 8) + b(1)?), with_sign(flag >> 1, (b(2)? << 8) + b(3)?), 4),
+    })
+}
+
+fn build_sfnt(flavor: u32, mut tables: Vec<(u32, Vec<u8>)>) -> Vec<u8> {
+    tables.sort_by_key(|(tag, _)| *tag);
+    let num_tables      = tables.len() as u16;
+    let entry_selector  = if num_tables == 0 { 0 } else { 15 - num_tables.leading_zeros() as u16 };
+    let search_range    = (1u16 << entry_selector) * 16;
+    let range_shift     = num_tables * 16 - search_range;
+
+    let mut font = Vec::with_capacity(12 + tables.len() * 16 + tables.iter().map(|(_, t)| t.len() + 3).sum::<usize>());
+    font.extend_from_slice(&flavor.to_be_bytes());
+    for value in [num_tables, search_range, entry_selector, range_shift] { font.extend_from_slice(&value.to_be_bytes()); }
+
+    let mut offset = 12 + tables.len() * 16;
+    for (tag, table) in &tables {
+        font.extend_from_slice(&tag.to_be_bytes());
+        font.extend_from_slice(&checksum(table).to_be_bytes());
+        font.extend_from_slice(&(offset as u32).to_be_bytes());
+        font.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        offset += (table.len() + 3) & !3;
+    }
+    for (_, table) in &tables {
+        font.extend_from_slice(table);
+        while font.len() % 4 != 0 { font.push(0) }
+    }
+    font
+}
+
+fn checksum(table: &[u8]) -> u32 {
+    table.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Option<u16> {
+    data.get(pos..pos + 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..pos + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_base128(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let mut value: u32 = 0;
+    for i in 0..5 {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        if i == 0 && byte == 0x80 { return None }
+        if value & 0xFE00_0000 != 0 { return None }
+        value = (value << 7) | (byte & 0x7f) as u32;
+        if byte & 0x80 == 0 { return Some(value) }
+    }
+    None
+}
+
+fn read_255_u16(data: &[u8], pos: &mut usize) -> Option<u16> {
+    let code = *data.get(*pos)?;
+    *pos += 1;
+    match code {
+        253 => { *pos += 2; read_u16(data, *pos - 2) }
+        254 => { *pos += 1; Some(*data.get(*pos - 1)? as u16 + 506) }
+        255 => { *pos += 1; Some(*data.get(*pos - 1)? as u16 + 253) }
+        _   => Some(code as u16),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+    use super::*;
+
+    const TRUETYPE: u32 = 0x0001_0000;
+    const NAME: u32     = 0x6E61_6D65;
+
+    fn tables() -> Vec<(u32, Vec<u8>)> {
+        vec![
+            (HEAD, (0..54).collect()),
+            (NAME, b"a name table that repeats, repeats, repeats, repeats".to_vec()),
+        ]
+    }
+
+    fn woff(flavor: u32, tables: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut directory   = Vec::new();
+        let mut data        = Vec::new();
+        let mut offset      = 44 + tables.len() * 20;
+        for (tag, table) in tables {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+            encoder.write_all(table).unwrap();
+            let compressed  = encoder.finish().unwrap();
+            let stored      = if compressed.len() < table.len() { compressed } else { table.clone() };
+            for value in [*tag, offset as u32, stored.len() as u32, table.len() as u32, checksum(table)] {
+                directory.extend_from_slice(&value.to_be_bytes());
+            }
+            offset += stored.len();
+            data.extend(stored);
+        }
+        let mut font = Vec::new();
+        font.extend_from_slice(&WOFF_SIGNATURE.to_be_bytes());
+        font.extend_from_slice(&flavor.to_be_bytes());
+        font.extend_from_slice(&(offset as u32).to_be_bytes());
+        font.extend_from_slice(&(tables.len() as u16).to_be_bytes());
+        font.resize(44, 0);
+        font.extend(directory);
+        font.extend(data);
+        font
+    }
+
+    // A brotli stream holding `data` in a single uncompressed meta-block
+    fn brotli_stored(data: &[u8]) -> Vec<u8> {
+        assert!(!data.is_empty() && data.len() <= 1 << 16);
+        // WBITS 16, not last, four length nibbles, MLEN - 1, uncompressed, padded to the byte
+        let header = ((data.len() as u32 - 1) << 4) | 1 << 20;
+        let mut stream = header.to_le_bytes()[..3].to_vec();
+        stream.extend_from_slice(data);
+        // Last and empty
+        stream.push(0b11);
+        stream
+    }
+
+    fn base128(mut value: u32) -> Vec<u8> {
+        let mut bytes = vec![(value & 0x7f) as u8];
+        value >>= 7;
+        while value > 0 {
+            bytes.insert(0, (value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes
+    }
+
+    // Table entries are (known tag index, transform version, original length, table data)
+    fn woff2(flavor: u32, tables: &[(u8, u8, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut directory   = Vec::new();
+        let mut stream      = Vec::new();
+        for (index, version, orig_length, table) in tables {
+            directory.push(index | version << 6);
+            directory.extend(base128(*orig_length));
+            if table.len() as u32 != *orig_length { directory.extend(base128(table.len() as u32)) }
+            stream.extend_from_slice(table);
+        }
+        let compressed = brotli_stored(&stream);
+        let mut font = Vec::new();
+        font.extend_from_slice(&WOFF2_SIGNATURE.to_be_bytes());
+        font.extend_from_slice(&flavor.to_be_bytes());
+        font.extend_from_slice(&0u32.to_be_bytes());
+        font.extend_from_slice(&(tables.len() as u16).to_be_bytes());
+        font.resize(20, 0);
+        font.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+        font.resize(48, 0);
+        font.extend(directory);
+        font.extend(compressed);
+        font
+    }
+
+    // A transformed glyf stream with a single empty glyph and short loca
+    fn empty_glyf() -> Vec<u8> {
+        let mut glyf = Vec::new();
+        for value in [0u16, 0, 1, 0] { glyf.extend_from_slice(&value.to_be_bytes()); }
+        for size in [2u32, 0, 0, 0, 0, 4, 0] { glyf.extend_from_slice(&size.to_be_bytes()); }
+        glyf.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        glyf
+    }
+
+    #[test]
+    fn sfnt_is_returned_unchanged() {
+        let font = build_sfnt(TRUETYPE, tables());
+        assert_eq!(decode_font(font.clone()), Some(font));
+    }
+
+    #[test]
+    fn woff_tables_are_inflated() {
+        let font = woff(TRUETYPE, &tables());
+        assert_eq!(decode_font(font), Some(build_sfnt(TRUETYPE, tables())));
+    }
+
+    #[test]
+    fn woff_with_truncated_table_fails() {
+        let mut font = woff(TRUETYPE, &tables());
+        font.truncate(font.len() - 4);
+        assert_eq!(decode_font(font), None);
+    }
+
+    #[test]
+    fn woff2_untransformed_tables_are_copied() {
+        let [(_, head), (_, name)] = <[_; 2]>::try_from(tables()).unwrap();
+        let font = woff2(TRUETYPE, &[(1, 0, head.len() as u32, head.clone()), (5, 0, name.len() as u32, name.clone())]);
+        assert_eq!(decode_font(font), Some(build_sfnt(TRUETYPE, vec![(HEAD, head), (NAME, name)])));
+    }
+
+    #[test]
+    fn woff2_glyf_is_rebuilt() {
+        let font = woff2(TRUETYPE, &[(10, 0, 0, empty_glyf()), (11, 0, 4, Vec::new())]);
+        assert_eq!(decode_font(font), Some(build_sfnt(TRUETYPE, vec![(GLYF, Vec::new()), (LOCA, vec![0, 0, 0, 0])])));
+    }
+
+    #[test]
+    fn woff2_loca_length_mismatch_fails() {
+        let font = woff2(TRUETYPE, &[(10, 0, 0, empty_glyf()), (11, 0, 8, Vec::new())]);
+        assert_eq!(decode_font(font), None);
+    }
+
+    #[test]
+    fn woff2_collections_are_rejected() {
+        let (_, name) = tables().remove(1);
+        assert_eq!(decode_font(woff2(TTC_FLAVOR, &[(5, 0, name.len() as u32, name)])), None);
+    }
+
+    #[test]
+    fn loca_widens_when_short_offsets_overflow() {
+        assert_eq!(build_loca(&[0, 4, 8], 0), (vec![0, 0, 0, 2, 0, 4], false));
+        assert_eq!(build_loca(&[0, 0x2_0000], 0), (vec![0, 0, 0, 0, 0, 2, 0, 0], true));
+        assert_eq!(build_loca(&[0, 4], 1), (vec![0, 0, 0, 0, 0, 0, 0, 4], false));
+    }
+
+    #[test]
+    fn hmtx_takes_side_bearings_from_glyphs() {
+        // Both side bearing arrays left out, one advance for two glyphs
+        let hmtx = reconstruct_hmtx(&[0x03, 0x01, 0xF4], 1, &[5, -2]);
+        assert_eq!(hmtx, Some(vec![0x01, 0xF4, 0x00, 0x05, 0xFF, 0xFE]));
+    }
+
+    #[test]
+    fn hmtx_with_more_metrics_than_glyphs_fails() {
+        assert_eq!(reconstruct_hmtx(&[0x03, 0, 1, 0, 2, 0, 3], 3, &[5, -2]), None);
+        assert_eq!(reconstruct_hmtx(&[0x00], 0, &[5]), None);
+    }
+}
@@ -1,15 +1,19 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::ops::Deref;
 use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
 use std::sync::{Arc, RwLock};
 use std::time::Instant;
 use std::vec;
 use std::mem;
 
-use floem::kurbo::{Point, Size};
-use floem::peniko::Image;
-use floem_renderer::text::Attrs;
-use lightningcss::properties::text::TextAlign;
+use floem::kurbo::{Point, Rect, Size};
+use floem::peniko::{Color, Image};
+use floem_renderer::text::{Attrs, FamilyOwned};
+use floem_renderer::usvg;
+use lightningcss::properties::font::FontVariantCaps;
+use lightningcss::properties::text::{TextAlign, TextDecorationLine, TextTransformCase, WhiteSpace};
 use lightningcss::stylesheet::StyleSheet;
 use regex::Regex;
 use roxmltree::{Document, Node, NodeId};
@@ -17,14 +21,27 @@ use scraper::{Html, ElementRef, Selector};
 use rustc_data_structures::fx::FxHashMap;
 use sha2::Digest;
 
-use crate::glyph_interner::GlyphCache;
-use crate::layout::layout_elem_lines;
-use crate::styling::style::{resolve_style, resolve_style_cached, resolve_style_scraper, StyleCache, Margins};
+use crate::glyph_interner::{glyph_extents, GlyphCache};
+use crate::IO::svg::{load_inline_svg, XLINK_NAMESPACE};
+use crate::layout::{clearance, hang_marker, layout_elem_lines, line_bounds, next_float_bottom, split_at_floats, BASELINE_RATIO};
+use crate::styling::content::{ContentItem, Counters, PseudoElement};
+use crate::styling::highlight::SyntaxTheme;
+use crate::styling::style::{resolve_pseudo_style_cached, resolve_style, resolve_style_cached, resolve_style_scraper, StyleCache, Margins};
+use crate::table_parser::MEASURE_WIDTH;
 
 // Note: The complex wrapper layer was removed for simplicity.
 // HTML parsing is handled directly via parse_root_html() and scraper functions.
 // XML parsing continues to use roxmltree via the existing parse_root() function.
 
+// Synthesized small caps are uppercase glyphs drawn at this fraction of the font size
+const SMALL_CAPS_SCALE: f32 = 0.7;
+// Columns between tab stops in preserved whitespace
+const TAB_SIZE: usize = 8;
+// Capital height as a fraction of the font size, what an initial letter is sized by
+const CAP_HEIGHT: f64 = 0.7;
+
+pub const DETAILS_LINK: &str = "details:";
+
 static BLOCK_ELEMENTS: [&str; 37] = [
     "html", "body", "article", "section", "nav", "aside",
     "h1", "h2", "h3", "h4", "h5", "h6", "hgroup", "header",
@@ -35,6 +52,34 @@ static BLOCK_ELEMENTS: [&str; 37] = [
 ];
 
 impl BlockElem {
+    /// The children painted, only the summary of a closed details element
+    pub fn visible_children(&self) -> &[Elem] {
+        match &self.details {
+            Some(details) if !details.open => &self.children[..details.summary_count.min(self.children.len())],
+            _ => &self.children,
+        }
+    }
+
+    // A break after a child is taken at the start of the next one, and one after the last child after this block,
+    // so the renderer only has to look at where blocks start
+    fn propagate_breaks(&mut self) {
+        for position in 1..self.children.len() {
+            let (before, after) = self.children.split_at_mut(position);
+            let (ElemType::Block(previous), ElemType::Block(next)) = (&mut before[position - 1].elem_type, &mut after[0].elem_type) else { continue };
+            if previous.fragmentation.break_after.is_forced() && !next.fragmentation.break_before.is_forced() {
+                next.fragmentation.break_before = previous.fragmentation.break_after;
+            }
+            // Avoiding a break before a block keeps it with the one ahead of it
+            if next.fragmentation.break_before == Break::Avoid && previous.fragmentation.break_after == Break::Auto {
+                previous.fragmentation.break_after = Break::Avoid;
+            }
+        }
+        let Some(Elem { elem_type: ElemType::Block(last), .. }) = self.children.last() else { return };
+        if last.fragmentation.break_after.is_forced() && !self.fragmentation.break_after.is_forced() {
+            self.fragmentation.break_after = last.fragmentation.break_after;
+        }
+    }
+
     pub fn add_child (&mut self, elem: Elem) {
         match &elem.elem_type {
             ElemType::Block(block) => { self.total_child_count += block.total_child_count; }
@@ -58,22 +103,117 @@ impl Elem {
             ElemType::Lines(_) => { self }
         }
     }
-    pub fn get_last_index (&self) -> Vec<usize> {
-        let mut indexes = Vec::new();
-        match &self.elem_type {
-            ElemType::Block(block) => {
-                if block.children.len() == 0 {return Vec::new()}
-                let index = block.children.len() - 1;
-                indexes.push(index);
-                let elem = &block.children[index];
-                indexes.append(&mut elem.get_last_index());
-                indexes
+    /// Index of the deepest elem starting at or above `y`, where a scrolled view begins
+    pub fn index_at(&self, y: f64) -> Vec<usize> {
+        let ElemType::Block(block) = &self.elem_type else { return Vec::new() };
+        // The cells of a row all start at its top, the row is where the view begins
+        if block.row { return Vec::new() }
+        let children = block.visible_children();
+        let Some(position) = children.iter().rposition(|child| child.point.y <= y) else { return Vec::new() };
+        let mut index = vec![position];
+        index.extend(children[position].index_at(y));
+        index
+    }
+    fn get_elem_mut(&mut self, path: &[usize]) -> Option<&mut Elem> {
+        let Some((&position, rest)) = path.split_first() else { return Some(self) };
+        match &mut self.elem_type {
+            ElemType::Block(block) => block.children.get_mut(position)?.get_elem_mut(rest),
+            ElemType::Lines(_) => None,
+        }
+    }
+
+    /// Opens or closes the details element at `path`, moving everything after it by the height of its content.
+    /// No float reaches across the details' edges, so the moved lines wrap as they would if laid out again
+    pub fn toggle_details(&mut self, path: &[usize]) {
+        let Some(delta) = self.get_elem_mut(path).and_then(|details| details.flip_details()) else { return };
+        self.shift_after(path, delta);
+    }
+
+    fn flip_details(&mut self) -> Option<f64> {
+        let ElemType::Block(block) = &mut self.elem_type else { return None };
+        let details     = block.details.as_mut()?;
+        details.open    = !details.open;
+        let [closed, open] = details.markers;
+        let (from, to, delta) = match details.open {
+            true    => (closed, open, details.content_height),
+            false   => (open, closed, -details.content_height),
+        };
+        if let Some(summary) = details.summary_count.checked_sub(1).and_then(|last| block.children.get_mut(last)) {
+            summary.replace_glyph(from, to);
+        }
+        self.size.height += delta;
+        Some(delta)
+    }
+
+    // Moves the elements after `path` in document order and resizes the blocks around it
+    fn shift_after(&mut self, path: &[usize], delta: f64) {
+        let ElemType::Block(block) = &mut self.elem_type else { return };
+        let Some((&position, rest)) = path.split_first() else { return };
+        for child in block.children.iter_mut().skip(position + 1) {
+            child.shift(delta);
+        }
+        if rest.is_empty() { return }
+        if let Some(child) = block.children.get_mut(position) {
+            child.size.height += delta;
+            child.shift_after(rest, delta);
+        }
+    }
+
+    fn shift(&mut self, delta: f64) {
+        self.point.y += delta;
+        if let ElemType::Block(block) = &mut self.elem_type {
+            block.children.iter_mut().for_each(|child| child.shift(delta));
+        }
+    }
+
+    /// Moves a laid out elem and everything in it, decoration boxes included as they are placed in painted x
+    pub fn translate(&mut self, dx: f64, dy: f64) {
+        self.point.x += dx;
+        self.point.y += dy;
+        if let Some(decoration) = &mut self.decoration {
+            decoration.x += dx;
+            decoration.cells.iter_mut().for_each(|cell| cell.x += dx);
+        }
+        if let ElemType::Block(block) = &mut self.elem_type {
+            block.children.iter_mut().for_each(|child| child.translate(dx, dy));
+        }
+    }
+
+    // Swaps the first glyph drawn with `from`, the disclosure marker of a summary
+    fn replace_glyph(&mut self, from: u16, to: u16) -> bool {
+        match &mut self.elem_type {
+            ElemType::Block(block) => block.children.iter_mut().any(|child| child.replace_glyph(from, to)),
+            ElemType::Lines(lines) => {
+                let glyphs = lines.elem_lines.iter_mut().flat_map(|line| line.inline_elems.iter_mut())
+                    .flat_map(|inline_elem| match &mut inline_elem.inline_content {
+                        InlineContent::Text(glyphs) | InlineContent::Link((glyphs, _)) => glyphs.iter_mut(),
+                        _ => [].iter_mut(),
+                    });
+                for glyph in glyphs {
+                    if glyph.char == from {
+                        glyph.char = to;
+                        return true
+                    }
+                }
+                false
             }
-            ElemType::Lines(_) => {
-                Vec::new()
+        }
+    }
+
+    // Makes all text in the element a link to `href`
+    fn link(&mut self, href: &str) {
+        match &mut self.elem_type {
+            ElemType::Block(block) => block.children.iter_mut().for_each(|child| child.link(href)),
+            ElemType::Lines(lines) => {
+                for inline_elem in lines.elem_lines.iter_mut().flat_map(|line| line.inline_elems.iter_mut()) {
+                    if let InlineContent::Text(glyphs) = &mut inline_elem.inline_content {
+                        inline_elem.inline_content = InlineContent::Link((std::mem::take(glyphs), href.to_string()));
+                    }
+                }
             }
         }
     }
+
     pub fn get_y(&self, elem_index: usize) -> f64 {
         let mut y = self.point.y;
         let mut current_elem_index = 0;
@@ -142,43 +282,183 @@ pub fn size_of_vec<T>(vec: &Vec<T>) -> usize {
     std::mem::size_of::<Vec<T>>() + vec.capacity() * std::mem::size_of::<T>()
 }
 pub type ImagePromise = Arc<RwLock<Option<(Image, Vec<u8>)>>>;
-pub struct HTMLPage { pub root: Elem, pub locations: FxHashMap<String, Vec<usize>>}
-pub struct Elem             { pub size: Size, pub point: Point, pub elem_type: ElemType }
+pub struct HTMLPage { pub root: Elem, pub locations: FxHashMap<String, Vec<usize>>, pub tables: Vec<Elem>, pub fixed: Option<FixedLayout> }
+pub struct Elem             { pub size: Size, pub point: Point, pub elem_type: ElemType, pub decoration: Option<Box<BlockDecoration>> }
 pub enum ElemType           { Block(BlockElem), Lines(ElemLines) }
-pub struct BlockElem        { pub children: Vec<Elem>, pub total_child_count: usize, }
+/// A `row` block is a table row, its children are the row's cells side by side rather than one below the other
+pub struct BlockElem        { pub children: Vec<Elem>, pub total_child_count: usize, pub details: Option<Details>, pub fragmentation: Fragmentation, pub row: bool }
+/// State of a details element, its children after the summary are hidden while it is closed
+pub struct Details          { pub open: bool, pub summary_count: usize, pub content_height: f64, pub markers: [u16; 2] }
 pub struct ElemLines        { pub height: f64, pub elem_lines: Vec<ElemLine> }
 #[derive(Clone)]
 pub struct ElemLine         { pub height: f64, pub inline_elems: Vec<InlineElem> }
 #[derive(Clone)]
-pub struct InlineElem       { pub x: f64, pub inline_content: InlineContent }
+pub struct InlineElem       { pub x: f64, pub inline_content: InlineContent, pub style: Option<Rc<InlineStyle>> }
 #[derive(Clone)]
-pub struct InlineItem       { pub size: Size, pub inline_content: InlineContent }
+pub struct InlineItem       { pub size: Size, pub inline_content: InlineContent, pub style: Option<Rc<InlineStyle>> }
 #[derive(Clone)]
-pub enum InlineContent      { Text(Vec<CharGlyph>), Image(ImageElem), Link((Vec<CharGlyph>, String)) }
+/// `Float` marks where a float's anchor stands in the text, by the float's index in the pending floats, and is never laid out itself
+pub enum InlineContent      { Text(Vec<CharGlyph>), Image(ImageElem), Link((Vec<CharGlyph>, String)), Math(Rc<MathBox>), Ruby(Rc<Ruby>), Break, Float(usize) }
 #[derive(Clone)]
 pub struct CharGlyph        { pub char: u16, pub x: f32}
 #[derive(Clone)]
-pub struct ImageElem { pub width: u16, pub height: u16, pub image_promise: ImagePromise}
+pub struct ImageElem { pub width: u16, pub height: u16, pub image_promise: ImagePromise, pub svg: Option<Arc<SvgImage>> }
+/// A parsed SVG, drawn as vectors at whatever size it is laid out to
+pub struct SvgImage { pub tree: usvg::Tree, pub hash: Vec<u8> }
+/// A laid out formula, its glyphs and rules (fraction bars, radical overlines) placed from the top left of its box
+pub struct MathBox { pub glyphs: Vec<MathGlyph>, pub rules: Vec<Rect>, pub color: Option<Color>, pub width: f64, pub height: f64 }
+/// Base text with its annotation centred above it, `raise` lifts the annotation's baseline off the base's
+pub struct Ruby { pub base: Vec<CharGlyph>, pub annotation: Vec<CharGlyph>, pub base_x: f32, pub annotation_x: f32, pub raise: f32, pub above: f32, pub width: f64 }
+/// A glyph of a formula, drawn from its layout origin
+#[derive(Clone)]
+pub struct MathGlyph { pub char: u16, pub x: f32, pub y: f32 }
+
+/// Text decoration, background and vertical alignment shared by every inline item of a styled span
+#[derive(Clone, Default, PartialEq)]
+pub struct InlineStyle {
+    pub decoration: TextDecorationLine,
+    pub decoration_color: Option<Color>,
+    pub background: Option<Color>,
+    pub vertical_align: VerticalAlign,
+}
+
+/// `Shift` raises the item above the line's baseline by that many pixels, negative values lower it
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum VerticalAlign {
+    #[default]
+    Baseline,
+    Shift(f32),
+    Middle,
+    Top,
+    Bottom,
+}
+
+/// Background and border painted behind a block, `x` and `width` span the border box
+#[derive(Clone, Default)]
+pub struct BlockDecoration {
+    pub background: Option<Color>,
+    pub borders: [BorderSide; 4],
+    pub x: f64,
+    pub width: f64,
+    pub margin_top: f64,
+    pub margin_bottom: f64,
+    /// Corner radii clockwise from the top left
+    pub radii: [f64; 4],
+    /// Table cell boxes painted over a row's own box, positioned the same way
+    pub cells: Vec<BlockDecoration>,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct BorderSide { pub width: f64, pub color: Color }
+
+/// Used `width`, `height`, `max-width` and `max-height` in px, None where the property is `auto` or `none`
+#[derive(Clone, Copy, Default)]
+pub struct BoxSize {
+    pub width: Option<f64>,
+    pub height: Option<f64>,
+    pub max_width: Option<f64>,
+    pub max_height: Option<f64>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FloatSide { Left, Right }
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Clear { #[default] None, Left, Right, Both }
+
+/// `line-height`, a factor of the font size unless given as a length
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum LineHeight { #[default] Normal, Factor(f64), Length(f64) }
+
+/// `initial-letter`, a letter `size` lines tall whose baseline sits on line `sink`
+#[derive(Clone, Copy, PartialEq)]
+pub struct InitialLetter { pub size: f64, pub sink: usize }
+
+/// `break-before`, `break-after` and `break-inside`, where `Page` also covers `always`, `left` and `right`
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Break { #[default] Auto, Avoid, Column, Page }
+
+impl Break {
+    pub fn is_forced(self) -> bool { matches!(self, Break::Column | Break::Page) }
+}
+
+/// How a block may be split across columns and pages, `orphans` and `widows` are inherited by its descendants
+#[derive(Clone, Copy, PartialEq)]
+pub struct Fragmentation { pub break_before: Break, pub break_after: Break, pub break_inside: Break, pub orphans: usize, pub widows: usize }
+
+impl Default for Fragmentation {
+    fn default() -> Self {
+        Fragmentation { break_before: Break::Auto, break_after: Break::Auto, break_inside: Break::Auto, orphans: 2, widows: 2 }
+    }
+}
+
+/// `position`, a positioned box is the containing block of the absolutely positioned boxes inside it
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Position { #[default] Static, Relative, Absolute }
+
+/// `top`, `right`, `bottom` and `left` of a positioned box in px, None where they are `auto`
+#[derive(Clone, Copy, Default)]
+pub struct Insets { pub top: Option<f64>, pub right: Option<f64>, pub bottom: Option<f64>, pub left: Option<f64> }
+
+/// Box absolutely positioned boxes are placed against, its left edge in painted x and its height when it is known before its content.
+/// Boxes are only taken out of the flow in a `fixed` one, a pre-paginated section drawn as a whole.
+#[derive(Clone, Copy)]
+pub struct ContainingBlock { pub left: f64, pub top: f64, pub width: f64, pub height: Option<f64>, pub fixed: bool }
+
+/// A pre-paginated section, laid out in the page box its viewport declares and scaled as a whole to fit the view
+pub struct FixedLayout { pub viewport: Size, pub spread: Spread }
+
+/// Where a fixed-layout page goes in a spread, from `rendition:spread` and the `page-spread-*` spine properties
+#[derive(Clone, Copy, Default)]
+pub struct Spread { pub side: SpreadSide, pub mode: SpreadMode }
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum SpreadSide { #[default] Auto, Left, Right }
+
+/// `rendition:spread`, the view orientations two pages are shown side by side in
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum SpreadMode { None, Landscape, Both, #[default] Auto }
+
+impl SpreadMode {
+    pub fn spreads(self, landscape: bool) -> bool {
+        match self {
+            SpreadMode::None                        => false,
+            SpreadMode::Landscape | SpreadMode::Auto => landscape,
+            SpreadMode::Both                        => true,
+        }
+    }
+}
+
+/// Area taken by a placed float, in the same x as painted lines. Lines beside it are narrowed until `bottom`.
+#[derive(Clone, Copy)]
+pub struct Float { pub side: FloatSide, pub left: f64, pub right: f64, pub top: f64, pub bottom: f64 }
 
 #[derive(Clone, Copy)]
 pub struct ListContext {
     pub list_type: ListType,
-    pub item_number: usize,
+    pub item_number: isize,
+    pub reversed: bool,
     pub depth: usize,
-    pub indent_width: f64,
+    pub marker_inside: bool,
 }
 
-#[derive(Clone, Copy)]
+impl Default for ListContext {
+    fn default() -> Self {
+        ListContext { list_type: ListType::None, item_number: 0, reversed: false, depth: 0, marker_inside: false }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
 pub enum ListType {
     Unordered(BulletStyle),
     Ordered(NumberStyle),
     None,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum BulletStyle { Disc, Circle, Square }
 
-#[derive(Clone, Copy)]  
+#[derive(Clone, Copy, PartialEq)]
 pub enum NumberStyle { Decimal, LowerAlpha, UpperAlpha, LowerRoman, UpperRoman }
 pub struct ObjectPool<T> {
     items: Vec<T>,
@@ -209,11 +489,47 @@ pub struct BookElemFactory  {
     base_path: String,
     pub cache: GlyphCache,
     pub images: HashMap<String, ImageElem>,
+    /// Encoded bytes of the raster images, for the `<image>` elements of inline SVGs
+    raster_images: HashMap<String, Arc<Vec<u8>>>,
     pub locations: FxHashMap<String, Vec<usize>>,
     pub root_font_size: f32,
     pub style_time: u128,
     pub style_cache: StyleCache,
     pub inline_pool: ObjectPool<Vec<InlineItem>>,
+    /// Marker of the current list item, placed on the first line laid out inside it
+    list_marker: Option<InlineItem>,
+    pub counters: Counters,
+    /// Tables too wide for the column, laid out at their natural width for the table view
+    pub tables: Vec<Elem>,
+    pub floats: Vec<Float>,
+    /// Nearest positioned ancestor, or the page when there is none
+    containing_block: ContainingBlock,
+    /// Floated images met in inline content, taken when line layout reaches their anchor
+    pending_floats: Vec<Option<(InlineItem, FloatSide, Margins)>>,
+    /// Floats whose anchor line had no room left for them, placed once that line ends
+    pub deferred_floats: Vec<usize>,
+    /// Floats placed while laying out a paragraph, each with the number of its lines above it
+    placed_floats: Vec<(usize, Elem)>,
+    /// Set when line layout wraps a line for lack of room
+    pub wrapped: bool,
+    /// Set while a float is laid out only to find its width
+    measuring_float: bool,
+    /// `::first-letter` and `::first-line` of the innermost block whose first line is not laid out yet
+    first_line_style: Option<FirstLineStyle>,
+    /// `text-indent` of the same block, taken by the first line laid out
+    pub text_indent: Option<f64>,
+    /// Closed and open disclosure markers of the last summary, for its details to swap between
+    summary_markers: Option<[u16; 2]>,
+    /// The text so far ends in collapsible whitespace or nothing precedes it on the line, so leading whitespace is dropped
+    pub after_space: bool,
+    /// Token colors for highlighted code blocks, matched to the reader's text color
+    pub syntax_theme: SyntaxTheme,
+}
+
+struct FirstLineStyle {
+    letter: Option<(f32, Margins, ParseState)>,
+    line: Option<(f32, ParseState)>,
+    block: ParseState,
 }
 #[derive(Clone)]
 pub struct ParseState {
@@ -223,24 +539,62 @@ pub struct ParseState {
     pub text_align: TextAlign,
     pub text_style: floem_renderer::text::Style,
     pub root_font_size: f32,
+    pub font_family: Option<Rc<[FamilyOwned]>>,
+    pub font_variant: FontVariantCaps,
+    pub text_transform: TextTransformCase,
+    pub color: Option<Color>,
+    pub inline_style: Option<Rc<InlineStyle>>,
+    pub block_decoration: Option<BlockDecoration>,
+    pub content: Option<Rc<[ContentItem]>>,
+    pub counter_reset: Option<Rc<[(String, isize)]>>,
+    pub counter_increment: Option<Rc<[(String, isize)]>>,
+    pub border_collapse: bool,
+    pub border_spacing: f64,
+    pub box_size: BoxSize,
+    pub float: Option<FloatSide>,
+    pub clear: Clear,
+    pub initial_letter: Option<InitialLetter>,
+    pub white_space: WhiteSpace,
+    pub line_height: LineHeight,
+    pub letter_spacing: f64,
+    pub word_spacing: f64,
+    pub text_indent: f64,
     pub list_context: ListContext,
-    pub prefix: &'static str,
+    pub fragmentation: Fragmentation,
+    pub position: Position,
+    pub insets: Insets,
     pub ancestors: Vec<NodeId>,
 }
 
 impl BookElemFactory {
-    pub fn new(cache: GlyphCache, images: HashMap<String, ImageElem>, font: &Attrs) -> Self {
+    pub fn new(cache: GlyphCache, images: HashMap<String, ImageElem>, raster_images: HashMap<String, Arc<Vec<u8>>>, font: &Attrs, text_color: Color) -> Self {
         BookElemFactory { 
             curr_x: 0., 
             curr_y: 0., 
             cache, 
             images, 
+            raster_images,
             base_path: String::new(), 
             locations: FxHashMap::default(), 
             root_font_size: font.font_size, 
             style_time: 0, 
             style_cache: StyleCache::new(),
             inline_pool: ObjectPool::new(),
+            list_marker: None,
+            counters: Counters::default(),
+            tables: Vec::new(),
+            floats: Vec::new(),
+            containing_block: ContainingBlock { left: 0., top: 0., width: 600., height: None, fixed: false },
+            pending_floats: Vec::new(),
+            deferred_floats: Vec::new(),
+            placed_floats: Vec::new(),
+            wrapped: false,
+            measuring_float: false,
+            first_line_style: None,
+            text_indent: None,
+            summary_markers: None,
+            after_space: true,
+            syntax_theme: SyntaxTheme::for_text(text_color),
         }
     }
 
@@ -262,82 +616,212 @@ impl BookElemFactory {
                 text_align: TextAlign::Left,
                 root_font_size: font.font_size,
                 text_style: floem_renderer::text::Style::Normal,
-                prefix: "",
-                list_context: ListContext {
-                    list_type: ListType::None,
-                    item_number: 0,
-                    depth: 0,
-                    indent_width: 0.0
-                },
+                font_family: None,
+                font_variant: FontVariantCaps::Normal,
+                text_transform: TextTransformCase::None,
+                color: None,
+                inline_style: None,
+                block_decoration: None,
+                content: None,
+                counter_reset: None,
+                counter_increment: None,
+                border_collapse: false,
+                border_spacing: 2.,
+                box_size: BoxSize::default(),
+                float: None,
+                clear: Clear::None,
+                initial_letter: None,
+                white_space: WhiteSpace::Normal,
+                line_height: LineHeight::Normal,
+                letter_spacing: 0.,
+                word_spacing: 0.,
+                text_indent: 0.,
+                list_context: ListContext::default(),
+                fragmentation: Fragmentation::default(),
+                position: Position::Static,
+                insets: Insets::default(),
                 ancestors: Vec::new(),
             };
             
             // For now, create a minimal block until we fully migrate
             let block = self.parse_element_scraper(body_element, font, style_sheets, parse_state, vec![0]);
-            let block_type = BlockElem { children: vec![block], total_child_count: 1 };
-            let root = Elem { size: Size::default(), point: Point::default(), elem_type: ElemType::Block(block_type) };
-            return HTMLPage { root, locations: self.locations.clone() }
+            let block_type = BlockElem { children: vec![block], total_child_count: 1, details: None, fragmentation: Fragmentation::default(), row: false };
+            let root = Elem { size: Size::default(), point: Point::default(), elem_type: ElemType::Block(block_type), decoration: None };
+            return HTMLPage { root, locations: self.locations.clone(), tables: std::mem::take(&mut self.tables), fixed: None }
         }
         
         let elem_lines = ElemLines { height: 0., elem_lines: Vec::new() };
-        let root = Elem { size: Size::default(), point: Point::default(), elem_type: ElemType::Lines(elem_lines) };
-        HTMLPage { root, locations: FxHashMap::default() }
+        let root = Elem { size: Size::default(), point: Point::default(), elem_type: ElemType::Lines(elem_lines), decoration: None };
+        HTMLPage { root, locations: FxHashMap::default(), tables: Vec::new(), fixed: None }
     }
 
-    pub fn parse_root(&mut self, node: Node, font: Attrs, file_path: String, style_sheets: &Vec<StyleSheet>, document: &Document) -> HTMLPage {
+    /// Lays out a section, `fixed` holding the spread placement of a pre-paginated one, which keeps the width of its viewport
+    pub fn parse_root(&mut self, node: Node, font: Attrs, file_path: String, style_sheets: &Vec<StyleSheet>, document: &Document, fixed: Option<Spread>) -> HTMLPage {
         self.curr_x = 0.;
         self.curr_y = 0.;
         self.base_path = file_path;
+        self.counters = Counters::default();
+        self.tables.clear();
+        self.floats.clear();
+        let viewport    = fixed.and_then(|_| read_viewport(node));
+        let width       = viewport.map_or(600., |viewport| viewport.width);
+        self.containing_block = ContainingBlock { left: 0., top: 0., width, height: viewport.map(|viewport| viewport.height), fixed: fixed.is_some() };
 
 
         for child in node.children() {
             if child.tag_name().name().eq("body") {
                 let parse_state = ParseState {
                     x: 0.,
-                    width: 600.,
+                    width,
                     font_weight: 400,
                     text_align: TextAlign::Left,
                     root_font_size: font.font_size,
                     text_style: floem_renderer::text::Style::Normal,
-                    prefix: "",
-                    list_context: ListContext {
-                        list_type: ListType::None,
-                        item_number: 0,
-                        depth: 0,
-                        indent_width: 0.0
-                    },
+                    font_family: None,
+                    font_variant: FontVariantCaps::Normal,
+                    text_transform: TextTransformCase::None,
+                    color: None,
+                    inline_style: None,
+                    block_decoration: None,
+                    content: None,
+                    counter_reset: None,
+                    counter_increment: None,
+                    border_collapse: false,
+                    border_spacing: 2.,
+                    box_size: BoxSize::default(),
+                    float: None,
+                    clear: Clear::None,
+                    initial_letter: None,
+                    white_space: WhiteSpace::Normal,
+                    line_height: LineHeight::Normal,
+                    letter_spacing: 0.,
+                    word_spacing: 0.,
+                    text_indent: 0.,
+                    list_context: ListContext::default(),
+                    fragmentation: Fragmentation::default(),
+                    position: Position::Static,
+                    insets: Insets::default(),
                     ancestors: Vec::new(),
                 };
                 let block = self.parse(child, font, style_sheets, parse_state, vec![0], document);
-                let block_type = BlockElem { children: vec![block], total_child_count: 1 };
-                let root = Elem { size: Size::default(), point: Point::default(), elem_type: ElemType::Block(block_type) };
-                return HTMLPage { root, locations: self.locations.clone() }
+                let block_type = BlockElem { children: vec![block], total_child_count: 1, details: None, fragmentation: Fragmentation::default(), row: false };
+                let root = Elem { size: Size::default(), point: Point::default(), elem_type: ElemType::Block(block_type), decoration: None };
+                // Without a viewport the page is as tall as its content
+                let fixed = fixed.map(|spread| FixedLayout { viewport: viewport.unwrap_or(Size::new(width, self.curr_y.max(1.))), spread });
+                return HTMLPage { root, locations: self.locations.clone(), tables: std::mem::take(&mut self.tables), fixed }
             }
         }
         let elem_lines = ElemLines { height: 0., elem_lines: Vec::new() };
-        let root = Elem { size: Size::default(), point: Point::default(), elem_type: ElemType::Lines(elem_lines) };
-        return HTMLPage { root, locations: FxHashMap::default() }
+        let root = Elem { size: Size::default(), point: Point::default(), elem_type: ElemType::Lines(elem_lines), decoration: None };
+        return HTMLPage { root, locations: FxHashMap::default(), tables: Vec::new(), fixed: None }
     }
 
     pub fn parse(&mut self, node: Node, mut font: Attrs, style_sheets: &Vec<StyleSheet>, mut parse_state: ParseState, mut index: Vec<usize>, document: &Document) -> Elem {
-        let mut block_elem      = BlockElem { children: Vec::new(), total_child_count: 0 };
+        let mut block_elem      = BlockElem { children: Vec::new(), total_child_count: 0, details: None, fragmentation: Fragmentation::default(), row: false };
         let mut inline_items    = self.inline_pool.get();
         inline_items.clear();
-        let     init_point      = Point::new(self.curr_x, self.curr_y);
+        let mut init_point      = Point::new(self.curr_x, self.curr_y);
         let now = Instant::now();
 
         let (margins, mut parse_state) = resolve_style_cached(style_sheets, &node, &mut font, parse_state, document, &mut self.style_cache);
-        parse_state.ancestors.push(node.id());
+        let (before, after) = self.resolve_pseudo_elements(node, style_sheets, font, &parse_state, document);
+        // A block without text of its own before its first child block passes its first line styles down to it
+        self.after_space = true;
+        let inherited = self.first_line_style.take();
+        self.first_line_style = self.resolve_first_line_style(node, style_sheets, font, &parse_state, document).or(inherited);
+        self.text_indent = Some(parse_state.text_indent);
 
         self.style_time += (Instant::now() - now).as_nanos();
+        // Reflowable sections are paginated and scrolled in document order, which a box taken out of the flow would break
+        if parse_state.position == Position::Absolute && !self.containing_block.fixed { parse_state.position = Position::Static }
+        // Details also start below every float, so opening or closing them can move what follows without rewrapping it
+        let clear = match node.has_tag_name("details") {
+            true    => Clear::Both,
+            false   => parse_state.clear,
+        };
+        if let Some(bottom) = clearance(self, clear) {
+            self.curr_y     = self.curr_y.max(bottom);
+            init_point.y    = self.curr_y;
+        }
+        // A floated block is laid out in a column of its own beside the flow, with the floats around it set aside
+        let mut float_box   = None;
+        if let Some(side) = parse_state.float.filter(|_| !self.measuring_float && parse_state.position != Position::Absolute) {
+            let available   = parse_state.width - parse_state.x;
+            let (outer_width, laid_out) = match parse_state.box_size.width {
+                Some(width) => (width + margins.left + margins.right, None),
+                None        => self.measure_float(node, font, style_sheets, parse_state.clone(), index.clone(), document, available, margins.left, true),
+            };
+            let outer_width = outer_width.min(available);
+            let (top, left) = self.float_position(side, outer_width, &parse_state);
+            // Laid out at its full width while measuring, the box only moves to where it floats
+            if let Some(mut elem) = laid_out {
+                elem.translate(left, top - elem.point.y);
+                self.floats.push(Float { side, left, right: left + outer_width, top, bottom: top + elem.size.height });
+                self.inline_pool.put(inline_items);
+                return elem
+            }
+            self.curr_y         = top;
+            init_point.y        = top;
+            parse_state.x       = left / 2.;
+            parse_state.width   = left / 2. + outer_width + margins.left / 2.;
+            float_box           = Some((side, left, outer_width, std::mem::take(&mut self.floats)));
+        }
+        // An absolutely positioned block is placed against its containing block the same way, and takes no room in the flow
+        let outer_block     = self.containing_block;
+        let mut flow_y      = None;
+        if parse_state.position == Position::Absolute && !self.measuring_float {
+            let insets      = parse_state.insets;
+            let outer_width = match (parse_state.box_size.width, insets.left, insets.right) {
+                (Some(width), _, _)             => width + margins.left + margins.right,
+                (None, Some(left), Some(right)) => outer_block.width - left - right,
+                (None, _, _)                    => self.measure_float(node, font, style_sheets, parse_state.clone(), index.clone(), document, MEASURE_WIDTH, margins.left, false).0,
+            }.min(outer_block.width);
+            // Without insets the box stays where it would have been in the flow
+            let left = match (insets.left, insets.right) {
+                (Some(left), _)     => outer_block.left + left,
+                (None, Some(right)) => outer_block.left + outer_block.width - right - outer_width,
+                (None, None)        => 2. * parse_state.x,
+            };
+            flow_y              = Some((self.curr_y, std::mem::take(&mut self.floats)));
+            self.curr_y         = insets.top.map_or(self.curr_y, |top| outer_block.top + top);
+            init_point.y        = self.curr_y;
+            parse_state.x       = left / 2.;
+            parse_state.width   = left / 2. + outer_width + margins.left / 2.;
+        }
+        parse_state.ancestors.push(node.id());
         parse_state.width   -= margins.left + margins.right;
         parse_state.x       += margins.left / 2.;
         self.curr_x         = parse_state.x;
         self.curr_y         += margins.top;
+        if parse_state.position != Position::Static {
+            self.containing_block = ContainingBlock { left: 2. * parse_state.x, top: self.curr_y, width: parse_state.width - parse_state.x, height: parse_state.box_size.height, fixed: outer_block.fixed };
+        }
+        // Lines are drawn at their elem x plus their own absolute x, so content starts at twice parse_state.x
+        let decoration      = parse_state.block_decoration.take().map(|mut decoration| {
+            decoration.x        += 2. * parse_state.x;
+            decoration.width    += parse_state.width - parse_state.x;
+            Box::new(decoration)
+        });
+        let is_list_item    = node.has_tag_name("li");
+        if is_list_item {
+            self.list_marker = self.generate_list_marker(parse_state.list_context, font, parse_state.clone());
+        }
+        // The summary toggles its details through a link naming the details' index
+        let details_link    = node.has_tag_name("details")
+            .then(|| format!("{DETAILS_LINK}{}", index.iter().map(|position| position.to_string()).collect::<Vec<_>>().join(".")));
+        let mut summary_end = None;
+        block_elem.fragmentation = parse_state.fragmentation;
         index.push(0);
         if let Some(id) = node.attribute("id") {
             self.locations.insert(id.to_string(), index.clone());
         }
+        self.update_counters(&parse_state);
+        let counter_mark = self.counters.mark();
+        if node.has_tag_name("summary") && node.parent_element().is_some_and(|parent| parent.has_tag_name("details")) {
+            let open = node.parent_element().is_some_and(|parent| parent.has_attribute("open"));
+            inline_items.push(self.disclosure_marker(open, font, &parse_state));
+        }
+        inline_items.extend(self.generate_content(node, before));
 
         for child in node.children() {
             let tag_name = child.tag_name().name();
@@ -346,47 +830,294 @@ impl BookElemFactory {
                 self.flush_inline_items(&mut block_elem, font, &mut inline_items, &parse_state, &mut index);
 
                 block_elem.add_child(match tag_name {
-                    "ul" | "ol" => self.parse_list(child, font, style_sheets, parse_state.clone(), index.clone(), document),
+                    "ul" | "ol" | "menu" => self.parse_list(child, font, style_sheets, parse_state.clone(), index.clone(), document),
                     "li" => {
-                        let mut li_state = parse_state.clone();
-                        li_state.prefix = "-\t";
-                        self.parse(child, font, style_sheets, li_state, index.clone(), document)
-                        //self.parse_list_item(child, font, style_sheets, parse_state, index.clone())
+                        next_list_item(&mut parse_state.list_context, child.attribute("value"));
+                        self.parse(child, font, style_sheets, parse_state.clone(), index.clone(), document)
                     },
                     "pre"   => self.parse_pre(child, font, style_sheets, parse_state.clone(), index.clone(), document),
                     "table" => self.parse_table(child, font, style_sheets, parse_state.clone(), index.clone(), document),
+                    "summary" if summary_end.is_none() && details_link.is_some() => {
+                        let mut summary = self.parse(child, font, style_sheets, parse_state.clone(), index.clone(), document);
+                        summary.link(details_link.as_deref().unwrap_or_default());
+                        summary_end = Some((self.curr_y, *index.last().unwrap() + 1));
+                        summary
+                    }
                     _       => self.parse(child, font, style_sheets, parse_state.clone(), index.clone(), document)
                 });
                 *index.last_mut().unwrap() += 1;
             }
             else {
-                self.process_inline_element(&mut block_elem, child, style_sheets, font, &parse_state, &mut index, &mut inline_items, document);
+                self.process_inline_element(child, style_sheets, font, &parse_state, &mut index, &mut inline_items, document);
             }
         }
+        inline_items.extend(self.generate_content(node, after));
+        self.counters.release(counter_mark);
         self.flush_inline_items(&mut block_elem, font, &mut inline_items, &parse_state, &mut index);
         self.inline_pool.put(inline_items);
+        block_elem.propagate_breaks();
+        // A decorated block without content, such as a rule, gets an empty line so the renderer can place its box
+        if block_elem.children.is_empty() && decoration.is_some() {
+            let height = parse_state.box_size.height.unwrap_or_default();
+            let lines = ElemLines { height, elem_lines: vec![ElemLine { height, inline_elems: Vec::new() }] };
+            block_elem.add_child(Elem { size: Size::new(parse_state.width, height), point: Point::new(parse_state.x, self.curr_y), elem_type: ElemType::Lines(lines), decoration: None });
+            self.curr_y += height;
+        }
+        // Details are laid out open, a closed one then gives back the height of everything after its summary.
+        // They reach down past the floats started inside them, which nothing after them wraps around
+        if let Some((summary_y, summary_count)) = summary_end {
+            self.curr_y         = self.floats.iter().filter(|float| float.top >= init_point.y).map(|float| float.bottom).fold(self.curr_y, f64::max);
+            let open            = node.has_attribute("open");
+            let content_height  = self.curr_y - summary_y;
+            if !open { self.curr_y -= content_height }
+            let markers         = self.summary_markers.take().unwrap_or_default();
+            block_elem.details  = Some(Details { open, summary_count, content_height, markers });
+        }
         self.curr_y += margins.bottom;
+        // An item without any text drops its marker rather than passing it to the next item
+        if is_list_item { self.list_marker = None }
+        self.first_line_style = None;
+        self.text_indent = None;
+
+        // Floats beside the flow take no room in it, so the height is how far the block moved curr_y
+        let block_height = self.curr_y - init_point.y;
+        if let Some((side, left, width, outer_floats)) = float_box {
+            self.floats = outer_floats;
+            self.floats.push(Float { side, left, right: left + width, top: init_point.y, bottom: self.curr_y });
+            self.curr_y = init_point.y;
+        }
+        self.containing_block = outer_block;
+        let mut elem = Elem { size: Size::new(600., block_height), point: init_point, elem_type: ElemType::Block(block_elem), decoration };
+        if let Some((flow_y, outer_floats)) = flow_y {
+            self.floats = outer_floats;
+            self.curr_y = flow_y;
+            // Anchored by its bottom, the box moves up into place once its height is known
+            if let (None, Some(bottom), Some(height)) = (parse_state.insets.top, parse_state.insets.bottom, outer_block.height) {
+                elem.shift(outer_block.top + height - bottom - block_height - init_point.y);
+            }
+        }
+        // A relatively positioned box is drawn offset from where the flow put it, the flow itself is left as it was
+        if parse_state.position == Position::Relative {
+            let insets  = parse_state.insets;
+            let dx      = insets.left.or(insets.right.map(|right| -right)).unwrap_or_default();
+            let dy      = insets.top.or(insets.bottom.map(|bottom| -bottom)).unwrap_or_default();
+            if dx != 0. || dy != 0. { elem.translate(dx, dy) }
+        }
+        elem
+    }
 
-        let block_height = block_elem.children.iter().fold(0., |acc, elem| acc + elem.size.height);
-        Elem { size: Size::new(600., block_height + margins.top + margins.bottom), point: init_point, elem_type: ElemType::Block(block_elem) }
+    // Shrink to fit: laid out at the full `available` width first, the widest line is the width the box takes, margins included.
+    // When lines had to wrap the box fills that width anyway, and with `keep` the layout is returned as the final one
+    fn measure_float(&mut self, node: Node, font: Attrs, style_sheets: &Vec<StyleSheet>, mut parse_state: ParseState, index: Vec<usize>, document: &Document, available: f64, margin_left: f64, keep: bool) -> (f64, Option<Elem>) {
+        let (curr_x, curr_y, counters, tables) = (self.curr_x, self.curr_y, self.counters.clone(), self.tables.len());
+        let (floats, wrapped) = (std::mem::take(&mut self.floats), std::mem::take(&mut self.wrapped));
+        parse_state.x       = 0.;
+        parse_state.width   = available + margin_left / 2.;
+        self.measuring_float = true;
+        let elem            = self.parse(node, font, style_sheets, parse_state, index, document);
+        self.measuring_float = false;
+        let filled          = std::mem::replace(&mut self.wrapped, wrapped);
+        (self.curr_x, self.curr_y, self.floats) = (curr_x, curr_y, floats);
+        if keep && filled { return (available, Some(elem)) }
+        self.counters = counters;
+        self.tables.truncate(tables);
+        (self.content_widths(&elem).1, None)
+    }
+
+    // The first height from curr_y down where `width` fits between the floats, and the painted x of its left edge there
+    fn float_position(&self, side: FloatSide, width: f64, parse_state: &ParseState) -> (f64, f64) {
+        let mut top = self.curr_y;
+        let (mut start, mut end) = line_bounds(self, parse_state, top);
+        while end - start < width {
+            let Some(bottom) = next_float_bottom(self, top) else { break };
+            top = bottom;
+            (start, end) = line_bounds(self, parse_state, top);
+        }
+        match side {
+            FloatSide::Left     => (top, parse_state.x + start),
+            FloatSide::Right    => (top, parse_state.x + end - width),
+        }
     }
 
     fn flush_inline_items(&mut self, block_elem: &mut BlockElem, font: Attrs, inline_items: &mut Vec<InlineItem>, parse_state: &ParseState, index: &mut Vec<usize>) {
+        let mut first_line = None;
+        // Whitespace alone lays out no first line, the styles wait for the flush that has text
+        if self.has_text(inline_items) {
+            if let Some(mut first_line_style) = self.first_line_style.take() {
+                if let Some(letter) = first_line_style.letter.take() {
+                    self.split_first_letter(inline_items, font, letter, &first_line_style.block);
+                }
+                first_line = first_line_style.line.map(|line| (line, first_line_style.block));
+            }
+        }
         if !inline_items.is_empty() {
-            if parse_state.prefix != "" {
-                inline_items.insert(0, self.parse_text(parse_state.prefix, font, parse_state.clone(), None).first().unwrap().clone())
+            if let Some(((size, line_state), block_state)) = first_line {
+                self.style_first_line(inline_items, font, size, &line_state, &block_state, parse_state);
+            }
+            let mut outside_marker = None;
+            if let Some(marker) = self.list_marker.take() {
+                match parse_state.list_context.marker_inside {
+                    true    => inline_items.insert(0, marker),
+                    false   => outside_marker = Some(marker),
+                }
+            }
+            let mut elem = layout_elem_lines(self, std::mem::take(inline_items), parse_state);
+            if let Some(marker) = outside_marker {
+                hang_marker(&mut elem, marker, parse_state.x);
+            }
+            // The floats stand between the lines above and below their anchor, keeping the children in height order
+            self.pending_floats.clear();
+            for part in split_at_floats(elem, std::mem::take(&mut self.placed_floats)) {
+                block_elem.add_child(part);
+                *index.last_mut().unwrap() += 1;
             }
-            block_elem.add_child(layout_elem_lines(self, std::mem::take(inline_items), parse_state));
-            *index.last_mut().unwrap() += 1;
         }
+        self.after_space = true;
+    }
+
+    fn has_text(&self, inline_items: &[InlineItem]) -> bool {
+        inline_items.iter().any(|item| self.is_text(item))
+    }
+
+    // Text with something other than whitespace in it
+    fn is_text(&self, item: &InlineItem) -> bool {
+        match &item.inline_content {
+            InlineContent::Text(glyphs) | InlineContent::Link((glyphs, _)) => glyphs.iter().any(|glyph| !self.cache.key(glyph.char).0.is_whitespace()),
+            InlineContent::Image(_) | InlineContent::Math(_) | InlineContent::Ruby(_) | InlineContent::Break | InlineContent::Float(_) => false,
+        }
+    }
+
+    // The first letter, with any punctuation before it, becomes an item of its own, a drop cap when floated or given initial-letter
+    fn split_first_letter(&mut self, inline_items: &mut Vec<InlineItem>, font: Attrs, letter: (f32, Margins, ParseState), block_state: &ParseState) {
+        let (size, margins, letter_state) = letter;
+        let Some(position) = inline_items.iter().position(|item| self.is_text(item)) else { return };
+        let item        = &mut inline_items[position];
+        let (glyphs, href) = match &mut item.inline_content {
+            InlineContent::Text(glyphs)         => (glyphs, None),
+            InlineContent::Link((glyphs, href)) => (glyphs, Some(href.clone())),
+            InlineContent::Image(_) | InlineContent::Math(_) | InlineContent::Ruby(_) | InlineContent::Break | InlineContent::Float(_) => return,
+        };
+        let chars: Vec<char> = glyphs.iter().map(|glyph| self.cache.key(glyph.char).0).collect();
+        let count       = (chars.iter().take_while(|ch| !ch.is_alphanumeric()).count() + 1).min(chars.len());
+        let text: String = chars[..count].iter().collect();
+        let rest: Vec<CharGlyph> = glyphs.drain(..).skip(count).collect();
+        let offset      = rest.first().map_or(item.size.width as f32, |glyph| glyph.x);
+        glyphs.extend(rest.into_iter().map(|glyph| CharGlyph { char: glyph.char, x: glyph.x - offset }));
+        item.size.width -= offset as f64;
+        let rest_empty  = glyphs.is_empty();
+
+        let line_height = self.cache.get_or_insert('x', font, block_state).0.size().height;
+        let letter_font = match letter_state.initial_letter {
+            // Sized so the capital reaches from the top of the first line to the baseline of the sink line
+            Some(initial)   => font.font_size((font.font_size as f64 + (initial.size - 1.) * line_height / CAP_HEIGHT).min(255.).round() as f32),
+            None            => font.font_size(size),
+        };
+        let float       = letter_state.float.or(letter_state.initial_letter.map(|_| FloatSide::Left));
+        let initial     = letter_state.initial_letter;
+        let Some(mut letter_item) = self.parse_text(&text, letter_font, letter_state, href.as_deref()).pop() else { return };
+        if let Some(initial) = initial {
+            let sink = initial.sink as f64;
+            let mut inline_style = letter_item.style.as_deref().cloned().unwrap_or_default();
+            inline_style.vertical_align = VerticalAlign::Shift((-(sink - 1.) * line_height * (1. - BASELINE_RATIO)) as f32);
+            letter_item.style       = Some(Rc::new(inline_style));
+            letter_item.size.height = sink * line_height;
+        }
+        if rest_empty { inline_items.remove(position); }
+        let letter_item = match float {
+            Some(side)  => self.float_anchor(letter_item, side, margins),
+            None        => letter_item,
+        };
+        inline_items.insert(position, letter_item);
     }
 
-    fn process_inline_element(&mut self, block_elem: &mut BlockElem, child: Node, style_sheets: &Vec<StyleSheet>, font: Attrs, parse_state: &ParseState, index: &mut Vec<usize>, inline_items: &mut Vec<InlineItem>, document: &Document) {
+    // Restyles items from the start for as long as they fit on the first line, a line break moved by the restyling is not redone
+    fn style_first_line(&mut self, inline_items: &mut Vec<InlineItem>, font: Attrs, size: f32, line_state: &ParseState, block_state: &ParseState, parse_state: &ParseState) {
+        let (start, end) = line_bounds(self, parse_state, self.curr_y);
+        let scale       = size / font.font_size;
+        let mut used    = 0.;
+        for item in inline_items.iter_mut() {
+            let glyphs = match &mut item.inline_content {
+                InlineContent::Text(glyphs) | InlineContent::Link((glyphs, _)) => glyphs,
+                InlineContent::Image(_) | InlineContent::Math(_) | InlineContent::Ruby(_) | InlineContent::Float(_) => {
+                    used += item.size.width;
+                    if used > end - start { break }
+                    continue
+                }
+                InlineContent::Break => break,
+            };
+            let (restyled, restyled_size) = self.restyle_glyphs(glyphs, font, scale, line_state, block_state);
+            if used + restyled_size.width > end - start { break }
+            used        += restyled_size.width;
+            *glyphs     = restyled;
+            item.size   = restyled_size;
+        }
+    }
+
+    // What `::first-line` sets differently from its block replaces what each glyph was shaped with
+    fn restyle_glyphs(&mut self, glyphs: &[CharGlyph], font: Attrs, scale: f32, line_state: &ParseState, block_state: &ParseState) -> (Vec<CharGlyph>, Size) {
+        let small_caps  = line_state.font_variant != block_state.font_variant && line_state.font_variant != FontVariantCaps::Normal;
+        let uppercase   = line_state.text_transform != block_state.text_transform && line_state.text_transform == TextTransformCase::Uppercase;
+        let mut state   = line_state.clone();
+        let mut restyled = Vec::with_capacity(glyphs.len());
+        let mut x: f32  = 0.;
+        let mut height: f64 = 0.;
+        for glyph in glyphs {
+            let (ch, glyph_size, weight, style, _, color) = self.cache.key(glyph.char);
+            if line_state.font_weight == block_state.font_weight { state.font_weight = weight }
+            if line_state.text_style == block_state.text_style { state.text_style = style }
+            if line_state.color == block_state.color { state.color = color }
+            let mut glyph_font = font.font_size((glyph_size as f32 * scale).round());
+            if small_caps && ch.is_lowercase() {
+                glyph_font = glyph_font.font_size((glyph_font.font_size * SMALL_CAPS_SCALE).round());
+            }
+            let chars: Vec<char> = match uppercase || (small_caps && ch.is_lowercase()) {
+                true    => ch.to_uppercase().collect(),
+                false   => vec![ch],
+            };
+            for ch in chars {
+                let (text_layout, index) = self.cache.get_or_insert(ch, glyph_font, &state);
+                restyled.push(CharGlyph { char: index, x });
+                x       += text_layout.size().width as f32;
+                height  = height.max(text_layout.size().height);
+            }
+        }
+        (restyled, Size::new(x as f64, height))
+    }
+
+    // A float goes at the current height, or below the floats beside it when it does not fit next to them.
+    // It is its own elem and does not advance curr_y, the lines after it wrap around it through line_bounds
+    pub fn place_float(&mut self, id: usize, line: usize, parse_state: &ParseState) {
+        let Some((item, side, margins)) = self.pending_floats.get_mut(id).and_then(Option::take) else { return };
+        let curr_y = self.curr_y;
+        self.floats.retain(|float| float.bottom > curr_y);
+        let width       = item.size.width + margins.left + margins.right;
+        let (top, left) = self.float_position(side, width, parse_state);
+        let height      = margins.top + item.size.height;
+        let image_line  = ElemLine { height: item.size.height, inline_elems: vec![InlineElem { x: left + margins.left - parse_state.x, inline_content: item.inline_content, style: item.style }] };
+        let elem_lines  = vec![ElemLine { height: margins.top, inline_elems: Vec::new() }, image_line];
+        self.placed_floats.push((line, Elem {
+            size: Size::new(width, height),
+            point: Point::new(parse_state.x, top),
+            elem_type: ElemType::Lines(ElemLines { height, elem_lines }),
+            decoration: None,
+        }));
+        self.floats.push(Float { side, left, right: left + width, top, bottom: top + height + margins.bottom });
+    }
+
+    /// Room a pending float takes across a line, margins included
+    pub fn float_width(&self, id: usize) -> f64 {
+        self.pending_floats.get(id).and_then(Option::as_ref).map_or(0., |(item, _, margins)| item.size.width + margins.left + margins.right)
+    }
+
+    fn process_inline_element(&mut self, child: Node, style_sheets: &Vec<StyleSheet>, font: Attrs, parse_state: &ParseState, index: &mut Vec<usize>, inline_items: &mut Vec<InlineItem>, document: &Document) {
         let tag_name = child.tag_name().name();
         match tag_name {
             "" => inline_items.extend(self.parse_text(child.text().unwrap_or_default(), font, parse_state.clone(), None)),
-            "img" => inline_items.push(self.parse_img(child, style_sheets, font, index, parse_state.clone(), document)),
-            "br" => { self.flush_inline_items(block_elem, font, inline_items, parse_state, index)},
+            "img" => inline_items.extend(self.parse_img(child, style_sheets, font, index, parse_state.clone(), document)),
+            "svg" => inline_items.extend(self.parse_svg(child, style_sheets, font, index, parse_state.clone(), document)),
+            "math" => inline_items.extend(self.parse_math(child, style_sheets, font, index, parse_state.clone(), document)),
+            "ruby" => inline_items.extend(self.parse_ruby(child, style_sheets, font, index, parse_state.clone(), document)),
+            "br" => inline_items.push(self.line_break(font, parse_state)),
             "a" => {
                 let href = child.attribute("href");
                 inline_items.extend(self.parse_inline(child, style_sheets, font, parse_state.clone(), href, index, document).0);
@@ -399,19 +1130,40 @@ impl BookElemFactory {
         let mut inline_items: Vec<InlineItem> = Vec::new();
         let now = Instant::now();
         let (_, mut parse_state) = resolve_style_cached(style_sheets, &node, &mut font, parse_state, document, &mut self.style_cache);
+        let (before, after) = self.resolve_pseudo_elements(node, style_sheets, font, &parse_state, document);
         parse_state.ancestors.push(node.id());
+        if let Some(background) = parse_state.block_decoration.take().and_then(|decoration| decoration.background) {
+            let mut inline_style = parse_state.inline_style.as_deref().cloned().unwrap_or_default();
+            inline_style.background = Some(background);
+            parse_state.inline_style = Some(Rc::new(inline_style));
+        }
 
         self.style_time += (Instant::now() - now).as_nanos();
         let mut final_text_align = parse_state.text_align;
         if let Some(id) = node.attribute("id") {
             self.locations.insert(id.to_string(), index.clone());
         }
+        self.update_counters(&parse_state);
+        let counter_mark = self.counters.mark();
+        inline_items.extend(self.generate_content(node, before));
         for child in node.children() {
             if child.tag_name().name().eq("") { 
                 inline_items.extend(self.parse_text(child.text().unwrap_or_default(), font, parse_state.clone(), href)); 
             }
                 else if child.tag_name().name().eq("img") {
-                    inline_items.push(self.parse_img(child, style_sheets, font, index, parse_state.clone(), document))
+                    inline_items.extend(self.parse_img(child, style_sheets, font, index, parse_state.clone(), document))
+                }
+                else if child.has_tag_name("svg") {
+                    inline_items.extend(self.parse_svg(child, style_sheets, font, index, parse_state.clone(), document))
+                }
+                else if child.has_tag_name("math") {
+                    inline_items.extend(self.parse_math(child, style_sheets, font, index, parse_state.clone(), document))
+                }
+                else if child.has_tag_name("ruby") {
+                    inline_items.extend(self.parse_ruby(child, style_sheets, font, index, parse_state.clone(), document))
+                }
+                else if child.has_tag_name("br") {
+                    inline_items.push(self.line_break(font, &parse_state))
                 }
             else if child.has_tag_name("a")  {
                 if let Some(href) = child.attribute("href") { 
@@ -428,83 +1180,270 @@ impl BookElemFactory {
                 inline_items.extend(inline); 
             }
         }
+        inline_items.extend(self.generate_content(node, after));
+        self.counters.release(counter_mark);
         (inline_items, final_text_align)
     }
 
-    pub fn parse_img(&mut self, node: Node, style_sheets: &Vec<StyleSheet>, mut font: Attrs, index: &Vec<usize>, mut parse_state: ParseState, document: &Document) -> InlineItem {
+    // Pseudo styles are matched before the element joins the ancestors, like the element itself
+    fn resolve_pseudo_elements<'a>(&mut self, node: Node, style_sheets: &Vec<StyleSheet>, font: Attrs<'a>, parse_state: &ParseState, document: &Document) -> (Option<(Attrs<'a>, ParseState)>, Option<(Attrs<'a>, ParseState)>) {
+        let mut resolve = |pseudo| {
+            let mut pseudo_font = font;
+            resolve_pseudo_style_cached(style_sheets, &node, pseudo, &mut pseudo_font, parse_state.clone(), document, &mut self.style_cache)
+                .map(|(_, pseudo_state)| (pseudo_font, pseudo_state))
+        };
+        (resolve(PseudoElement::Before), resolve(PseudoElement::After))
+    }
+
+    fn resolve_first_line_style(&mut self, node: Node, style_sheets: &Vec<StyleSheet>, font: Attrs, parse_state: &ParseState, document: &Document) -> Option<FirstLineStyle> {
+        let (mut letter_font, mut line_font) = (font, font);
+        let letter  = resolve_pseudo_style_cached(style_sheets, &node, PseudoElement::FirstLetter, &mut letter_font, parse_state.clone(), document, &mut self.style_cache);
+        let line    = resolve_pseudo_style_cached(style_sheets, &node, PseudoElement::FirstLine, &mut line_font, parse_state.clone(), document, &mut self.style_cache);
+        if letter.is_none() && line.is_none() { return None }
+        Some(FirstLineStyle {
+            letter: letter.map(|(margins, letter_state)| (letter_font.font_size, margins, letter_state)),
+            line: line.map(|(_, line_state)| (line_font.font_size, line_state)),
+            block: parse_state.clone(),
+        })
+    }
+
+    // Resets come before increments, as in CSS
+    fn update_counters(&mut self, parse_state: &ParseState) {
+        for (name, value) in parse_state.counter_reset.iter().flat_map(|counters| counters.iter()) {
+            self.counters.reset(name, *value);
+        }
+        for (name, by) in parse_state.counter_increment.iter().flat_map(|counters| counters.iter()) {
+            self.counters.increment(name, *by);
+        }
+    }
+
+    fn generate_content(&mut self, node: Node, pseudo: Option<(Attrs, ParseState)>) -> Vec<InlineItem> {
+        let Some((font, parse_state)) = pseudo else { return Vec::new() };
+        self.update_counters(&parse_state);
+        let Some(content) = parse_state.content.clone() else { return Vec::new() };
+        let mut text = String::new();
+        for item in content.iter() {
+            match item {
+                ContentItem::Text(value)    => text.push_str(value),
+                ContentItem::Attr(name)     => text.push_str(node.attribute(name.as_str()).unwrap_or_default()),
+                ContentItem::Counter(name, style) => text.push_str(&format_counter(self.counters.value(name), *style)),
+                ContentItem::Counters(name, separator, style) => {
+                    let values: Vec<String> = self.counters.values(name).into_iter().map(|value| format_counter(value, *style)).collect();
+                    text.push_str(&values.join(separator))
+                }
+                ContentItem::OpenQuote      => text.push('“'),
+                ContentItem::CloseQuote     => text.push('”'),
+            }
+        }
+        self.parse_text(&text, font, parse_state, None)
+    }
+
+    pub fn parse_img(&mut self, node: Node, style_sheets: &Vec<StyleSheet>, mut font: Attrs, index: &Vec<usize>, mut parse_state: ParseState, document: &Document) -> Option<InlineItem> {
         if let Some(id) = node.attribute("id") {
             self.locations.insert(id.to_string(), index.clone());
         }
         parse_state.ancestors.push(node.id());
-        let (_, mut parse_state) = resolve_style_cached(style_sheets, &node, &mut font, parse_state, document, &mut self.style_cache);
-        let relative_path   = node.attribute("src").unwrap();
+        let (margins, parse_state) = resolve_style_cached(style_sheets, &node, &mut font, parse_state, document, &mut self.style_cache);
+        let relative_path   = node.attribute("src")?;
         let image_path      = resolve_path(&self.base_path, relative_path);
-        let image           = self.images.get(&image_path).unwrap();
-        let size            = Size::new(image.width as f64, image.height as f64);
-        InlineItem {size, inline_content: InlineContent::Image(image.clone())}
+        let image           = self.images.get(&image_path)?.clone();
+        let float           = parse_state.float;
+        self.float_or_inline(image_item(image, node.attribute("width"), node.attribute("height"), parse_state), float, margins)
     }
 
+    // A floated image leaves only its anchor in the text, line layout places it beside the line the anchor ends up on
+    fn float_or_inline(&mut self, item: InlineItem, float: Option<FloatSide>, margins: Margins) -> Option<InlineItem> {
+        match float {
+            Some(side) => Some(self.float_anchor(item, side, margins)),
+            None => {
+                self.after_space = false;
+                Some(item)
+            }
+        }
+    }
 
+    fn float_anchor(&mut self, item: InlineItem, side: FloatSide, margins: Margins) -> InlineItem {
+        self.pending_floats.push(Some((item, side, margins)));
+        InlineItem { size: Size::ZERO, inline_content: InlineContent::Float(self.pending_floats.len() - 1), style: None }
+    }
 
-    pub fn parse_text(&mut self, text: &str, font: Attrs, parse_state: ParseState, href: Option<&str>) -> Vec<InlineItem> {
-        if text.is_empty() || text == "\n" { return Vec::new(); }
-        
-        let mut inline_items: Vec<InlineItem> = Vec::new();
-        let mut segments: Vec<(usize, usize)> = Vec::new();
-        let mut segment_start = 0;
-        let mut current_is_space: Option<bool> = None;
-        let mut only_whitespace = true;
-        
-        for (i, ch) in text.char_indices() {
-            let is_space = ch.is_whitespace();
-            if !is_space { only_whitespace = false; }
-            
-            match current_is_space {
-                Some(flag) if flag == is_space => {
-                    // Continue current segment
-                }
-                Some(_) => {
-                    // End current segment and start new one
-                    segments.push((segment_start, i));
-                    segment_start = i;
-                    current_is_space = Some(is_space);
-                }
-                None => {
-                    current_is_space = Some(is_space);
+    // The usual EPUB cover, an svg wrapping a single <image>, is laid out as that image, any other svg is drawn as is
+    pub fn parse_svg(&mut self, node: Node, style_sheets: &Vec<StyleSheet>, mut font: Attrs, index: &Vec<usize>, mut parse_state: ParseState, document: &Document) -> Option<InlineItem> {
+        if let Some(id) = node.attribute("id") {
+            self.locations.insert(id.to_string(), index.clone());
+        }
+        parse_state.ancestors.push(node.id());
+        let (margins, parse_state) = resolve_style_cached(style_sheets, &node, &mut font, parse_state, document, &mut self.style_cache);
+        let float = parse_state.float;
+        let drawn: Vec<Node> = node.children()
+            .filter(|child| child.is_element() && !matches!(child.tag_name().name(), "title" | "desc" | "metadata"))
+            .collect();
+        if let [image] = drawn[..] {
+            if image.has_tag_name("image") {
+                let href = image.attribute((XLINK_NAMESPACE, "href")).or(image.attribute("href"))?;
+                let image_elem = self.images.get(&resolve_path(&self.base_path, href))?.clone();
+                return self.float_or_inline(image_item(image_elem, image.attribute("width"), image.attribute("height"), parse_state), float, margins)
+            }
+        }
+        let svg     = load_inline_svg(node, &self.base_path, &self.raster_images).ok()?;
+        let size    = svg.tree.size();
+        let image   = ImageElem { width: size.width().round() as u16, height: size.height().round() as u16, image_promise: Arc::new(RwLock::new(None)), svg: Some(Arc::new(svg)) };
+        self.float_or_inline(image_item(image, node.attribute("width"), node.attribute("height"), parse_state), float, margins)
+    }
+
+
+
+    // Each run of base text is paired with the rt that follows it, rp is only for readers without ruby support
+    pub fn parse_ruby(&mut self, node: Node, style_sheets: &Vec<StyleSheet>, mut font: Attrs, index: &Vec<usize>, parse_state: ParseState, document: &Document) -> Vec<InlineItem> {
+        if let Some(id) = node.attribute("id") {
+            self.locations.insert(id.to_string(), index.clone());
+        }
+        let (_, mut parse_state) = resolve_style_cached(style_sheets, &node, &mut font, parse_state, document, &mut self.style_cache);
+        parse_state.ancestors.push(node.id());
+        let mut inline_items = Vec::new();
+        let mut base = String::new();
+        for child in node.children() {
+            match child.tag_name().name() {
+                "rp" => (),
+                "rt" | "rtc" => {
+                    let mut annotation_font = font;
+                    let (_, annotation_state) = resolve_style_cached(style_sheets, &child, &mut annotation_font, parse_state.clone(), document, &mut self.style_cache);
+                    let annotation = ruby_text(child);
+                    let item = self.ruby_item(base.trim(), font, &parse_state, annotation.trim(), annotation_font, &annotation_state);
+                    inline_items.push(item);
+                    base.clear();
                 }
+                _ => base.push_str(&ruby_text(child)),
             }
         }
-        
-        if only_whitespace { return Vec::new(); }
-        if segment_start < text.len() {
-            segments.push((segment_start, text.len()));
+        // Base text left without an annotation is ordinary text
+        if !base.trim().is_empty() {
+            inline_items.extend(self.parse_text(&base, font, parse_state, None));
         }
+        self.after_space = false;
+        inline_items
+    }
 
-        inline_items.reserve(segments.len());
-        
-        for (start, end) in segments {
-            let segment = &text[start..end];
-            let mut char_x = 0.;
-            let mut segment_height: f64 = 0.;
-            let mut char_glyphs = Vec::with_capacity(segment.chars().count());
-            
-            for ch in segment.chars() {
-                let (text_layout, index) = self.cache.get_or_insert(ch, font, &parse_state);
-                char_glyphs.push(CharGlyph { char: index, x: char_x });
-                char_x += text_layout.size().width as f32;
-                segment_height = segment_height.max(text_layout.size().height);
+    fn ruby_item(&mut self, base: &str, font: Attrs, parse_state: &ParseState, annotation: &str, annotation_font: Attrs, annotation_state: &ParseState) -> InlineItem {
+        let base_item       = self.shape_text(base, font, parse_state, None);
+        let annotation_item = self.shape_text(annotation, annotation_font, annotation_state, None);
+        let (base_ascent, _) = glyph_extents(self.cache.get_or_insert(' ', font, parse_state).0);
+        let (annotation_ascent, annotation_descent) = glyph_extents(self.cache.get_or_insert(' ', annotation_font, annotation_state).0);
+        let width           = base_item.size.width.max(annotation_item.size.width);
+        let (base_glyphs, annotation_glyphs) = match (base_item.inline_content, annotation_item.inline_content) {
+            (InlineContent::Text(base_glyphs), InlineContent::Text(annotation_glyphs)) => (base_glyphs, annotation_glyphs),
+            // Anything but plain text keeps the base as it was shaped and leaves out the annotation
+            (base_content, _) => return InlineItem { size: base_item.size, inline_content: base_content, style: base_item.style },
+        };
+        let ruby = Ruby {
+            base: base_glyphs,
+            annotation: annotation_glyphs,
+            base_x: ((width - base_item.size.width) / 2.) as f32,
+            annotation_x: ((width - annotation_item.size.width) / 2.) as f32,
+            raise: (base_ascent + annotation_descent) as f32,
+            above: (base_ascent + annotation_descent + annotation_ascent) as f32,
+            width,
+        };
+        InlineItem { size: Size::new(width, base_item.size.height), inline_content: InlineContent::Ruby(Rc::new(ruby)), style: base_item.style }
+    }
+
+    pub fn parse_text(&mut self, text: &str, font: Attrs, parse_state: ParseState, href: Option<&str>) -> Vec<InlineItem> {
+        if text.is_empty() { return Vec::new(); }
+
+        let white_space     = parse_state.white_space;
+        let collapse        = matches!(white_space, WhiteSpace::Normal | WhiteSpace::NoWrap | WhiteSpace::PreLine);
+        let keep_newlines   = !matches!(white_space, WhiteSpace::Normal | WhiteSpace::NoWrap);
+        let wrap            = !matches!(white_space, WhiteSpace::Pre | WhiteSpace::NoWrap);
+        let lines: Vec<&str> = match keep_newlines {
+            true    => text.split('\n').collect(),
+            false   => vec![text],
+        };
+        let mut inline_items: Vec<InlineItem> = Vec::new();
+        for (line_index, line) in lines.into_iter().enumerate() {
+            if line_index > 0 {
+                inline_items.push(self.line_break(font, &parse_state));
             }
-            
-            let size = Size::new(char_x as f64, segment_height as f64);
-            let inline_content = match href {
-                None => InlineContent::Text(char_glyphs),
-                Some(href) => InlineContent::Link((char_glyphs, href.to_string()))
+            let line = match collapse {
+                true    => Cow::Borrowed(line),
+                false   => expand_tabs(line),
             };
-            inline_items.push(InlineItem { size, inline_content });
+            // Collapsible whitespace is one space, dropped after a space or at the start of a line, whichever element it ended in
+            let mut unbroken = String::new();
+            for (run, is_space) in whitespace_runs(&line) {
+                let run = match (collapse, is_space) {
+                    (true, true) if self.after_space => continue,
+                    (true, true) => " ",
+                    _ => run,
+                };
+                if collapse { self.after_space = is_space }
+                match wrap {
+                    true    => inline_items.push(self.shape_text(run, font, &parse_state, href)),
+                    false   => unbroken.push_str(run),
+                }
+            }
+            if !unbroken.is_empty() {
+                inline_items.push(self.shape_text(&unbroken, font, &parse_state, href));
+            }
         }
-
+        if !collapse { self.after_space = false }
         inline_items
     }
+
+    // A forced break, as tall as a line of text in this font so that an empty line keeps its height
+    pub fn line_break(&mut self, font: Attrs, parse_state: &ParseState) -> InlineItem {
+        let height = match parse_state.line_height {
+            LineHeight::Normal          => self.cache.get_or_insert(' ', font, parse_state).0.size().height,
+            LineHeight::Factor(factor)  => factor * font.font_size as f64,
+            LineHeight::Length(length)  => length,
+        };
+        self.after_space = true;
+        InlineItem { size: Size::new(0., height), inline_content: InlineContent::Break, style: None }
+    }
+
+    // A run of text that is laid out as a unit, the line can only break between runs
+    fn shape_text(&mut self, text: &str, font: Attrs, parse_state: &ParseState, href: Option<&str>) -> InlineItem {
+        let small_caps_font = font.font_size((font.font_size * SMALL_CAPS_SCALE).round());
+        let line_height = match parse_state.line_height {
+            LineHeight::Normal          => None,
+            LineHeight::Factor(factor)  => Some(factor * font.font_size as f64),
+            LineHeight::Length(length)  => Some(length),
+        };
+        let letter_spacing  = parse_state.letter_spacing as f32;
+        let word_spacing    = parse_state.word_spacing as f32;
+        let segment         = transform_text(text, parse_state.text_transform);
+        let mut char_x      = 0.;
+        let mut segment_height: f64 = 0.;
+        let mut char_glyphs = Vec::with_capacity(segment.chars().count());
+
+        for ch in segment.chars() {
+            let small_caps = match parse_state.font_variant {
+                FontVariantCaps::SmallCaps | FontVariantCaps::PetiteCaps        => ch.is_lowercase(),
+                FontVariantCaps::AllSmallCaps | FontVariantCaps::AllPetiteCaps  => ch.is_lowercase() || ch.is_uppercase(),
+                _ => false,
+            };
+            if small_caps {
+                for upper in ch.to_uppercase() {
+                    let (text_layout, index) = self.cache.get_or_insert(upper, small_caps_font, parse_state);
+                    char_glyphs.push(CharGlyph { char: index, x: char_x });
+                    char_x += text_layout.size().width as f32 + letter_spacing;
+                    segment_height = segment_height.max(text_layout.size().height);
+                }
+                continue
+            }
+            let (text_layout, index) = self.cache.get_or_insert(ch, font, parse_state);
+            char_glyphs.push(CharGlyph { char: index, x: char_x });
+            char_x += text_layout.size().width as f32 + letter_spacing;
+            if ch.is_whitespace() { char_x += word_spacing }
+            segment_height = segment_height.max(text_layout.size().height);
+        }
+
+        let size = Size::new(char_x as f64, line_height.unwrap_or(segment_height));
+        let inline_content = match href {
+            None => InlineContent::Text(char_glyphs),
+            Some(href) => InlineContent::Link((char_glyphs, href.to_string()))
+        };
+        InlineItem { size, inline_content, style: parse_state.inline_style.clone() }
+    }
     pub fn parse_text5(&mut self, node: Node, font: Attrs, parse_state: ParseState, href: Option<&str>) -> Vec<InlineItem> {
         let mut inline_items: Vec<InlineItem> = Vec::new();
 
@@ -556,8 +1495,8 @@ impl BookElemFactory {
             }
             let size = Size::new(char_x as f64, token_height as f64);
             match href {
-                None => inline_items.push(InlineItem { size, inline_content: InlineContent::Text(char_glyphs) }),
-                Some(href) => inline_items.push(InlineItem { size, inline_content: InlineContent::Link((char_glyphs, href.to_string())) })
+                None => inline_items.push(InlineItem { size, inline_content: InlineContent::Text(char_glyphs), style: parse_state.inline_style.clone() }),
+                Some(href) => inline_items.push(InlineItem { size, inline_content: InlineContent::Link((char_glyphs, href.to_string())), style: parse_state.inline_style.clone() })
             }
         }
 
@@ -576,8 +1515,8 @@ impl BookElemFactory {
             char_glyphs.push(CharGlyph { char: index, x: 0. });
             let size = text_layout.size();
             match href {
-                None => inline_items.push(InlineItem { size, inline_content: InlineContent::Text(char_glyphs) }),
-                Some(href) => inline_items.push(InlineItem { size, inline_content: InlineContent::Link((char_glyphs, href.to_string())) })
+                None => inline_items.push(InlineItem { size, inline_content: InlineContent::Text(char_glyphs), style: parse_state.inline_style.clone() }),
+                Some(href) => inline_items.push(InlineItem { size, inline_content: InlineContent::Link((char_glyphs, href.to_string())), style: parse_state.inline_style.clone() })
             }
             return inline_items;
         }
@@ -601,134 +1540,61 @@ impl BookElemFactory {
             }
             let size = Size::new(char_x as f64, word_height as f64);
             match href {
-                None => inline_items.push(InlineItem { size, inline_content: InlineContent::Text(char_glyphs) }),
-                Some(href) => inline_items.push(InlineItem { size, inline_content: InlineContent::Link((char_glyphs, href.to_string())) })
+                None => inline_items.push(InlineItem { size, inline_content: InlineContent::Text(char_glyphs), style: parse_state.inline_style.clone() }),
+                Some(href) => inline_items.push(InlineItem { size, inline_content: InlineContent::Link((char_glyphs, href.to_string())), style: parse_state.inline_style.clone() })
             }
         }
 
         inline_items
     }
 
-    fn parse_list(&mut self, node: Node, font: Attrs, style_sheets: &Vec<StyleSheet>, mut parse_state: ParseState, mut index: Vec<usize>, document: &Document) -> Elem {
-        let list_type = match node.tag_name().name() {
-            "ul" => ListType::Unordered(BulletStyle::Disc),
-            "ol" => ListType::Ordered(NumberStyle::Decimal),
-            _ => unreachable!(),
-        };
-        let init_point = Point::new(self.curr_x, self.curr_y);
-
-        let mut list_context = parse_state.list_context;
-        list_context.list_type = list_type;
-        list_context.indent_width = 20.0 * list_context.depth as f64;
-        list_context.depth += 1;
-        list_context.item_number = 0;
-
-        parse_state.list_context = list_context;
-        parse_state.width -= list_context.indent_width;
-        parse_state.x += list_context.indent_width;
-        self.curr_x = parse_state.x;
+    fn parse_list(&mut self, node: Node, font: Attrs, style_sheets: &Vec<StyleSheet>, mut parse_state: ParseState, index: Vec<usize>, document: &Document) -> Elem {
+        let item_count = node.children().filter(|child| child.has_tag_name("li")).count();
+        parse_state.list_context = begin_list(parse_state.list_context, node.tag_name().name(), node.has_attribute("reversed"), node.attribute("start"), item_count);
         self.parse(node, font, style_sheets, parse_state, index, document)
-            /*
-
-
-        let mut block_elem = BlockElem { children: Vec::new(), total_child_count: 0 };
-
-        for child in node.children() {
-            parse_state.list_context.item_number += 1;
-            block_elem.add_child(self.parse_list_item(child, font, style_sheets, parse_state, index.clone()));
-            *index.last_mut().unwrap() += 1;
-        }
-        let block_height = block_elem.children.iter().fold(0., |acc, elem| acc + elem.size.height);
-        Elem { size: Size::new(parse_state.width, block_height), point: init_point, elem_type: ElemType::Block(block_elem) }
-*/
     }
-    
-    fn parse_list_item(&mut self, node: Node, font: Attrs, style_sheets: &Vec<StyleSheet>, mut parse_state: ParseState, index: Vec<usize>, document: &Document) -> Elem {
-        let mut list_context = parse_state.list_context;
-
-        let marker = self.generate_list_marker(list_context, font, parse_state.clone());
-        
-        let mut block_elem = BlockElem { children: Vec::new(), total_child_count: 0 };
-        let marker_elem = layout_elem_lines(self, vec![marker.clone()], &parse_state);
-        block_elem.add_child(marker_elem);
-        
-        //parse_state.width -= marker.size.width;
-        parse_state.x += marker.size.width;
-        //self.curr_x += marker.size.width;
-        self.curr_y -= marker.size.height;
-
-        let mut content = self.parse(node, font, style_sheets, parse_state.clone(), index, document);
-
-        block_elem.add_child(content);
-
-        let total_height = block_elem.children.iter().fold(0., |acc, elem| acc + elem.size.height);
-
-        parse_state.width += marker.size.width;
-        parse_state.x -= marker.size.width;
-        Elem { 
-            size: Size::new(parse_state.width + marker.size.width, total_height), 
-            point: Point::new(self.curr_x, self.curr_y),
-            elem_type: ElemType::Block(block_elem) 
-        }
-
 
-    }
-    
-    fn generate_list_marker(&mut self, context: ListContext, font: Attrs, parse_state: ParseState) -> InlineItem {
+    // Markers ignore the item's text decoration and transform, and keep their trailing space as the gap to the content
+    fn generate_list_marker(&mut self, context: ListContext, font: Attrs, mut parse_state: ParseState) -> Option<InlineItem> {
+        let number = context.item_number;
         let marker_text = match context.list_type {
             ListType::Unordered(BulletStyle::Disc) => "• ".to_string(),
             ListType::Unordered(BulletStyle::Circle) => "◦ ".to_string(),
             ListType::Unordered(BulletStyle::Square) => "▪ ".to_string(),
-            ListType::Ordered(NumberStyle::Decimal) => format!("{}. ", context.item_number),
-            ListType::Ordered(NumberStyle::LowerAlpha) => format!("{}. ", self.to_alpha_lower(context.item_number)),
-            ListType::Ordered(NumberStyle::UpperAlpha) => format!("{}. ", self.to_alpha_upper(context.item_number)),
-            ListType::Ordered(NumberStyle::LowerRoman) => format!("{}. ", self.to_roman_lower(context.item_number)),
-            ListType::Ordered(NumberStyle::UpperRoman) => format!("{}. ", self.to_roman_upper(context.item_number)),
-            ListType::None => " ".to_string(),
+            ListType::Ordered(_) => format!("{}. ", format_counter(number, context.list_type)),
+            ListType::None => return None,
         };
-        
-        let mut inline_items = self.parse_text(&marker_text, font, parse_state, None);
-        inline_items.into_iter().next().unwrap_or_else(|| {
-            InlineItem { 
-                size: Size::new(0.0, 0.0), 
-                inline_content: InlineContent::Text(Vec::new()) 
-            }
-        })
-    }
-    
-    fn to_alpha_lower(&self, num: usize) -> char {
-        if num == 0 || num > 26 { 'a' } else { (b'a' + (num - 1) as u8) as char }
-    }
-    
-    fn to_alpha_upper(&self, num: usize) -> char {
-        if num == 0 || num > 26 { 'A' } else { (b'A' + (num - 1) as u8) as char }
-    }
-    
-    fn to_roman_lower(&self, num: usize) -> String {
-        self.to_roman(num).to_lowercase()
-    }
-    
-    fn to_roman_upper(&self, num: usize) -> String {
-        self.to_roman(num)
-    }
-    
-    fn to_roman(&self, mut num: usize) -> String {
-        let vals = [1000, 900, 500, 400, 100, 90, 50, 40, 10, 9, 5, 4, 1];
-        let numerals = ["M", "CM", "D", "CD", "C", "XC", "L", "XL", "X", "IX", "V", "IV", "I"];
-        let mut result = String::new();
-        
-        for (i, &val) in vals.iter().enumerate() {
-            while num >= val {
-                result.push_str(numerals[i]);
-                num -= val;
-            }
+        parse_state.inline_style = None;
+
+        let mut char_x = 0.;
+        let mut height: f64 = 0.;
+        let mut char_glyphs = Vec::with_capacity(marker_text.len());
+        for ch in marker_text.chars() {
+            let (text_layout, index) = self.cache.get_or_insert(ch, font, &parse_state);
+            char_glyphs.push(CharGlyph { char: index, x: char_x });
+            char_x += text_layout.size().width as f32;
+            height = height.max(text_layout.size().height);
         }
-        result
+        Some(InlineItem { size: Size::new(char_x as f64, height), inline_content: InlineContent::Text(char_glyphs), style: None })
+    }
+
+    // The triangle before a summary, both forms are shaped so that toggling the details can swap them
+    fn disclosure_marker(&mut self, open: bool, font: Attrs, parse_state: &ParseState) -> InlineItem {
+        let closed_item = self.shape_text("▸ ", font, parse_state, None);
+        let open_item   = self.shape_text("▾ ", font, parse_state, None);
+        let marker      = |item: &InlineItem| match &item.inline_content {
+            InlineContent::Text(glyphs) => glyphs.first().map_or(0, |glyph| glyph.char),
+            _ => 0,
+        };
+        self.summary_markers = Some([marker(&closed_item), marker(&open_item)]);
+        self.after_space = true;
+        if open { open_item } else { closed_item }
     }
 
+
     // Scraper-based element parsing
     pub fn parse_element_scraper(&mut self, element: ElementRef, mut font: Attrs, style_sheets: &Vec<StyleSheet>, mut parse_state: ParseState, mut index: Vec<usize>) -> Elem {
-        let mut block_elem = BlockElem { children: Vec::new(), total_child_count: 0 };
+        let mut block_elem = BlockElem { children: Vec::new(), total_child_count: 0, details: None, fragmentation: Fragmentation::default(), row: false };
         let mut inline_items = self.inline_pool.get();
         inline_items.clear();
         let init_point = Point::new(self.curr_x, self.curr_y);
@@ -745,6 +1611,10 @@ impl BookElemFactory {
         parse_state.x += margins.left / 2.;
         self.curr_x = parse_state.x;
         self.curr_y += margins.top;
+        let is_list_item = element.value().name() == "li";
+        if is_list_item {
+            self.list_marker = self.generate_list_marker(parse_state.list_context, font, parse_state.clone());
+        }
         index.push(0);
         
         if let Some(id) = element.value().attr("id") {
@@ -769,11 +1639,10 @@ impl BookElemFactory {
                         self.flush_inline_items(&mut block_elem, font, &mut inline_items, &parse_state, &mut index);
                         
                         block_elem.add_child(match tag_name {
-                            "ul" | "ol" => self.parse_list_scraper(element_ref, font, style_sheets, parse_state.clone(), index.clone()),
+                            "ul" | "ol" | "menu" => self.parse_list_scraper(element_ref, font, style_sheets, parse_state.clone(), index.clone()),
                             "li" => {
-                                let mut li_state = parse_state.clone();
-                                li_state.prefix = "-\t";
-                                self.parse_element_scraper(element_ref, font, style_sheets, li_state, index.clone())
+                                next_list_item(&mut parse_state.list_context, element_ref.value().attr("value"));
+                                self.parse_element_scraper(element_ref, font, style_sheets, parse_state.clone(), index.clone())
                             },
                             "pre" => self.parse_pre_scraper(element_ref, font, style_sheets, parse_state.clone(), index.clone()),
                             "table" => self.parse_table_scraper(element_ref, font, style_sheets, parse_state.clone(), index.clone()),
@@ -790,10 +1659,12 @@ impl BookElemFactory {
         
         self.flush_inline_items(&mut block_elem, font, &mut inline_items, &parse_state, &mut index);
         self.inline_pool.put(inline_items);
+        block_elem.propagate_breaks();
         self.curr_y += margins.bottom;
+        if is_list_item { self.list_marker = None }
 
         let block_height = block_elem.children.iter().fold(0., |acc, elem| acc + elem.size.height);
-        Elem { size: Size::new(600., block_height + margins.top + margins.bottom), point: init_point, elem_type: ElemType::Block(block_elem) }
+        Elem { size: Size::new(600., block_height + margins.top + margins.bottom), point: init_point, elem_type: ElemType::Block(block_elem), decoration: None }
     }
 
     fn process_inline_element_scraper(&mut self, block_elem: &mut BlockElem, child: ElementRef, style_sheets: &Vec<StyleSheet>, font: Attrs, parse_state: &ParseState, index: &mut Vec<usize>, inline_items: &mut Vec<InlineItem>) {
@@ -810,8 +1681,10 @@ impl BookElemFactory {
     }
 
     // Placeholder implementations for scraper-based parsing methods
-    fn parse_list_scraper(&mut self, element: ElementRef, font: Attrs, style_sheets: &Vec<StyleSheet>, parse_state: ParseState, index: Vec<usize>) -> Elem {
-        // TODO: Implement scraper-based list parsing
+    fn parse_list_scraper(&mut self, element: ElementRef, font: Attrs, style_sheets: &Vec<StyleSheet>, mut parse_state: ParseState, index: Vec<usize>) -> Elem {
+        let item_count = element.children().filter_map(ElementRef::wrap).filter(|child| child.value().name() == "li").count();
+        let value = element.value();
+        parse_state.list_context = begin_list(parse_state.list_context, value.name(), value.attr("reversed").is_some(), value.attr("start"), item_count);
         self.parse_element_scraper(element, font, style_sheets, parse_state, index)
     }
 
@@ -825,13 +1698,17 @@ impl BookElemFactory {
         let image_path = resolve_path(&self.base_path, relative_path);
         
         if let Some(image) = self.images.get(&image_path) {
-            let size = Size::new(image.width as f64, image.height as f64);
-            InlineItem { size, inline_content: InlineContent::Image(image.clone()) }
+            let mut image   = image.clone();
+            let size        = resolve_image_size(&image, element.value().attr("width"), element.value().attr("height"), parse_state.box_size, parse_state.width);
+            image.width     = size.width.round() as u16;
+            image.height    = size.height.round() as u16;
+            InlineItem { size, inline_content: InlineContent::Image(image), style: None }
         } else {
             // Default empty image
             InlineItem { 
                 size: Size::new(0.0, 0.0), 
-                inline_content: InlineContent::Text(Vec::new()) 
+                inline_content: InlineContent::Text(Vec::new()),
+                style: None,
             }
         }
     }
@@ -873,7 +1750,204 @@ impl BookElemFactory {
 
 }
 
-fn resolve_path(html_path: &str, relative_path: &str) -> String {
+fn image_item(mut image: ImageElem, width: Option<&str>, height: Option<&str>, parse_state: ParseState) -> InlineItem {
+    let size        = resolve_image_size(&image, width, height, parse_state.box_size, parse_state.width);
+    image.width     = size.width.round() as u16;
+    image.height    = size.height.round() as u16;
+    // Images only take the alignment, decorations and backgrounds belong to text
+    let style = parse_state.inline_style
+        .filter(|style| style.vertical_align != VerticalAlign::Baseline)
+        .map(|style| Rc::new(InlineStyle { vertical_align: style.vertical_align, ..Default::default() }));
+    InlineItem {size, inline_content: InlineContent::Image(image), style}
+}
+
+// CSS sizes win over the attributes, a missing dimension follows the intrinsic aspect ratio
+fn resolve_image_size(image: &ImageElem, width_attribute: Option<&str>, height_attribute: Option<&str>, box_size: BoxSize, container_width: f64) -> Size {
+    let intrinsic   = Size::new(image.width as f64, image.height as f64);
+    let ratio       = if intrinsic.height > 0. { intrinsic.width / intrinsic.height } else { 1. };
+    let width       = box_size.width.or_else(|| width_attribute.and_then(|value| resolve_dimension_attribute(value, Some(container_width))));
+    let height      = box_size.height.or_else(|| height_attribute.and_then(|value| resolve_dimension_attribute(value, None)));
+    let mut size = match (width, height) {
+        (Some(width), Some(height)) => Size::new(width, height),
+        (Some(width), None)         => Size::new(width, width / ratio),
+        (None, Some(height))        => Size::new(height * ratio, height),
+        (None, None)                => intrinsic,
+    };
+    // Limits keep the aspect ratio unless the other dimension was set explicitly
+    if let Some(max_width) = box_size.max_width.filter(|max_width| size.width > *max_width) {
+        if height.is_none() { size.height *= max_width / size.width }
+        size.width = max_width;
+    }
+    if let Some(max_height) = box_size.max_height.filter(|max_height| size.height > *max_height) {
+        if width.is_none() { size.width *= max_height / size.height }
+        size.height = max_height;
+    }
+    Size::new(size.width.max(0.), size.height.max(0.))
+}
+
+// `300`, `300px` or `50%`, percentages need a base to resolve against
+fn resolve_dimension_attribute(value: &str, percentage_base: Option<f64>) -> Option<f64> {
+    let value = value.trim();
+    match value.strip_suffix('%') {
+        Some(percentage) => Some(percentage.trim().parse::<f64>().ok()? / 100. * percentage_base?),
+        None => value.trim_end_matches("px").trim().parse().ok(),
+    }
+}
+
+// Page box of a fixed-layout section, from `<meta name="viewport" content="width=1200, height=1600">` in its head
+fn read_viewport(html: Node) -> Option<Size> {
+    let head    = html.children().find(|child| child.has_tag_name("head"))?;
+    let meta    = head.children().find(|child| child.has_tag_name("meta") && child.attribute("name").is_some_and(|name| name.trim().eq_ignore_ascii_case("viewport")))?;
+    let (mut width, mut height) = (None, None);
+    for declaration in meta.attribute("content")?.split([',', ';']) {
+        let Some((name, value)) = declaration.split_once('=') else { continue };
+        match name.trim() {
+            "width"     => width = resolve_dimension_attribute(value, None),
+            "height"    => height = resolve_dimension_attribute(value, None),
+            _ => ()
+        }
+    }
+    Some(Size::new(width.filter(|width| *width > 0.)?, height.filter(|height| *height > 0.)?))
+}
+
+/// Formats a list or CSS counter value, bullets and values without a letter or numeral fall back to their glyph or decimal
+pub fn format_counter(value: isize, list_type: ListType) -> String {
+    match list_type {
+        ListType::None => String::new(),
+        ListType::Unordered(BulletStyle::Disc) => "•".to_string(),
+        ListType::Unordered(BulletStyle::Circle) => "◦".to_string(),
+        ListType::Unordered(BulletStyle::Square) => "▪".to_string(),
+        ListType::Ordered(_) if value <= 0 => value.to_string(),
+        ListType::Ordered(NumberStyle::Decimal) => value.to_string(),
+        ListType::Ordered(NumberStyle::LowerAlpha) => to_alpha(value as usize).to_lowercase(),
+        ListType::Ordered(NumberStyle::UpperAlpha) => to_alpha(value as usize),
+        ListType::Ordered(NumberStyle::LowerRoman) => to_roman(value as usize).to_lowercase(),
+        ListType::Ordered(NumberStyle::UpperRoman) => to_roman(value as usize),
+    }
+}
+
+// Bijective base 26, so 27 becomes AA
+fn to_alpha(mut num: usize) -> String {
+    let mut letters = Vec::new();
+    while num > 0 {
+        num -= 1;
+        letters.push((b'A' + (num % 26) as u8) as char);
+        num /= 26;
+    }
+    letters.iter().rev().collect()
+}
+
+fn to_roman(mut num: usize) -> String {
+    if num >= 4000 { return num.to_string() }
+    let vals = [1000, 900, 500, 400, 100, 90, 50, 40, 10, 9, 5, 4, 1];
+    let numerals = ["M", "CM", "D", "CD", "C", "XC", "L", "XL", "X", "IX", "V", "IV", "I"];
+    let mut result = String::new();
+
+    for (i, &val) in vals.iter().enumerate() {
+        while num >= val {
+            result.push_str(numerals[i]);
+            num -= val;
+        }
+    }
+    result
+}
+
+/// Context for a new `ul`/`ol`, the counter sits one step before the first item
+fn begin_list(parent: ListContext, tag_name: &str, reversed: bool, start: Option<&str>, item_count: usize) -> ListContext {
+    let list_type = match tag_name {
+        "ol" => ListType::Ordered(NumberStyle::Decimal),
+        _ => ListType::Unordered(match parent.depth {
+            0 => BulletStyle::Disc,
+            1 => BulletStyle::Circle,
+            _ => BulletStyle::Square,
+        }),
+    };
+    let start = start.and_then(|start| start.trim().parse::<isize>().ok())
+        .unwrap_or(if reversed { item_count as isize } else { 1 });
+    ListContext {
+        list_type,
+        item_number: if reversed { start + 1 } else { start - 1 },
+        reversed,
+        depth: parent.depth + 1,
+        marker_inside: parent.marker_inside,
+    }
+}
+
+fn next_list_item(list_context: &mut ListContext, value: Option<&str>) {
+    let step = if list_context.reversed { -1 } else { 1 };
+    list_context.item_number = value.and_then(|value| value.trim().parse().ok()).unwrap_or(list_context.item_number + step);
+}
+
+// The text of a ruby base or annotation, without any rp inside it
+fn ruby_text(node: Node) -> String {
+    node.descendants()
+        .filter(|descendant| descendant.is_text() && !descendant.ancestors().any(|ancestor| ancestor.has_tag_name("rp")))
+        .filter_map(|descendant| descendant.text())
+        .collect()
+}
+
+// Preserved whitespace can leave several words in one segment, capitalize takes the first letter of each
+fn transform_text(segment: &str, text_transform: TextTransformCase) -> Cow<str> {
+    match text_transform {
+        TextTransformCase::None         => Cow::Borrowed(segment),
+        TextTransformCase::Uppercase    => Cow::Owned(segment.to_uppercase()),
+        TextTransformCase::Lowercase    => Cow::Owned(segment.to_lowercase()),
+        TextTransformCase::Capitalize   => {
+            let mut word_start  = true;
+            let mut capitalized = String::with_capacity(segment.len() + 2);
+            for ch in segment.chars() {
+                if word_start && ch.is_alphabetic() {
+                    capitalized.extend(ch.to_uppercase());
+                    word_start = false;
+                } else {
+                    capitalized.push(ch);
+                    if ch.is_whitespace() { word_start = true }
+                    else if ch.is_alphanumeric() { word_start = false }
+                }
+            }
+            if capitalized == segment { Cow::Borrowed(segment) } else { Cow::Owned(capitalized) }
+        }
+    }
+}
+
+// Alternating runs of collapsible whitespace and of everything else, a no-break space is not whitespace here
+fn whitespace_runs(text: &str) -> Vec<(&str, bool)> {
+    let mut runs    = Vec::new();
+    let mut start   = 0;
+    let mut current = None;
+    for (i, ch) in text.char_indices() {
+        let is_space = matches!(ch, ' ' | '\t' | '\n' | '\r' | '\x0C');
+        if let Some(flag) = current.filter(|flag| *flag != is_space) {
+            runs.push((&text[start..i], flag));
+            start = i;
+        }
+        current = Some(is_space);
+    }
+    if let Some(is_space) = current {
+        runs.push((&text[start..], is_space));
+    }
+    runs
+}
+
+// Tab stops every TAB_SIZE columns, counted from the start of the line
+fn expand_tabs(line: &str) -> Cow<str> {
+    if !line.contains('\t') { return Cow::Borrowed(line) }
+    let mut expanded    = String::with_capacity(line.len() + TAB_SIZE);
+    let mut column      = 0;
+    for ch in line.chars() {
+        if ch == '\t' {
+            let spaces = TAB_SIZE - column % TAB_SIZE;
+            expanded.extend(std::iter::repeat(' ').take(spaces));
+            column += spaces;
+        } else {
+            expanded.push(ch);
+            column += 1;
+        }
+    }
+    Cow::Owned(expanded)
+}
+
+pub(crate) fn resolve_path(html_path: &str, relative_path: &str) -> String {
     let html_dir = Path::new(html_path).parent().unwrap_or_else(|| Path::new(""));
     let joined = html_dir.join(relative_path);
     let mut normalized_path = PathBuf::new();
@@ -888,12 +1962,15 @@ fn resolve_path(html_path: &str, relative_path: &str) -> String {
 }
 
 mod tests {
+    use std::borrow::Cow;
     use std::collections::HashMap;
     use std::fs;
     use floem::peniko::Color;
     use floem_renderer::text::{Attrs, FamilyOwned, LineHeightValue};
+    use lightningcss::properties::text::TextTransformCase;
+    use lightningcss::stylesheet::{ParserOptions, StyleSheet};
     use roxmltree::Document;
-    use crate::book_elem::{BookElemFactory, InlineContent};
+    use crate::book_elem::{expand_tabs, transform_text, whitespace_runs, BookElemFactory, Elem, ElemType, InlineContent};
     use crate::glyph_interner::GlyphCache;
 
     #[test]
@@ -911,9 +1988,77 @@ mod tests {
                 .line_height(LineHeightValue::Normal(1.5))
                 .color(Color::rgb8(43, 43, 43))
                 ;
-            let mut book_factory = BookElemFactory::new(cache, HashMap::new(), &base_font);
+            let mut book_factory = BookElemFactory::new(cache, HashMap::new(), HashMap::new(), &base_font, Color::rgb8(43, 43, 43));
             //let root = book_factory.parse_root(document.root(), base_font, "/".to_string(), &Vec::new());
         }
 
     }
-}
\ No newline at end of file
+
+    // The text of each line laid out for `body`, a line to a string
+    fn laid_out_lines(body: &str, css: &str) -> Vec<String> {
+        fn collect(elem: &Elem, cache: &GlyphCache, lines: &mut Vec<String>) {
+            match &elem.elem_type {
+                ElemType::Block(block) => block.children.iter().for_each(|child| collect(child, cache, lines)),
+                ElemType::Lines(elem_lines) => lines.extend(elem_lines.elem_lines.iter().map(|line| line.inline_elems.iter()
+                    .flat_map(|inline_elem| match &inline_elem.inline_content {
+                        InlineContent::Text(glyphs) | InlineContent::Link((glyphs, _)) => glyphs.as_slice(),
+                        _ => &[],
+                    })
+                    .map(|glyph| cache.key(glyph.char).0)
+                    .collect())),
+            }
+        }
+        let html            = format!(r#"<html xmlns="http://www.w3.org/1999/xhtml"><body>{body}</body></html>"#);
+        let document        = Document::parse(&html).unwrap();
+        let style_sheets    = vec![StyleSheet::parse(css, ParserOptions::default()).unwrap()];
+        let font            = Attrs::new().font_size(20.);
+        let mut factory     = BookElemFactory::new(GlyphCache::new(), HashMap::new(), HashMap::new(), &font, Color::BLACK);
+        let page            = factory.parse_root(document.root_element(), font, "page.xhtml".to_string(), &style_sheets, &document, None);
+        let mut lines       = Vec::new();
+        collect(&page.root, &factory.cache, &mut lines);
+        lines
+    }
+
+    #[test]
+    fn whitespace_runs_alternate() {
+        assert_eq!(whitespace_runs("  a\t\nbc d\u{a0}e "), vec![("  ", true), ("a", false), ("\t\n", true), ("bc", false), (" ", true), ("d\u{a0}e", false), (" ", true)]);
+        assert!(whitespace_runs("").is_empty());
+    }
+
+    #[test]
+    fn tabs_expand_to_the_next_stop() {
+        assert_eq!(expand_tabs("no tabs"), "no tabs");
+        assert_eq!(expand_tabs("\tx"), " ".repeat(8) + "x");
+        assert_eq!(expand_tabs("abc\tx\ty"), format!("abc{}x{}y", " ".repeat(5), " ".repeat(7)));
+    }
+
+    #[test]
+    fn normal_whitespace_collapses_across_elements() {
+        assert_eq!(laid_out_lines("<p>  a \n\t b  <span> c</span> </p>", ""), vec!["a b c"]);
+        assert_eq!(laid_out_lines("<p>a<span> </span> <em> b</em></p>", ""), vec!["a b"]);
+    }
+
+    #[test]
+    fn break_drops_the_spaces_around_it() {
+        assert_eq!(laid_out_lines("<p>a <br/> b</p>", ""), vec!["a", "b"]);
+        assert_eq!(laid_out_lines("<p>a<br/><br/>b</p>", ""), vec!["a", "", "b"]);
+    }
+
+    #[test]
+    fn pre_keeps_spaces_tabs_and_newlines() {
+        assert_eq!(laid_out_lines("<p>a  b\n\tc</p>", "p { white-space: pre }"), vec!["a  b", "        c"]);
+        assert_eq!(laid_out_lines("<p>a  b\n c</p>", "p { white-space: pre-wrap }"), vec!["a  b", " c"]);
+    }
+
+    #[test]
+    fn pre_line_collapses_spaces_but_keeps_newlines() {
+        assert_eq!(laid_out_lines("<p>a   b \n  c</p>", "p { white-space: pre-line }"), vec!["a b", "c"]);
+    }
+
+    #[test]
+    fn capitalize_takes_every_word_of_a_segment() {
+        assert_eq!(transform_text("one (two)\tthree 4th", TextTransformCase::Capitalize), "One (Two)\tThree 4th");
+        assert!(matches!(transform_text("Already Up", TextTransformCase::Capitalize), Cow::Borrowed(_)));
+        assert_eq!(laid_out_lines("<p>a  quiet\nnight</p>", "p { white-space: pre; text-transform: capitalize }"), vec!["A  Quiet", "Night"]);
+    }
+}
@@ -24,10 +24,12 @@ use rustc_data_structures::fx::FxHashMap;
 use sha2::{Digest, Sha256};
 use threadpool::ThreadPool;
 
-use crate::book_elem::{BookElemFactory, CharGlyph, Elem, get_size, HTMLPage, ImageElem, ImagePromise, InlineContent, InlineElem, MemUsage, ParseState};
+use crate::book_elem::{BookElemFactory, CharGlyph, Elem, get_size, HTMLPage, ImageElem, ImagePromise, InlineContent, InlineElem, MemUsage, ParseState, Spread, SpreadMode, SpreadSide};
 use crate::glyph_interner::GlyphCache;
 
 use crate::IO::epub::{remove_dtd};
+use crate::IO::fonts::load_embedded_fonts;
+use crate::IO::svg::load_svg;
 use crate::IO::library::{read_book_position, update_book_path, update_last_read, write_book_position};
 use crate::library::{Page, Signals};
 use crate::renderer::html_renderer::HtmlRenderer;
@@ -44,6 +46,8 @@ pub fn create_epub_reader(path: &str, library_path: &str, prev_page: Page, signa
 
     let sections: Vec<String> = epub.spine().elements().iter()
         .map(|elem| epub.manifest().by_id(elem.name()).unwrap().value().to_string()).collect();
+    let renditions = read_renditions(&epub);
+    let right_to_left = epub.spine().get_attribute("page-progression-direction").is_some_and(|direction| direction == "rtl");
     let html_text: Vec<String> = epub.reader().iter()
         .map(|cont| {
             let text = cont.unwrap().to_string();
@@ -53,20 +57,20 @@ pub fn create_epub_reader(path: &str, library_path: &str, prev_page: Page, signa
         }).collect();
 
     let now = Instant::now();
-    let image_map = process_images(&epub);
+    let (image_map, raster_images) = process_images(&epub);
 
     println!("Elapsed image processing time: {}", now.elapsed().as_millis());
     //let image_map: HashMap<String, ImageElem> = HashMap::new();
 
     let font_family = "Liberation Serif".to_string();
     let f = &[FamilyOwned::Name(font_family)];
+    let text_color = Color::rgb8(43, 43, 43);
     let base_font = Attrs::new()
         .font_size(20.)
         .family(f)
         .line_height(LineHeightValue::Normal(1.5))
-        .color(Color::rgb8(43, 43, 43))
+        .color(text_color)
         ;
-    let cache = GlyphCache::new();
 
 
 
@@ -87,23 +91,28 @@ pub fn create_epub_reader(path: &str, library_path: &str, prev_page: Page, signa
     let html_contents: Vec<(String, bool)> = html_text.iter()
         .map(|section| (section.clone(), is_html_content(section)))
         .collect();
-    let css_strings: Vec<String> = epub.manifest().all_by_media_type("text/css").iter()
-        .map(|css_name| epub.read_file(css_name.value()).unwrap())
+    let css_paths: Vec<String> = epub.manifest().all_by_media_type("text/css").iter()
+        .map(|css_name| css_name.value().to_string())
+        .collect();
+    let css_strings: Vec<String> = css_paths.iter()
+        .map(|css_path| epub.read_file(css_path).unwrap())
         .collect();
     let style_sheets: Vec<StyleSheet> = css_strings.iter()
         .map(|css_string| StyleSheet::parse(css_string, ParserOptions::default()).unwrap())
         .collect();
+    let embedded_fonts = load_embedded_fonts(&epub, &css_paths, &style_sheets);
+    let cache = GlyphCache::with_fonts(embedded_fonts);
     //let style_sheets = Vec::new();
     let now = Instant::now();
-    let mut book_factory = BookElemFactory::new(cache, image_map, &base_font);
-    let elems: Vec<HTMLPage> = documents.iter().zip(&sections).zip(&html_contents)
-        .map(|((document, section), (html_content, is_html))| {
+    let mut book_factory = BookElemFactory::new(cache, image_map, raster_images, &base_font, text_color);
+    let elems: Vec<HTMLPage> = documents.iter().zip(&sections).zip(&html_contents).zip(&renditions)
+        .map(|(((document, section), (html_content, is_html)), rendition)| {
             if false {
                 // Use scraper-based HTML parsing for HTML5 content
                 book_factory.parse_root_html(html_content, base_font, section.clone(), &style_sheets)
             } else {
                 // Use roxmltree for XHTML/XML content
-                book_factory.parse_root(document.root_element(), base_font, section.clone(), &style_sheets, document)
+                book_factory.parse_root(document.root_element(), base_font, section.clone(), &style_sheets, document, *rendition)
             }
         })
         .collect();
@@ -139,7 +148,8 @@ pub fn create_epub_reader(path: &str, library_path: &str, prev_page: Page, signa
     println!("Size: {}", mem_usage.char_size / 1_000);
     println!("Inline Size: {}", mem_usage.inline_size / 1_000);
     println!("Image Size: {}", mem_usage.img_size / 1_000);
-    let mut html_renderer = HtmlRenderer::new(start_index_signal, book_factory.cache, pages, current_url, set_at_end, get_go_on);
+    let scroll_mode = create_rw_signal(false);
+    let mut html_renderer = HtmlRenderer::new(start_index_signal, book_factory.cache, pages, sections.clone(), right_to_left, current_url, set_at_end, get_go_on, scroll_mode);
     html_renderer = html_renderer.style(|style| style.flex_grow(1.0).margin(40).width_full().background(Color::WHITE));
 
 
@@ -176,12 +186,17 @@ pub fn create_epub_reader(path: &str, library_path: &str, prev_page: Page, signa
              show_sidebar.update(|v| *v = !*v);
             EventPropagation::Continue
         });
+    let scroll_button = button(label(move || if scroll_mode.get() { "Pages" } else { "Scroll" }))
+        .on_click(move |_| {
+            scroll_mode.update(|scroll| *scroll = !*scroll);
+            EventPropagation::Continue
+        });
     let back_button = button(label(move || { "Back" }))
         .on_click(move |_| {
             signals.active_page.set(prev_page);
             EventPropagation::Continue
         });
-    let top_panel = h_stack((back_button, toggle_button)).style(move |s| s.border_bottom(1).flex_shrink(0.).flex_grow(0.));
+    let top_panel = h_stack((back_button, toggle_button, scroll_button)).style(move |s| s.border_bottom(1).flex_shrink(0.).flex_grow(0.));
     let toc = create_toc(epub.toc().elements());
     //let toc_view = v_stack((toc_view(toc, toc_on_click, 0),)).scroll()
        //     .style(|s| s.border_right(1).width(321).height_full());
@@ -255,20 +270,65 @@ pub fn create_epub_reader(path: &str, library_path: &str, prev_page: Page, signa
     stack
 }
 
+// Spread placement of each pre-paginated section in spine order, None for those that reflow.
+// Spine properties override the book's `rendition:layout` and `rendition:spread` for their section.
+fn read_renditions(epub: &Epub) -> Vec<Option<Spread>> {
+    let book_value  = |name: &str| epub.metadata().get(name).first().map(|meta| meta.value().trim().to_string());
+    let fixed       = book_value("rendition:layout").is_some_and(|layout| layout == "pre-paginated");
+    let mode        = book_value("rendition:spread").and_then(|spread| spread_mode(&spread)).unwrap_or_default();
+    epub.spine().elements().iter()
+        .map(|itemref| {
+            let properties: Vec<&str> = itemref.get_attribute("properties").unwrap_or_default().split_whitespace()
+                .map(|property| property.trim_start_matches("rendition:"))
+                .collect();
+            let fixed = match (properties.contains(&"layout-pre-paginated"), properties.contains(&"layout-reflowable")) {
+                (true, _)       => true,
+                (_, true)       => false,
+                _               => fixed,
+            };
+            if !fixed { return None }
+            let mode = properties.iter().find_map(|property| property.strip_prefix("spread-").and_then(spread_mode)).unwrap_or(mode);
+            let side = match () {
+                _ if properties.contains(&"page-spread-left")   => SpreadSide::Left,
+                _ if properties.contains(&"page-spread-right")  => SpreadSide::Right,
+                _ => SpreadSide::Auto,
+            };
+            // A centred page is shown on its own
+            let mode = if properties.contains(&"page-spread-center") { SpreadMode::None } else { mode };
+            Some(Spread { side, mode })
+        })
+        .collect()
+}
+
+// `portrait` is deprecated and read as `both`
+fn spread_mode(value: &str) -> Option<SpreadMode> {
+    match value {
+        "none"                  => Some(SpreadMode::None),
+        "landscape"             => Some(SpreadMode::Landscape),
+        "both" | "portrait"     => Some(SpreadMode::Both),
+        "auto"                  => Some(SpreadMode::Auto),
+        _ => None,
+    }
+}
+
 fn create_toc(elems: Vec<&rbook::xml::Element>) -> Vec<TocEntry> {
     elems.iter().map(|elem| TocEntry {title: elem.name().to_string(), link: elem.value().to_string(), children: create_toc(elem.children())}).collect()
 }
 
 
-fn process_images(epub: &Epub) -> HashMap<String, ImageElem> {
+// The encoded raster images are returned too, for SVGs that embed them
+fn process_images(epub: &Epub) -> (HashMap<String, ImageElem>, HashMap<String, Arc<Vec<u8>>>) {
     let mut image_map: HashMap<String, ImageElem> = HashMap::new();
     let pool = ThreadPool::new(8);
     let image_types = ["jpeg", "jpg", "png", "gif", "webp"];
+    let mut raster_bytes: HashMap<String, Arc<Vec<u8>>> = HashMap::new();
+    let mut svg_paths = Vec::new();
 
     for elem in epub.manifest().elements() {
         
         let image_path      = elem.value();
         let file_extension  = image_path.split(".").skip(1).next().unwrap();
+        if file_extension == "svg" { svg_paths.push(image_path); }
         if !image_types.contains(&file_extension) { continue; }
         let image_type = match file_extension {
             "jpeg"  => ImageFormat::Jpeg,
@@ -279,17 +339,18 @@ fn process_images(epub: &Epub) -> HashMap<String, ImageElem> {
             _       => continue
         };
 
-        let image_bytes = epub.read_bytes_file(image_path).unwrap();
-        let image_size  = ImageReader::with_format(Cursor::new(&image_bytes), image_type).into_dimensions().unwrap();
+        let image_bytes = Arc::new(epub.read_bytes_file(image_path).unwrap());
+        raster_bytes.insert(image_path.to_string(), image_bytes.clone());
+        let image_size  = ImageReader::with_format(Cursor::new(image_bytes.as_slice()), image_type).into_dimensions().unwrap();
         let width       = image_size.0 as u16;
         let height      = image_size.1 as u16;
 
         let image_promise: ImagePromise = Arc::new(RwLock::new(None));
-        let image = ImageElem { width, height, image_promise: image_promise.clone() };
+        let image = ImageElem { width, height, image_promise: image_promise.clone(), svg: None };
         image_map.insert(image_path.to_string(), image);
         pool.execute(move || {
             
-            let data = Arc::new(ImageReader::with_format(Cursor::new(image_bytes), image_type).decode().unwrap().to_rgba8().into_raw());
+            let data = Arc::new(ImageReader::with_format(Cursor::new(image_bytes.as_slice()), image_type).decode().unwrap().to_rgba8().into_raw());
             let mut hasher  = Sha256::new();
             let blob        = Blob::new(data.clone());
             hasher.update(&blob);
@@ -298,5 +359,12 @@ fn process_images(epub: &Epub) -> HashMap<String, ImageElem> {
             *image_promise.write().unwrap() = Some((image.clone(), hash));
         });
     }
-    image_map
+    for svg_path in svg_paths {
+        let Ok(svg_bytes) = epub.read_bytes_file(svg_path) else { continue };
+        let Ok(svg) = load_svg(&svg_bytes, svg_path, &raster_bytes) else { continue };
+        let size = svg.tree.size();
+        let image = ImageElem { width: size.width().round() as u16, height: size.height().round() as u16, image_promise: Arc::new(RwLock::new(None)), svg: Some(Arc::new(svg)) };
+        image_map.insert(svg_path.to_string(), image);
+    }
+    (image_map, raster_bytes)
 }
@@ -1,41 +1,123 @@
-use floem_renderer::text::{Attrs, AttrsList, Style, TextLayout, Weight};
+use std::rc::Rc;
+use floem::peniko::Color;
+use floem_renderer::text::{fontdb, Attrs, AttrsList, FamilyOwned, Style, TextLayout, Weight, FONT_SYSTEM};
 use rustc_data_structures::fx::FxHashMap;
 use crate::book_elem::ParseState;
+use crate::IO::fonts::EmbeddedFonts;
+
+// (char, font size, weight, style, resolved family, color)
+pub type GlyphKey = (char, u8, u16, Style, u16, Option<Color>);
 
 pub struct GlyphCache {
-    table: FxHashMap<(char, u8, u16, floem_renderer::text::Style), u16>,
-    reverse: Vec<TextLayout>
+    table: FxHashMap<GlyphKey, u16>,
+    reverse: Vec<TextLayout>,
+    keys: Vec<GlyphKey>,
+    fonts: EmbeddedFonts,
+    // Family index 0 is the base font passed in through Attrs
+    families: Vec<FamilyOwned>,
+    chains: FxHashMap<Rc<[FamilyOwned]>, u16>,
+    resolved: FxHashMap<(u16, char, u16, Style), u16>,
+    last_chain: Option<(Rc<[FamilyOwned]>, u16)>,
 }
 
 impl GlyphCache {
     pub fn new() -> Self {
-        Self {table: FxHashMap::default(), reverse: Vec::with_capacity(100)}
+        Self::with_fonts(EmbeddedFonts::default())
+    }
+    pub fn with_fonts(fonts: EmbeddedFonts) -> Self {
+        Self {
+            table: FxHashMap::default(), reverse: Vec::with_capacity(100), keys: Vec::with_capacity(100), fonts,
+            families: vec![FamilyOwned::Name(String::new())], chains: FxHashMap::default(),
+            resolved: FxHashMap::default(), last_chain: None,
+        }
     }
     pub fn get_or_insert(&mut self, char: char, mut font: Attrs, parse_state: &ParseState) -> (&TextLayout, u16) {
         let font_size = font.font_size as u8;
+        let family = self.resolve_family(char, parse_state);
         font = font.raw_weight(parse_state.font_weight);
         font = font.style(parse_state.text_style);
-        
-        
-        let index = self.table.entry((char, font_size, parse_state.font_weight, parse_state.text_style)).or_insert_with(|| {
-            let index = self.reverse.len() as u16;
-            let mut layout = TextLayout::new();
-            let mut buf = [0u8; 4];
-            let s = char.encode_utf8(&mut buf);
-            layout.set_text(s, AttrsList::new(font));
-            self.reverse.push(layout);
-            index
-        });
-        (self.reverse.get(*index as usize).unwrap(), *index)
+
+        if let Some(color) = parse_state.color { font = font.color(color) }
+
+        let key = (char, font_size, parse_state.font_weight, parse_state.text_style, family, parse_state.color);
+        if let Some(index) = self.table.get(&key) {
+            return (self.reverse.get(*index as usize).unwrap(), *index)
+        }
+        let index = self.reverse.len() as u16;
+        let family_slice = [self.families[family as usize].clone()];
+        let attrs = if family == 0 { font } else { font.family(&family_slice) };
+        let mut layout = TextLayout::new();
+        let mut buf = [0u8; 4];
+        let s = char.encode_utf8(&mut buf);
+        layout.set_text(s, AttrsList::new(attrs));
+        self.reverse.push(layout);
+        self.keys.push(key);
+        self.table.insert(key, index);
+        (self.reverse.get(index as usize).unwrap(), index)
     }
     pub fn get(&self, index: u16) -> &TextLayout{
         self.reverse.get(index as usize).unwrap()
     }
 
+    /// What a glyph was shaped from, for restyling text that is already laid out
+    pub fn key(&self, index: u16) -> GlyphKey {
+        self.keys[index as usize]
+    }
+
+    // Picks the first family in the CSS font-family chain that is loaded and covers the char
+    fn resolve_family(&mut self, char: char, parse_state: &ParseState) -> u16 {
+        let Some(chain) = &parse_state.font_family else { return 0 };
+        let chain_id = match &self.last_chain {
+            Some((last, id)) if Rc::ptr_eq(last, chain) => *id,
+            _ => {
+                let next_id = self.chains.len() as u16;
+                let id = *self.chains.entry(chain.clone()).or_insert(next_id);
+                self.last_chain = Some((chain.clone(), id));
+                id
+            }
+        };
+        let resolved_key = (chain_id, char, parse_state.font_weight, parse_state.text_style);
+        if let Some(family) = self.resolved.get(&resolved_key) {
+            return *family
+        }
+
+        let mut family_index = 0;
+        let mut font_system = FONT_SYSTEM.lock();
+        for family in chain.iter() {
+            let candidate = match family {
+                FamilyOwned::Name(name) => match self.fonts.family_name(name) {
+                    Some(embedded) => FamilyOwned::Name(embedded.to_string()),
+                    None => family.clone(),
+                },
+                generic => generic.clone(),
+            };
+            let query = fontdb::Query {
+                families: &[candidate.as_family()],
+                weight: Weight(parse_state.font_weight),
+                stretch: Default::default(),
+                style: parse_state.text_style,
+            };
+            let Some(id) = font_system.db().query(&query) else { continue };
+            let covers = font_system.get_font(id).map_or(false, |font| font.rustybuzz().glyph_index(char).is_some());
+            if !covers { continue }
+            family_index = match self.families.iter().position(|f| *f == candidate) {
+                Some(index) => index as u16,
+                None => {
+                    self.families.push(candidate);
+                    (self.families.len() - 1) as u16
+                }
+            };
+            break;
+        }
+        drop(font_system);
+        self.resolved.insert(resolved_key, family_index);
+        family_index
+    }
+
     pub fn total_memory_usage(&self) -> usize {
-        let entry_size = size_of::<((char, u8, u16, Style), u16)>();
+        let entry_size = size_of::<(GlyphKey, u16)>();
 
-        let table_overhead = size_of::<FxHashMap<(char, u8, u16, Style), u16>>();
+        let table_overhead = size_of::<FxHashMap<GlyphKey, u16>>();
         let table_data = self.table.len() * entry_size;
 
         let reverse_overhead = size_of::<Vec<TextLayout>>();
@@ -44,4 +126,12 @@ impl GlyphCache {
         table_overhead + table_data + reverse_overhead + reverse_data
         //self.reverse.len()
     }
-}
\ No newline at end of file
+}
+
+/// Ascent and descent of a shaped glyph's line, the renderer draws it with the baseline their sum below the origin
+pub fn glyph_extents(text_layout: &TextLayout) -> (f64, f64) {
+    match text_layout.lines().first().and_then(|line| line.layout_opt().as_ref()).and_then(|layout| layout.first()) {
+        Some(line)  => (line.max_ascent as f64, line.max_descent as f64),
+        None        => (text_layout.size().height, 0.),
+    }
+}
@@ -1,20 +1,25 @@
 use floem::kurbo::{Point, Size};
 use lightningcss::properties::text::TextAlign;
-use crate::book_elem::{BookElemFactory, Elem, ElemLine, ElemLines, ElemType, InlineContent, InlineElem, InlineItem, ParseState};
+use crate::book_elem::{BookElemFactory, Clear, Elem, FloatSide, ElemLine, ElemLines, ElemType, InlineContent, InlineElem, InlineItem, ParseState, VerticalAlign};
+
+/// Fraction of a line's height that lies above its baseline
+pub const BASELINE_RATIO: f64 = 1. / 1.6;
 
 pub fn add_line(parser: &mut BookElemFactory, mut curr_line: ElemLine, mut elem_lines: ElemLines, parse_state: &ParseState) -> ElemLines{
-    let line_width = parser.curr_x - parse_state.x;
+    let (line_start, line_end) = line_bounds(parser, parse_state, parser.curr_y);
+    let line_width  = parser.curr_x - line_start;
+    let free_width  = line_end - parser.curr_x + parse_state.x;
     match parse_state.text_align {
         TextAlign::Start | TextAlign::Left => {}
         TextAlign::Right | TextAlign::End => {
-            let offset = parse_state.width - line_width;
+            let offset = free_width;
             for inline_elem in &mut curr_line.inline_elems {
                 inline_elem.x += offset;
             }
         }
         TextAlign::Center => {
 
-            let offset = (parse_state.width - line_width) / 2.0;
+            let offset = free_width / 2.0;
             for inline_elem in &mut curr_line.inline_elems {
                 inline_elem.x += offset;
             }
@@ -22,8 +27,8 @@ pub fn add_line(parser: &mut BookElemFactory, mut curr_line: ElemLine, mut elem_
         TextAlign::Justify => {
 
             let count = curr_line.inline_elems.len();
-            if count > 1 && line_width / parse_state.width > 0.80 {
-                let extra_space = parse_state.width - line_width;
+            if count > 1 && line_width / (line_width + free_width) > 0.80 {
+                let extra_space = free_width;
                 let gap_count = count - 1;
                 let gap = extra_space / gap_count as f64;
                 for (i, inline_elem) in curr_line.inline_elems.iter_mut().enumerate() {
@@ -34,7 +39,7 @@ pub fn add_line(parser: &mut BookElemFactory, mut curr_line: ElemLine, mut elem_
                                 glyph.x = glyph.x// Add scaling here
                             }
                         }
-                        InlineContent::Image(_) => {}
+                        InlineContent::Image(_) | InlineContent::Math(_) | InlineContent::Ruby(_) | InlineContent::Break | InlineContent::Float(_) => {}
                         InlineContent::Link(_) => {}
                     }
                 }
@@ -43,20 +48,88 @@ pub fn add_line(parser: &mut BookElemFactory, mut curr_line: ElemLine, mut elem_
         TextAlign::MatchParent => {}
         TextAlign::JustifyAll => {}
     }
-    parser.curr_x         = parse_state.x;
     parser.curr_y         += curr_line.height;
     elem_lines.height   += curr_line.height;
     elem_lines.elem_lines.push(curr_line);
+    // Floats the line had no room for start beside the next one
+    for id in std::mem::take(&mut parser.deferred_floats) {
+        parser.place_float(id, elem_lines.elem_lines.len(), parse_state);
+    }
+    parser.curr_x         = line_bounds(parser, parse_state, parser.curr_y).0;
     elem_lines
 }
 
+/// Where a line at `y` starts and how far it may run, in `curr_x` terms, narrowed by the floats beside it
+pub fn line_bounds(parser: &BookElemFactory, parse_state: &ParseState, y: f64) -> (f64, f64) {
+    let mut bounds = (parse_state.x, parse_state.width);
+    // Painted x is the elem's x plus the line's own, both starting from parse_state.x
+    for float in parser.floats.iter().filter(|float| float.top <= y && y < float.bottom) {
+        match float.side {
+            FloatSide::Left     => bounds.0 = bounds.0.max(float.right - parse_state.x),
+            FloatSide::Right    => bounds.1 = bounds.1.min(float.left - parse_state.x),
+        }
+    }
+    bounds
+}
+
+/// The nearest bottom of a float beside `y`, where a line that does not fit next to the floats can move to
+pub fn next_float_bottom(parser: &BookElemFactory, y: f64) -> Option<f64> {
+    parser.floats.iter()
+        .filter(|float| float.top <= y && y < float.bottom)
+        .map(|float| float.bottom)
+        .min_by(f64::total_cmp)
+}
+
+/// The lowest bottom of the floats a `clear` moves below
+pub fn clearance(parser: &BookElemFactory, clear: Clear) -> Option<f64> {
+    parser.floats.iter()
+        .filter(|float| match clear {
+            Clear::None     => false,
+            Clear::Left     => float.side == FloatSide::Left,
+            Clear::Right    => float.side == FloatSide::Right,
+            Clear::Both     => true,
+        })
+        .map(|float| float.bottom)
+        .max_by(f64::total_cmp)
+}
+
 pub fn layout_elem_lines(parser: &mut BookElemFactory, mut inline_items: Vec<InlineItem>, parse_state: &ParseState) -> Elem{
-    let init_point      = Point::new(parser.curr_x, parser.curr_y);
+    let init_point      = Point::new(parse_state.x, parser.curr_y);
     let mut elem_lines  = ElemLines {height: 0., elem_lines: Vec::new()};
     let mut curr_line   = ElemLine  {height: 0., inline_elems: Vec::new()};
     let mut width =     0.;
+    let mut line_end;
+    (parser.curr_x, line_end) = line_bounds(parser, parse_state, parser.curr_y);
+    // Negative for hanging paragraphs, the first line then starts left of the others
+    parser.curr_x += parser.text_indent.take().unwrap_or_default();
     for mut inline_item in inline_items {
+        if let InlineContent::Float(id) = inline_item.inline_content {
+            // Placed beside the line its anchor is on, the line's content moves over when the float takes its start
+            if curr_line.inline_elems.is_empty() || parser.curr_x + parser.float_width(id) <= line_end {
+                let line_start  = line_bounds(parser, parse_state, parser.curr_y).0;
+                parser.place_float(id, elem_lines.elem_lines.len(), parse_state);
+                let bounds      = line_bounds(parser, parse_state, parser.curr_y);
+                for inline_elem in &mut curr_line.inline_elems {
+                    inline_elem.x += bounds.0 - line_start;
+                }
+                parser.curr_x   += bounds.0 - line_start;
+                line_end        = bounds.1;
+            } else {
+                parser.deferred_floats.push(id);
+            }
+            continue
+        }
+        if let InlineContent::Break = inline_item.inline_content {
+            trim_trailing_space(parser, &mut curr_line);
+            curr_line.height    = f64::max(curr_line.height, inline_item.size.height);
+            elem_lines          = add_line(parser, curr_line, elem_lines, &parse_state);
+            curr_line           = ElemLine {height: 0., inline_elems: Vec::new()};
+            line_end            = line_bounds(parser, parse_state, parser.curr_y).1;
+            continue
+        }
         if inline_item.size.width > parse_state.x + parse_state.width {
+            parser.wrapped      = true;
+            trim_trailing_space(parser, &mut curr_line);
             elem_lines          = add_line(parser, curr_line, elem_lines, &parse_state);
 
             if let InlineContent::Image(image) = &mut inline_item.inline_content {
@@ -68,22 +141,115 @@ pub fn layout_elem_lines(parser: &mut BookElemFactory, mut inline_items: Vec<Inl
                 parser.curr_x = parse_state.x + parse_state.width;
             }
 
-            let mut new_line    = ElemLine {height: inline_item.size.height, inline_elems: Vec::new()};
-            let inline_elem     = InlineElem {x: 0., inline_content: inline_item.inline_content};
+            let mut new_line    = ElemLine {height: required_height(&inline_item), inline_elems: Vec::new()};
+            let inline_elem     = InlineElem {x: 0., inline_content: inline_item.inline_content, style: inline_item.style};
             new_line.inline_elems.push(inline_elem);
             elem_lines          = add_line(parser, new_line, elem_lines, &parse_state);
             curr_line           = ElemLine {height: 0., inline_elems: Vec::new()};
+            line_end            = line_bounds(parser, parse_state, parser.curr_y).1;
             continue
         }
-        else if parser.curr_x + inline_item.size.width > parse_state.width {
+        else if parser.curr_x + inline_item.size.width > line_end {
+            // Whitespace at a soft wrap is dropped rather than starting the next line
+            if is_space(parser, &inline_item.inline_content) { continue }
+            parser.wrapped      = true;
+            trim_trailing_space(parser, &mut curr_line);
             elem_lines          = add_line(parser, curr_line, elem_lines, &parse_state);
             curr_line           = ElemLine {height: 0., inline_elems: Vec::new()};
+            line_end            = line_bounds(parser, parse_state, parser.curr_y).1;
+            // Nothing fits beside the floats, the line moves down past them one at a time
+            while parser.curr_x + inline_item.size.width > line_end {
+                let Some(bottom) = next_float_bottom(parser, parser.curr_y) else { break };
+                let gap         = ElemLine {height: bottom - parser.curr_y, inline_elems: Vec::new()};
+                elem_lines      = add_line(parser, gap, elem_lines, &parse_state);
+                line_end        = line_bounds(parser, parse_state, parser.curr_y).1;
+            }
         }
-        curr_line.height    = f64::max(curr_line.height, inline_item.size.height);
-        let inline_elem     = InlineElem {x: parser.curr_x, inline_content: inline_item.inline_content};
+        curr_line.height    = f64::max(curr_line.height, required_height(&inline_item));
+        let inline_elem     = InlineElem {x: parser.curr_x, inline_content: inline_item.inline_content, style: inline_item.style};
         parser.curr_x         += inline_item.size.width;
         curr_line.inline_elems.push(inline_elem);
     }
+    trim_trailing_space(parser, &mut curr_line);
     elem_lines = add_line(parser, curr_line, elem_lines, &parse_state);
-    Elem {size: Size::new(parse_state.width, elem_lines.height), point: init_point, elem_type: ElemType::Lines(elem_lines)}
-}
\ No newline at end of file
+    Elem {size: Size::new(parse_state.width, elem_lines.height), point: init_point, elem_type: ElemType::Lines(elem_lines), decoration: None}
+}
+
+fn is_space(parser: &BookElemFactory, inline_content: &InlineContent) -> bool {
+    match inline_content {
+        InlineContent::Text(glyphs) | InlineContent::Link((glyphs, _)) => !glyphs.is_empty() && glyphs.iter().all(|glyph| parser.cache.key(glyph.char).0 == ' '),
+        InlineContent::Image(_) | InlineContent::Math(_) | InlineContent::Ruby(_) | InlineContent::Break | InlineContent::Float(_) => false,
+    }
+}
+
+// Spaces ending a line take no room in it, so alignment only sees the text
+fn trim_trailing_space(parser: &mut BookElemFactory, curr_line: &mut ElemLine) {
+    while curr_line.inline_elems.last().is_some_and(|inline_elem| is_space(parser, &inline_elem.inline_content)) {
+        parser.curr_x = curr_line.inline_elems.pop().unwrap().x;
+    }
+}
+
+/// Extent of a shifted item above and below the baseline, `None` when it is aligned to the line box instead
+pub fn baseline_extents(height: f64, is_image: bool, vertical_align: VerticalAlign) -> Option<(f64, f64)> {
+    let shift = match vertical_align {
+        VerticalAlign::Shift(shift) => shift as f64,
+        // Centred on the baseline, text is already close to centred
+        VerticalAlign::Middle if is_image => return Some((height / 2., height / 2.)),
+        VerticalAlign::Middle => 0.,
+        VerticalAlign::Baseline | VerticalAlign::Top | VerticalAlign::Bottom => return None,
+    };
+    match is_image {
+        true    => Some((height + shift, -shift)),
+        false   => Some((height * BASELINE_RATIO + shift, height * (1. - BASELINE_RATIO) - shift)),
+    }
+}
+
+// The smallest line height that keeps a shifted item inside the line
+fn required_height(inline_item: &InlineItem) -> f64 {
+    let height          = inline_item.size.height;
+    let vertical_align  = inline_item.style.as_ref().map(|style| style.vertical_align).unwrap_or_default();
+    // Formulas sit on the baseline by their depth, the way a shifted image does
+    let is_image        = matches!(inline_item.inline_content, InlineContent::Image(_) | InlineContent::Math(_));
+    let required        = match baseline_extents(height, is_image, vertical_align) {
+        Some((above, below)) => height.max(above / BASELINE_RATIO).max(below / (1. - BASELINE_RATIO)),
+        None => height,
+    };
+    // Annotations stand above the text, the line grows until they fit above its baseline
+    match &inline_item.inline_content {
+        InlineContent::Ruby(ruby) => required.max(ruby.above as f64 / BASELINE_RATIO),
+        _ => required,
+    }
+}
+
+/// Splits laid out lines around the floats placed beside them, each float coming after the lines above its own.
+/// Lines left with neither height nor content, as when a paragraph holds only a float, are dropped
+pub fn split_at_floats(elem: Elem, floats: Vec<(usize, Elem)>) -> Vec<Elem> {
+    if floats.is_empty() { return vec![elem] }
+    let ElemType::Lines(elem_lines) = elem.elem_type else { return vec![elem] };
+    let mut parts   = Vec::new();
+    let mut lines   = elem_lines.elem_lines.into_iter();
+    let mut taken   = 0;
+    let mut y       = elem.point.y;
+    let mut push_part = |parts: &mut Vec<Elem>, part: Vec<ElemLine>| {
+        let height = part.iter().map(|line| line.height).sum::<f64>();
+        if height == 0. && part.iter().all(|line| line.inline_elems.is_empty()) { return }
+        parts.push(Elem { size: Size::new(elem.size.width, height), point: Point::new(elem.point.x, y), elem_type: ElemType::Lines(ElemLines { height, elem_lines: part }), decoration: None });
+        y += height;
+    };
+    for (line, float) in floats {
+        let part = lines.by_ref().take(line.saturating_sub(taken)).collect();
+        taken = taken.max(line);
+        push_part(&mut parts, part);
+        parts.push(float);
+    }
+    push_part(&mut parts, lines.collect());
+    parts
+}
+
+/// Hangs an outside list marker in the margin to the left of the first line
+pub fn hang_marker(elem: &mut Elem, marker: InlineItem, x: f64) {
+    let ElemType::Lines(elem_lines) = &mut elem.elem_type else { return };
+    let Some(first_line) = elem_lines.elem_lines.first_mut() else { return };
+    let inline_elem = InlineElem {x: x - marker.size.width, inline_content: marker.inline_content, style: marker.style};
+    first_line.inline_elems.insert(0, inline_elem);
+}
@@ -18,6 +18,7 @@ mod parser;
 mod toc;
 mod table_parser;
 mod pre_parser;
+mod math_parser;
 mod renderer;
 mod arena;
 mod book_elem_arena;
@@ -0,0 +1,491 @@
+use std::rc::Rc;
+use floem::kurbo::{Rect, Size, Vec2};
+use floem_renderer::text::{Attrs, Style};
+use lightningcss::stylesheet::StyleSheet;
+use roxmltree::{Document, Node};
+use crate::book_elem::{BookElemFactory, InlineContent, InlineItem, InlineStyle, MathBox, MathGlyph, ParseState, VerticalAlign};
+use crate::glyph_interner::glyph_extents;
+use crate::styling::style::resolve_style_cached;
+
+/// Size of scripts, limits and inline fractions relative to their base
+const SCRIPT_SCALE: f32 = 0.71;
+const MIN_SCRIPT_SIZE: f32 = 8.;
+/// Height of the math axis above the baseline, fraction bars and tables are centred on it
+const AXIS_HEIGHT: f64 = 0.25;
+/// Space around binary operators and relations, in em
+const OPERATOR_SPACE: f64 = 0.22;
+const FENCES: &str = "()[]{}|‖⟨⟩⌈⌉⌊⌋";
+const LARGE_OPERATORS: &str = "∑∏∐⋃⋂⨁⨂⨀∫∬∭∮⋀⋁";
+
+// A formula under construction, with y measured down from its baseline
+#[derive(Default)]
+struct MathLayout {
+    glyphs: Vec<MathGlyph>,
+    rules: Vec<Rect>,
+    width: f64,
+    ascent: f64,
+    descent: f64,
+}
+
+impl MathLayout {
+    // Places `other` with its origin at `x` on the baseline, lowered by `y`
+    fn append(&mut self, other: MathLayout, x: f64, y: f64) {
+        self.glyphs.extend(other.glyphs.into_iter().map(|glyph| MathGlyph { char: glyph.char, x: glyph.x + x as f32, y: glyph.y + y as f32 }));
+        self.rules.extend(other.rules.into_iter().map(|rule| rule + Vec2::new(x, y)));
+        self.width      = self.width.max(x + other.width);
+        self.ascent     = self.ascent.max(other.ascent - y);
+        self.descent    = self.descent.max(other.descent + y);
+    }
+
+    fn add_rule(&mut self, rule: Rect) {
+        self.width      = self.width.max(rule.x1);
+        self.ascent     = self.ascent.max(-rule.y0);
+        self.descent    = self.descent.max(rule.y1);
+        self.rules.push(rule);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.glyphs.is_empty() && self.rules.is_empty()
+    }
+}
+
+#[derive(Clone, Copy)]
+struct MathStyle<'a> {
+    font: Attrs<'a>,
+    /// Display math keeps fractions and limits at full size
+    display: bool,
+}
+
+impl<'a> MathStyle<'a> {
+    fn em(&self) -> f64 {
+        self.font.font_size as f64
+    }
+
+    fn rule_thickness(&self) -> f64 {
+        (self.em() / 18.).max(1.)
+    }
+
+    fn script(&self) -> Self {
+        let size = (self.font.font_size * SCRIPT_SCALE).max(MIN_SCRIPT_SIZE).min(self.font.font_size);
+        MathStyle { font: self.font.font_size(size.round()), display: false }
+    }
+
+    // Fractions in running text are set at script size so the line keeps its height
+    fn fraction(&self) -> Self {
+        match self.display {
+            true    => MathStyle { font: self.font, display: false },
+            false   => self.script(),
+        }
+    }
+
+    fn scaled(&self, scale: f64) -> Self {
+        MathStyle { font: self.font.font_size((self.em() * scale).min(255.).round() as f32), display: self.display }
+    }
+}
+
+impl BookElemFactory {
+    /// Lays out a `<math>` element as one unbreakable item, falling back to its `alttext` when nothing in it can be drawn
+    pub fn parse_math(&mut self, node: Node, style_sheets: &Vec<StyleSheet>, mut font: Attrs, index: &Vec<usize>, mut parse_state: ParseState, document: &Document) -> Vec<InlineItem> {
+        if let Some(id) = node.attribute("id") {
+            self.locations.insert(id.to_string(), index.clone());
+        }
+        parse_state.ancestors.push(node.id());
+        let (_, parse_state) = resolve_style_cached(style_sheets, &node, &mut font, parse_state, document, &mut self.style_cache);
+        let display     = node.attribute("display") == Some("block");
+        let style       = MathStyle { font, display };
+        let layout      = self.layout_math_row(node, style, &parse_state);
+        if layout.is_empty() {
+            return match node.attribute("alttext") {
+                Some(alttext)   => self.parse_text(alttext, font, parse_state, None),
+                None            => Vec::new(),
+            }
+        }
+        // Kept on the baseline of the text around it, lowered by its depth like a shifted image
+        let shift       = match parse_state.inline_style.as_ref().map(|style| style.vertical_align) {
+            Some(VerticalAlign::Shift(shift)) => shift,
+            _ => 0.,
+        };
+        let math_style  = InlineStyle { vertical_align: VerticalAlign::Shift(shift - layout.descent as f32), ..Default::default() };
+        let height      = layout.ascent + layout.descent;
+        let math_box    = MathBox {
+            glyphs: layout.glyphs.into_iter().map(|glyph| MathGlyph { y: glyph.y + layout.ascent as f32, ..glyph }).collect(),
+            rules: layout.rules.into_iter().map(|rule| rule + Vec2::new(0., layout.ascent)).collect(),
+            color: parse_state.color,
+            width: layout.width,
+            height,
+        };
+        let item = InlineItem { size: Size::new(layout.width, height), inline_content: InlineContent::Math(Rc::new(math_box)), style: Some(Rc::new(math_style)) };
+        self.after_space = false;
+        vec![item]
+    }
+
+    fn layout_math(&mut self, node: Node, style: MathStyle, parse_state: &ParseState) -> MathLayout {
+        let children: Vec<Node> = node.children().filter(|child| child.is_element()).collect();
+        match node.tag_name().name() {
+            "mi"    => {
+                let text    = node.text().unwrap_or_default().trim();
+                // Single letter identifiers are variables and set in italic, longer ones are names such as sin
+                let italic  = text.chars().count() == 1 && text.chars().all(char::is_alphabetic);
+                let state   = variant_state(parse_state, node.attribute("mathvariant"), italic);
+                self.layout_math_text(text, style, &state)
+            }
+            "mn" | "mtext" => {
+                let state   = variant_state(parse_state, node.attribute("mathvariant"), false);
+                self.layout_math_text(node.text().unwrap_or_default().trim(), style, &state)
+            }
+            "ms"    => self.layout_math_text(&format!("\"{}\"", node.text().unwrap_or_default().trim()), style, parse_state),
+            "mo"    => self.layout_operator(node, style, parse_state),
+            "mspace" => MathLayout {
+                width: node.attribute("width").and_then(|width| math_length(width, style.em())).unwrap_or_default(),
+                ..Default::default()
+            },
+            "mfrac" => match children[..] {
+                [numerator, denominator, ..] => self.layout_fraction(numerator, denominator, node.attribute("linethickness"), style, parse_state),
+                _ => MathLayout::default(),
+            },
+            "msqrt" => {
+                let radicand = self.layout_math_row(node, style, parse_state);
+                self.layout_radical(radicand, None, style, parse_state)
+            }
+            "mroot" => match children[..] {
+                [radicand, degree, ..] => {
+                    let radicand    = self.layout_math(radicand, style, parse_state);
+                    let degree      = self.layout_math(degree, style.script().script(), parse_state);
+                    self.layout_radical(radicand, Some(degree), style, parse_state)
+                }
+                _ => MathLayout::default(),
+            },
+            "msub" | "msup" | "msubsup" => {
+                let Some(base) = children.first() else { return MathLayout::default() };
+                let (sub, sup) = match (node.tag_name().name(), &children[1..]) {
+                    ("msub", [sub, ..])             => (Some(*sub), None),
+                    ("msup", [sup, ..])             => (None, Some(*sup)),
+                    ("msubsup", [sub, sup, ..])     => (Some(*sub), Some(*sup)),
+                    _ => (None, None),
+                };
+                let base = self.layout_math(*base, style, parse_state);
+                self.layout_scripts(base, sub, sup, style, parse_state)
+            }
+            "munder" | "mover" | "munderover" => {
+                let Some(base_node) = children.first() else { return MathLayout::default() };
+                let (under, over) = match (node.tag_name().name(), &children[1..]) {
+                    ("munder", [under, ..])             => (Some(*under), None),
+                    ("mover", [over, ..])               => (None, Some(*over)),
+                    ("munderover", [under, over, ..])   => (Some(*under), Some(*over)),
+                    _ => (None, None),
+                };
+                // Limits of a sum in running text move to the side, as scripts
+                let large = is_large_operator(*base_node);
+                if large && !style.display {
+                    let base = self.layout_math(*base_node, style, parse_state);
+                    return self.layout_scripts(base, under, over, style, parse_state)
+                }
+                let base_style  = if large { style.scaled(1.4) } else { style };
+                let base        = self.layout_math(*base_node, base_style, parse_state);
+                self.layout_limits(base, under, over, style, parse_state)
+            }
+            "mtable" => self.layout_table(node, style, parse_state),
+            "mfenced" => {
+                let open        = node.attribute("open").unwrap_or("(");
+                let close       = node.attribute("close").unwrap_or(")");
+                let separators: Vec<char> = node.attribute("separators").unwrap_or(",").chars().filter(|ch| !ch.is_whitespace()).collect();
+                let mut parts   = Vec::new();
+                for (i, child) in children.iter().enumerate() {
+                    if i > 0 {
+                        if let Some(separator) = separators.get(i - 1).or(separators.last()) {
+                            parts.push(MathPart::Text(separator.to_string()))
+                        }
+                    }
+                    parts.push(MathPart::Node(*child));
+                }
+                parts.insert(0, MathPart::Fence(open.to_string()));
+                parts.push(MathPart::Fence(close.to_string()));
+                self.layout_parts(parts, style, parse_state)
+            }
+            // The first child is the presentation markup, the annotations are for other processors
+            "semantics" => children.first().map(|child| self.layout_math(*child, style, parse_state)).unwrap_or_default(),
+            "annotation" | "annotation-xml" | "none" | "mprescripts" => MathLayout::default(),
+            "mphantom" => {
+                let layout = self.layout_math_row(node, style, parse_state);
+                MathLayout { width: layout.width, ascent: layout.ascent, descent: layout.descent, ..Default::default() }
+            }
+            // mrow, mstyle, mpadded, merror, menclose and unknown elements lay their children out in a row
+            _ => self.layout_math_row(node, style, parse_state),
+        }
+    }
+
+    fn layout_math_row(&mut self, node: Node, style: MathStyle, parse_state: &ParseState) -> MathLayout {
+        let parts = node.children().filter(|child| child.is_element()).map(|child| match child.has_tag_name("mo") {
+            true if is_fence(child) => MathPart::Fence(child.text().unwrap_or_default().trim().to_string()),
+            _ => MathPart::Node(child),
+        }).collect();
+        self.layout_parts(parts, style, parse_state)
+    }
+
+    // Fences are laid out last, stretched to the height of what they enclose
+    fn layout_parts(&mut self, parts: Vec<MathPart>, style: MathStyle, parse_state: &ParseState) -> MathLayout {
+        let mut laid_out: Vec<Option<MathLayout>> = Vec::with_capacity(parts.len());
+        let (mut ascent, mut descent) = (0f64, 0f64);
+        for part in &parts {
+            let layout = match part {
+                MathPart::Node(node)    => Some(self.layout_math(*node, style, parse_state)),
+                MathPart::Text(text)    => Some(self.layout_math_text(text, style, parse_state)),
+                MathPart::Fence(_)      => None,
+            };
+            if let Some(layout) = &layout {
+                ascent  = ascent.max(layout.ascent);
+                descent = descent.max(layout.descent);
+            }
+            laid_out.push(layout);
+        }
+        let mut row = MathLayout::default();
+        for (part, layout) in parts.iter().zip(laid_out) {
+            match (part, layout) {
+                (_, Some(layout)) => {
+                    let x = row.width;
+                    row.append(layout, x, 0.);
+                }
+                (MathPart::Fence(text), None) => {
+                    let fence   = self.layout_stretched(text, ascent, descent, style, parse_state);
+                    let x       = row.width;
+                    row.append(fence.0, x, fence.1);
+                }
+                _ => ()
+            }
+        }
+        row
+    }
+
+    // A glyph scaled to cover `ascent` above and `descent` below the baseline, with the offset that centres it on them
+    fn layout_stretched(&mut self, text: &str, ascent: f64, descent: f64, style: MathStyle, parse_state: &ParseState) -> (MathLayout, f64) {
+        let normal  = self.layout_math_text(text, style, parse_state);
+        let height  = normal.ascent + normal.descent;
+        if height <= 0. || ascent + descent <= height { return (normal, 0.) }
+        let glyph   = self.layout_math_text(text, style.scaled((ascent + descent) / height), parse_state);
+        let offset  = ((descent - ascent) - (glyph.descent - glyph.ascent)) / 2.;
+        (glyph, offset)
+    }
+
+    fn layout_math_text(&mut self, text: &str, style: MathStyle, parse_state: &ParseState) -> MathLayout {
+        let mut layout = MathLayout::default();
+        for ch in text.chars() {
+            let (text_layout, index) = self.cache.get_or_insert(ch, style.font, parse_state);
+            let (ascent, descent) = glyph_extents(text_layout);
+            // The renderer draws a glyph from its layout origin, ascent and descent above the baseline
+            layout.glyphs.push(MathGlyph { char: index, x: layout.width as f32, y: -(ascent + descent) as f32 });
+            layout.width    += text_layout.size().width;
+            layout.ascent   = layout.ascent.max(ascent);
+            layout.descent  = layout.descent.max(descent);
+        }
+        layout
+    }
+
+    // Binary operators and relations get space on both sides, prefix signs and punctuation do not
+    fn layout_operator(&mut self, node: Node, style: MathStyle, parse_state: &ParseState) -> MathLayout {
+        let text        = node.text().unwrap_or_default().trim();
+        let prefix      = node.prev_sibling_element().is_none() || node.attribute("form") == Some("prefix");
+        let spaced      = match text {
+            "+" | "−" | "-" | "±" | "∓" => !prefix,
+            "=" | "≠" | "<" | ">" | "≤" | "≥" | "≈" | "≡" | "∼" | "≅" | "∝" | "×" | "÷" | "·" | "⋅" | "∘" | "→" | "←" | "↔"
+            | "⇒" | "⇐" | "⇔" | "↦" | "∈" | "∉" | "⊂" | "⊆" | "⊃" | "⊇" | "∪" | "∩" | "∧" | "∨" | "∣" | ":=" => true,
+            _ => false,
+        };
+        let mut layout  = MathLayout::default();
+        let space       = if spaced { style.em() * OPERATOR_SPACE } else { 0. };
+        // Separators only take space after them
+        let after       = if matches!(text, "," | ";") { style.em() * OPERATOR_SPACE / 2. } else { space };
+        let glyph       = self.layout_math_text(text, style, parse_state);
+        layout.append(glyph, space, 0.);
+        layout.width    += after;
+        layout
+    }
+
+    fn layout_fraction(&mut self, numerator: Node, denominator: Node, line_thickness: Option<&str>, style: MathStyle, parse_state: &ParseState) -> MathLayout {
+        let part_style  = style.fraction();
+        let numerator   = self.layout_math(numerator, part_style, parse_state);
+        let denominator = self.layout_math(denominator, part_style, parse_state);
+        let thickness   = match line_thickness {
+            Some("thin")    => style.rule_thickness() / 2.,
+            Some("medium") | None => style.rule_thickness(),
+            Some("thick")   => style.rule_thickness() * 2.,
+            Some(value)     => value.parse::<f64>().map(|factor| factor * style.rule_thickness())
+                .ok().or_else(|| math_length(value, style.em())).unwrap_or(style.rule_thickness()),
+        };
+        let gap         = style.em() * 0.1;
+        let padding     = style.em() * 0.1;
+        let width       = numerator.width.max(denominator.width) + 2. * padding;
+        let axis        = -style.em() * AXIS_HEIGHT;
+        let mut layout  = MathLayout::default();
+        let (numerator_x, numerator_y) = ((width - numerator.width) / 2., axis - thickness / 2. - gap - numerator.descent);
+        let (denominator_x, denominator_y) = ((width - denominator.width) / 2., axis + thickness / 2. + gap + denominator.ascent);
+        layout.append(numerator, numerator_x, numerator_y);
+        layout.append(denominator, denominator_x, denominator_y);
+        if thickness > 0. {
+            layout.add_rule(Rect::new(0., axis - thickness / 2., width, axis + thickness / 2.));
+        }
+        layout
+    }
+
+    // The radical sign is stretched to the radicand, with a bar over it, and the degree tucked into its crook
+    fn layout_radical(&mut self, radicand: MathLayout, degree: Option<MathLayout>, style: MathStyle, parse_state: &ParseState) -> MathLayout {
+        let thickness   = style.rule_thickness();
+        let gap         = style.em() * 0.12;
+        let (sign, offset) = self.layout_stretched("√", radicand.ascent + gap + thickness, radicand.descent, style, parse_state);
+        let mut layout  = MathLayout::default();
+        let mut x       = 0.;
+        if let Some(degree) = degree {
+            let raise   = (sign.ascent - offset) * 0.6;
+            let width   = degree.width;
+            layout.append(degree, 0., -raise);
+            x = (width - sign.width * 0.4).max(0.);
+        }
+        let sign_top    = offset - sign.ascent;
+        let sign_width  = sign.width;
+        layout.append(sign, x, offset);
+        let bar_top     = sign_top.min(-(radicand.ascent + gap + thickness));
+        let radicand_width = radicand.width;
+        layout.append(radicand, x + sign_width, 0.);
+        layout.add_rule(Rect::new(x + sign_width, bar_top, x + sign_width + radicand_width + style.em() * 0.1, bar_top + thickness));
+        layout
+    }
+
+    fn layout_scripts(&mut self, mut base: MathLayout, sub: Option<Node>, sup: Option<Node>, style: MathStyle, parse_state: &ParseState) -> MathLayout {
+        let script_style = style.script();
+        let x           = base.width;
+        let sub         = sub.map(|sub| self.layout_math(sub, script_style, parse_state));
+        let sup         = sup.map(|sup| self.layout_math(sup, script_style, parse_state));
+        let mut sup_y   = -(style.em() * 0.45).max(base.ascent - style.em() * 0.3);
+        let mut sub_y   = (style.em() * 0.2).max(base.descent * 0.5);
+        // Scripts on both sides are pushed apart until they no longer touch
+        if let (Some(sub), Some(sup)) = (&sub, &sup) {
+            let clash = (sup_y + sup.descent) - (sub_y - sub.ascent) + style.em() * 0.1;
+            if clash > 0. {
+                sup_y -= clash / 2.;
+                sub_y += clash / 2.;
+            }
+        }
+        if let Some(sup) = sup { base.append(sup, x, sup_y) }
+        if let Some(sub) = sub { base.append(sub, x, sub_y) }
+        base
+    }
+
+    fn layout_limits(&mut self, base: MathLayout, under: Option<Node>, over: Option<Node>, style: MathStyle, parse_state: &ParseState) -> MathLayout {
+        let script_style = style.script();
+        let gap         = style.em() * 0.1;
+        let under       = under.map(|under| self.layout_math(under, script_style, parse_state));
+        let over        = over.map(|over| self.layout_math(over, script_style, parse_state));
+        let width       = [Some(&base), under.as_ref(), over.as_ref()].into_iter().flatten().map(|layout| layout.width).fold(0., f64::max);
+        let mut layout  = MathLayout::default();
+        let (base_ascent, base_descent) = (base.ascent, base.descent);
+        if let Some(over) = over {
+            let y = -base_ascent - gap - over.descent;
+            let x = (width - over.width) / 2.;
+            layout.append(over, x, y);
+        }
+        if let Some(under) = under {
+            let y = base_descent + gap + under.ascent;
+            let x = (width - under.width) / 2.;
+            layout.append(under, x, y);
+        }
+        let base_x = (width - base.width) / 2.;
+        layout.append(base, base_x, 0.);
+        layout
+    }
+
+    // Cells are centred in their columns and rows, and the whole table on the math axis
+    fn layout_table(&mut self, node: Node, style: MathStyle, parse_state: &ParseState) -> MathLayout {
+        let row_gap     = style.em() * 0.3;
+        let column_gap  = style.em() * 0.8;
+        let cell_style  = MathStyle { font: style.font, display: false };
+        let mut rows: Vec<Vec<MathLayout>> = Vec::new();
+        for row in node.children().filter(|child| child.has_tag_name("mtr") || child.has_tag_name("mlabeledtr")) {
+            let mut cells = Vec::new();
+            for cell in row.children().filter(|child| child.is_element()) {
+                cells.push(match cell.has_tag_name("mtd") {
+                    true    => self.layout_math_row(cell, cell_style, parse_state),
+                    false   => self.layout_math(cell, cell_style, parse_state),
+                });
+            }
+            rows.push(cells);
+        }
+        let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        let widths: Vec<f64> = (0..columns)
+            .map(|column| rows.iter().filter_map(|row| row.get(column)).map(|cell| cell.width).fold(0., f64::max))
+            .collect();
+        let extents: Vec<(f64, f64)> = rows.iter()
+            .map(|row| row.iter().fold((0., 0.), |(ascent, descent): (f64, f64), cell| (ascent.max(cell.ascent), descent.max(cell.descent))))
+            .collect();
+        let height      = extents.iter().map(|(ascent, descent)| ascent + descent).sum::<f64>() + row_gap * rows.len().saturating_sub(1) as f64;
+        let mut y       = -style.em() * AXIS_HEIGHT - height / 2.;
+        let mut layout  = MathLayout::default();
+        for (row, (ascent, descent)) in rows.into_iter().zip(extents) {
+            let mut x = 0.;
+            for (column, cell) in row.into_iter().enumerate() {
+                let cell_x = x + (widths[column] - cell.width) / 2.;
+                layout.append(cell, cell_x, y + ascent);
+                x += widths[column] + column_gap;
+            }
+            y += ascent + descent + row_gap;
+        }
+        layout.width = layout.width.max(widths.iter().sum::<f64>() + column_gap * columns.saturating_sub(1) as f64);
+        layout
+    }
+}
+
+enum MathPart<'a, 'input> {
+    Node(Node<'a, 'input>),
+    Text(String),
+    Fence(String),
+}
+
+fn is_fence(node: Node) -> bool {
+    let text = node.text().unwrap_or_default().trim();
+    node.attribute("stretchy") != Some("false")
+        && (node.attribute("fence") == Some("true") || (text.chars().count() == 1 && FENCES.contains(text)))
+}
+
+fn is_large_operator(node: Node) -> bool {
+    let text = node.text().unwrap_or_default().trim();
+    node.has_tag_name("mo") && node.attribute("largeop") != Some("false")
+        && (node.attribute("largeop") == Some("true") || (text.chars().count() == 1 && LARGE_OPERATORS.contains(text)))
+}
+
+// `mathvariant` picks the weight and slant of a token, identifiers default to italic
+fn variant_state(parse_state: &ParseState, variant: Option<&str>, italic: bool) -> ParseState {
+    let mut state = parse_state.clone();
+    let (bold, italic) = match variant {
+        Some("normal")      => (false, false),
+        Some("bold")        => (true, false),
+        Some("italic")      => (false, true),
+        Some("bold-italic") => (true, true),
+        _                   => (false, italic),
+    };
+    if bold     { state.font_weight = 700 }
+    if italic   { state.text_style = Style::Italic }
+    state
+}
+
+// MathML lengths, with the named spaces of MathML 3
+fn math_length(value: &str, em: f64) -> Option<f64> {
+    let value = value.trim();
+    let named = match value {
+        "veryverythinmathspace"     => Some(1.),
+        "verythinmathspace"         => Some(2.),
+        "thinmathspace"             => Some(3.),
+        "mediummathspace"           => Some(4.),
+        "thickmathspace"            => Some(5.),
+        "verythickmathspace"        => Some(6.),
+        "veryverythickmathspace"    => Some(7.),
+        _ => None,
+    };
+    if let Some(eighteenths) = named { return Some(em * eighteenths / 18.) }
+    let split   = value.find(|ch: char| !(ch.is_ascii_digit() || ch == '.' || ch == '-')).unwrap_or(value.len());
+    let number  = value[..split].parse::<f64>().ok()?;
+    match &value[split..] {
+        "em"    => Some(number * em),
+        "ex"    => Some(number * em / 2.),
+        "px" | "" => Some(number),
+        "pt"    => Some(number * 4. / 3.),
+        _       => None,
+    }
+}
@@ -1,10 +1,14 @@
+use std::iter::Peekable;
 use std::time::Instant;
+use std::vec::IntoIter;
 use floem::kurbo::{Point, Size};
 use floem_renderer::text::Attrs;
+use lightningcss::properties::text::WhiteSpace;
 use lightningcss::stylesheet::StyleSheet;
 use roxmltree::{Document, Node};
 use scraper::ElementRef;
 use crate::book_elem::{BookElemFactory, CharGlyph, Elem, ElemLine, ElemLines, ElemType, InlineContent, InlineElem, ParseState};
+use crate::styling::highlight::{grammar, highlight, TokenKind};
 use crate::styling::style::{resolve_style, resolve_style_scraper};
 
 impl BookElemFactory {
@@ -24,57 +28,70 @@ impl BookElemFactory {
         let mut max_height = 0.0;
 
         let now = Instant::now();
-        let (_, parse_state) = resolve_style(style_sheets, &node, &mut font, parse_state, document);
+        let (_, mut parse_state) = resolve_style(style_sheets, &node, &mut font, parse_state, document);
         self.style_time += (Instant::now() - now).as_nanos();
+        // Margins are not applied to pre blocks, so the box starts and ends with the lines
+        let decoration = parse_state.block_decoration.take().map(|mut decoration| {
+            decoration.x            += parse_state.x;
+            decoration.width        += parse_state.width;
+            decoration.margin_top    = 0.;
+            decoration.margin_bottom = 0.;
+            Box::new(decoration)
+        });
 
+        // Each inner element is laid out in its own style, links included, and with highlighting the char's token color goes over it.
+        // Tokens are found in the whole block at once, as comments and strings run across the inner elements
         fn recurse_pre<'a>(
             factory: &mut BookElemFactory,
             node: Node<'a, 'a>,
             font: Attrs,
             style_sheets: &Vec<StyleSheet>,
             parse_state: &ParseState,
+            href: Option<&str>,
+            document: &Document,
+            tokens: &mut Option<Tokens>,
             x: &mut f64,
             max_height: &mut f64,
             current_line: &mut Vec<InlineElem>,
             lines: &mut Vec<ElemLine>,
         ) {
-            if let Some(text) = node.text() {
-                for line in text.split_inclusive('\n') {
-                    for ch in line.chars() {
-                        if ch == '\n' {
-                            lines.push(ElemLine {
-                                height: *max_height,
-                                inline_elems: std::mem::take(current_line),
-                            });
-                            *x = 0.0;
-                            *max_height = 0.0;
-                            continue;
-                        }
-                        let (text_layout, index) = factory.cache.get_or_insert(ch, font, parse_state);
-                        *max_height = max_height.max(text_layout.size().height);
-                        current_line.push(InlineElem {
-                            x: *x,
-                            inline_content: InlineContent::Text(vec![CharGlyph { char: index, x: 0. }]),
-                        });
-                        *x += text_layout.size().width;
+            if let Some(text) = node.text().filter(|_| node.is_text()) {
+                let mut char_state = parse_state.clone();
+                for ch in text.chars() {
+                    if let Some(tokens) = tokens {
+                        char_state.color = tokens.next_char(ch).map(|kind| factory.syntax_theme.color(kind)).or(parse_state.color);
                     }
+                    push_pre_char(factory, ch, font, &char_state, href, x, max_height, current_line, lines);
                 }
-            } else {
-                for child in node.children() {
-                    if child.is_element() {
-                        //println!("pre: {}", child.tag_name().name());
-                        recurse_pre(factory, child, font, style_sheets, parse_state, x, max_height, current_line, lines);
-                    }
+                return
+            }
+            let mut child_state = parse_state.clone();
+            child_state.ancestors.push(node.id());
+            for child in node.children().filter(|child| child.is_element() || child.is_text()) {
+                if child.is_text() {
+                    recurse_pre(factory, child, font, style_sheets, &child_state, href, document, tokens, x, max_height, current_line, lines);
+                    continue
                 }
+                let mut child_font  = font;
+                let (_, state)      = resolve_style(style_sheets, &child, &mut child_font, child_state.clone(), document);
+                let child_href      = child.has_tag_name("a").then(|| child.attribute("href")).flatten().or(href);
+                recurse_pre(factory, child, child_font, style_sheets, &state, child_href, document, tokens, x, max_height, current_line, lines);
             }
         }
 
+        let mut tokens = code_language(node).and_then(grammar).map(|grammar| {
+            let source: String = node.descendants().filter(|node| node.is_text()).filter_map(|node| node.text()).collect();
+            Tokens { tokens: highlight(&source, grammar).into_iter().peekable(), offset: 0 }
+        });
         recurse_pre(
             self,
             node,
             font,
             style_sheets,
             &parse_state,
+            None,
+            document,
+            &mut tokens,
             &mut x,
             &mut max_height,
             &mut current_line,
@@ -96,6 +113,7 @@ impl BookElemFactory {
             size: Size::new(parse_state.width, total_height),
             point: init_point,
             elem_type: ElemType::Lines(ElemLines { height: total_height, elem_lines: lines }),
+            decoration,
         }
     }
 
@@ -148,6 +166,7 @@ impl BookElemFactory {
                             current_line.push(InlineElem {
                                 x: *x,
                                 inline_content: InlineContent::Text(vec![CharGlyph { char: index, x: 0. }]),
+                                style: parse_state.inline_style.clone(),
                             });
                             *x += text_layout.size().width;
                         }
@@ -186,6 +205,91 @@ impl BookElemFactory {
             size: Size::new(parse_state.width, total_height),
             point: init_point,
             elem_type: ElemType::Lines(ElemLines { height: total_height, elem_lines: lines }),
+            decoration: None,
+        }
+    }
+}
+
+/// Highlighted tokens of a code block, walked through a char at a time
+struct Tokens { tokens: Peekable<IntoIter<(usize, usize, TokenKind)>>, offset: usize }
+
+impl Tokens {
+    // The kind of token `ch` is part of, `ch` being the next char of the block's text
+    fn next_char(&mut self, ch: char) -> Option<TokenKind> {
+        let offset = self.offset;
+        self.offset += ch.len_utf8();
+        while self.tokens.next_if(|(_, end, _)| *end <= offset).is_some() {}
+        match self.tokens.peek() {
+            Some((start, _, kind)) if *start <= offset => Some(*kind),
+            _ => None,
         }
     }
-}
\ No newline at end of file
+}
+
+/// The language named by a `language-*` or `lang-*` class on the pre or the code element inside it
+fn code_language<'a>(node: Node<'a, 'a>) -> Option<&'a str> {
+    let code = node.children().find(|child| child.has_tag_name("code"));
+    [Some(node), code].into_iter().flatten()
+        .filter_map(|node| node.attribute("class"))
+        .flat_map(|class| class.split_whitespace())
+        .find_map(|class| class.strip_prefix("language-").or_else(|| class.strip_prefix("lang-")))
+}
+
+fn push_pre_char(
+    factory: &mut BookElemFactory,
+    ch: char,
+    font: Attrs,
+    parse_state: &ParseState,
+    href: Option<&str>,
+    x: &mut f64,
+    max_height: &mut f64,
+    current_line: &mut Vec<InlineElem>,
+    lines: &mut Vec<ElemLine>,
+) {
+    if ch == '\n' {
+        lines.push(ElemLine {
+            height: *max_height,
+            inline_elems: std::mem::take(current_line),
+        });
+        *x = 0.0;
+        *max_height = 0.0;
+        return;
+    }
+    let (text_layout, index) = factory.cache.get_or_insert(ch, font, parse_state);
+    *max_height = max_height.max(text_layout.size().height);
+    let glyphs = vec![CharGlyph { char: index, x: 0. }];
+    current_line.push(InlineElem {
+        x: *x,
+        inline_content: match href {
+            Some(href)  => InlineContent::Link((glyphs, href.to_string())),
+            None        => InlineContent::Text(glyphs),
+        },
+        style: parse_state.inline_style.clone(),
+    });
+    *x += text_layout.size().width;
+    if wraps(parse_state.white_space) && *x > parse_state.width - parse_state.x {
+        wrap_pre_line(factory, x, *max_height, current_line, lines);
+    }
+}
+
+fn wraps(white_space: WhiteSpace) -> bool {
+    matches!(white_space, WhiteSpace::PreWrap | WhiteSpace::BreakSpaces | WhiteSpace::PreLine)
+}
+
+// Ends the line after its last space, carrying the word that overflowed onto the next one, or breaks a word too long for the line
+fn wrap_pre_line(factory: &BookElemFactory, x: &mut f64, height: f64, current_line: &mut Vec<InlineElem>, lines: &mut Vec<ElemLine>) {
+    let is_space = |inline_elem: &InlineElem| match &inline_elem.inline_content {
+        InlineContent::Text(glyphs) => glyphs.iter().all(|glyph| factory.cache.key(glyph.char).0 == ' '),
+        _ => false,
+    };
+    let split = match current_line.iter().rposition(is_space) {
+        Some(space) => space + 1,
+        None if current_line.len() > 1 => current_line.len() - 1,
+        None => return,
+    };
+    let mut carried = current_line.split_off(split);
+    let offset      = carried.first().map_or(*x, |inline_elem| inline_elem.x);
+    carried.iter_mut().for_each(|inline_elem| inline_elem.x -= offset);
+    *x              -= offset;
+    lines.push(ElemLine { height, inline_elems: std::mem::replace(current_line, carried) });
+}
@@ -1,22 +1,41 @@
 use std::collections::HashMap;
 use std::ops::Deref;
+use std::rc::Rc;
 use std::time;
 use std::time::Instant;
 use floem::{Clipboard, View, ViewId};
 use floem::context::{ComputeLayoutCx, EventCx, PaintCx};
 use floem::event::{Event, EventPropagation};
 use floem::keyboard::{Key, Modifiers, NamedKey};
-use floem::kurbo::{Point, Rect, Size};
+use floem::kurbo::{Affine, Point, Rect, RoundedRect, RoundedRectRadii, Size, Stroke, Vec2};
 use floem::prelude::{Color, RwSignal, SignalUpdate};
 use floem::reactive::{ReadSignal, SignalGet, SignalRead, WriteSignal};
 use floem::style::{Cursor, CursorStyle};
 use floem::views::Decorators;
-use floem_renderer::{Img, Renderer};
+use floem_renderer::{Img, Renderer, Svg};
 use floem_renderer::text::TextLayout;
+use lightningcss::properties::text::TextDecorationLine;
 use sha2::Digest;
 
-use crate::book_elem::{CharGlyph, Elem, ElemLine, ElemType, HTMLPage, InlineContent};
+use crate::book_elem::{BlockDecoration, BlockElem, Break, CharGlyph, Elem, ElemLine, ElemType, HTMLPage, ImageElem, InlineContent, InlineStyle, SpreadSide, VerticalAlign, DETAILS_LINK};
+use crate::layout::{baseline_extents, BASELINE_RATIO};
 use crate::glyph_interner::GlyphCache;
+use crate::table_parser::TABLE_LINK;
+
+const TABLE_PAN_STEP: f64 = 40.;
+const TABLE_ZOOM_STEP: f64 = 0.1;
+const MIN_TABLE_ZOOM: f64 = 0.25;
+const MAX_TABLE_ZOOM: f64 = 4.;
+// Image viewer zoom steps multiply, so each step looks the same at any zoom
+const IMAGE_ZOOM_STEP: f64 = 1.2;
+const MIN_IMAGE_ZOOM: f64 = 0.05;
+const MAX_IMAGE_ZOOM: f64 = 16.;
+/// Page asked for when paging back into the previous section, before that section is paginated
+const LAST_PAGE: usize = usize::MAX;
+const SCROLLBAR_WIDTH: f64 = 8.;
+const MIN_THUMB_HEIGHT: f64 = 24.;
+/// Share of the view the arrow keys scroll by, the rest stays in view to keep the reader's place
+const SCROLL_PAGE: f64 = 0.9;
 
 #[derive(Clone)]
 pub(crate) struct RenderState {
@@ -25,36 +44,123 @@ pub(crate) struct RenderState {
     col_index: f32,
     line_index: isize,
     terminate: bool,
-    selected_text: String,
+    /// Place in the text of the next glyph painted
+    pub(crate) position: TextPosition,
+    pub(crate) text_map: TextMap,
+    /// From paint coordinates to the view's unzoomed pointer coordinates, where glyphs are mapped
+    to_pointer: Affine,
     first_line_rendered: bool,
-    pub(crate) selection: Option<Selection>
+    pub(crate) selection: Option<Selection>,
+    /// Set while paginating, where the lines of each decorated block on the page land, by the block's index
+    placements: Option<DecorationBoxes>,
+    /// `orphans` and `widows` of the block whose lines are being painted
+    orphans: usize,
+    widows: usize,
+    /// Lines above this point of the document were painted on the page before, set while a table row cut by a page end is taken up again
+    skip_above: Option<f64>,
 }
 
-#[derive(Clone)]
-pub(crate) struct Selection {
-    pub(crate) start_col: usize,
-    pub(crate) end_col: usize,
-    pub(crate) start_selection: Point,
-    pub(crate) end_selection: Point,
+impl RenderState {
+    fn new(selection: Option<Selection>) -> Self {
+        RenderState {x: 0., y: 0., col_index: 0., terminate: false, line_index: 0, position: TextPosition::default(), text_map: TextMap::default(), to_pointer: Affine::IDENTITY, first_line_rendered: false, selection, placements: None, orphans: 0, widows: 0, skip_above: None}
+    }
+
+    // Records where the glyph at `position` was painted and moves on to the next one
+    fn map_glyph(&mut self, rect: Rect) {
+        if self.text_map.elems.last().map_or(true, |elem| elem.section != self.position.section || elem.index != self.position.index) {
+            self.text_map.elems.push(TextPosition { offset: 0, ..self.position.clone() });
+        }
+        let rect = self.to_pointer.transform_rect_bbox(rect);
+        self.text_map.glyphs.push(GlyphBox { rect, elem: self.text_map.elems.len() - 1, offset: self.position.offset });
+        self.position.offset += 1;
+    }
+
+    // The next line painted is the lines elem at `index`, starting `offset` glyphs into it
+    fn start_line(&mut self, index: &[usize], offset: usize) {
+        if self.position.index != index {
+            self.position.index = index.to_vec();
+        }
+        self.position.offset = offset;
+    }
 }
 
+/// Where a page starts, it is painted forward from there until its columns are full, and where its decorated blocks land
+#[derive(Clone)]
+struct Page { start_index: Vec<usize>, start_elem_index: usize, start_offset_y: f64, boxes: Rc<DecorationBoxes> }
+
+/// The pages of a section for one viewport, laid out again when the section, viewport or zoom changes
+struct Pagination { url: String, size: Size, pages: Vec<Page> }
+
+// Where a line ended up on screen, collected while paginating to size block decorations
+#[derive(Clone)]
+struct LinePlacement { col_x: f64, y: f64, height: f64, doc_y: f64 }
+
+type DecorationBoxes = HashMap<Vec<usize>, Vec<LinePlacement>>;
+
+/// A place in the book's text, before the glyph `offset` glyphs into the lines elem at `index` of a section.
+/// Ordered as the text reads and kept apart from where it is laid out, so it holds across page turns and zoom.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct TextPosition { pub(crate) section: usize, pub(crate) index: Vec<usize>, pub(crate) offset: usize }
+
+/// The text between where a selection was started and where it was extended to, in either order
+#[derive(Clone)]
+pub(crate) struct Selection { pub(crate) anchor: TextPosition, pub(crate) focus: TextPosition }
+
 impl Selection {
-    pub fn new(start_col: usize, end_col: usize, start_selection: Point, end_selection: Point) -> Self {
-        Self {start_col, end_col, start_selection, end_selection}
+    pub fn new(anchor: TextPosition, focus: TextPosition) -> Self {
+        Self {anchor, focus}
+    }
+
+    pub fn range(&self) -> (&TextPosition, &TextPosition) {
+        match self.anchor <= self.focus {
+            true    => (&self.anchor, &self.focus),
+            false   => (&self.focus, &self.anchor),
+        }
+    }
+
+    pub fn contains(&self, position: &TextPosition) -> bool {
+        let (start, end) = self.range();
+        start <= position && position < end
     }
 }
 
+/// A glyph of the last frame in pointer coordinates, `elem` indexing the lines elem it belongs to in its text map
+#[derive(Clone, Copy)]
+pub(crate) struct GlyphBox { pub(crate) rect: Rect, pub(crate) elem: usize, pub(crate) offset: usize }
+
+/// Where the glyphs of the last frame were painted, for pointer positions to be looked up in
+#[derive(Clone, Default)]
+pub(crate) struct TextMap { pub(crate) elems: Vec<TextPosition>, pub(crate) glyphs: Vec<GlyphBox> }
+
 pub struct HtmlRenderer {
     id: ViewId,
 
     read_current_url: RwSignal<String>,
-    pages: HashMap<String, HTMLPage>,
+    pub(crate) pages: HashMap<String, HTMLPage>,
 
+    /// First elem of the current page, set from outside to jump to a position
     start_index: RwSignal<Vec<usize>>,
-    end_index: Vec<usize>,
+    /// First inline elem painted of the page being laid out
+    start_elem_index: usize,
+
+    pagination: Option<Pagination>,
+    page: usize,
+    /// Decoration boxes of the page being painted, none while the table view paints its single column
+    page_boxes: Option<Rc<DecorationBoxes>>,
 
-    start_elem_index: RwSignal<usize>,
-    end_elem_index: RwSignal<usize>,
+    /// Reading as one column scrolled by the pixel instead of in pages
+    scroll_mode: RwSignal<bool>,
+    /// Spine order of the sections, scrolling runs on from the end of one into the next
+    pub(crate) sections: Vec<String>,
+    /// Pages progress from right to left, the first page of a spread goes on the right
+    right_to_left: bool,
+    /// Distance from the top of the current section to the top of the view
+    scroll_top: f64,
+    /// Section and elem the scrolled view last started at, anything else was navigated to from outside
+    scroll_url: String,
+    scroll_index: Vec<usize>,
+    scroll_height: f64,
+    scrollbar_drag: bool,
 
     size: Size,
     point: Point,
@@ -64,12 +170,11 @@ pub struct HtmlRenderer {
     col_gap: f64,
     scale: f64,
 
-    glyph_cache : GlyphCache,
+    pub(crate) glyph_cache : GlyphCache,
 
     start_offset_y: f64,
     end_offset_y: f64,
 
-    render_forward: bool,
     get_go_on: ReadSignal<bool>,
     at_ends: WriteSignal<i8>,
 
@@ -78,77 +183,109 @@ pub struct HtmlRenderer {
     click_location: Option<Point>,
     pub(crate) press_location: Option<Point>,
 
-    pub(crate) move_location: Point,
-
     copy: bool,
-    pub(crate) selection_active: bool,
-    drag_in_progress: bool,
+    pub(crate) selection: Option<Selection>,
+    pub(crate) text_map: TextMap,
     key_press: bool,
 
+    /// Wide table of the current page shown in place of the page, panned and zoomed independently
+    open_table: RwSignal<Option<usize>>,
+    table_pan: Point,
+    table_zoom: f64,
+    table_drag: Option<Point>,
+    table_bounds: Rect,
+    /// Image clicked on the page, shown alone at its own resolution over the page
+    open_image: RwSignal<Option<ImageElem>>,
+    image_pan: Point,
+    image_zoom: f64,
+    /// The zoom follows the view so the whole image stays in it
+    image_fit: bool,
+    image_drag: Option<Point>,
+    image_bounds: Rect,
+    /// Details element of the current section clicked open or closed, toggled before the next paint
+    toggle_details: RwSignal<Option<Vec<usize>>>,
 }
 
 impl HtmlRenderer {
 
-    pub fn new(start_index: RwSignal<Vec<usize>>, glyph_cache: GlyphCache, pages: HashMap<String, HTMLPage>, read_current_url: RwSignal<String>, at_ends: WriteSignal<i8>, get_go_on: ReadSignal<bool>) -> Self{
+    pub fn new(start_index: RwSignal<Vec<usize>>, glyph_cache: GlyphCache, pages: HashMap<String, HTMLPage>, sections: Vec<String>, right_to_left: bool, read_current_url: RwSignal<String>, at_ends: WriteSignal<i8>, get_go_on: ReadSignal<bool>, scroll_mode: RwSignal<bool>) -> Self{
         let mut html_renderer = HtmlRenderer {
-            id: ViewId::new(), start_index, end_index: Vec::new(),
-            start_elem_index: RwSignal::new(0), end_elem_index: RwSignal::new(0),
+            id: ViewId::new(), start_index, start_elem_index: 0,
+            pagination: None, page: 0, page_boxes: None,
+            scroll_mode, sections, right_to_left, scroll_top: 0., scroll_url: String::new(), scroll_index: Vec::new(), scroll_height: 0., scrollbar_drag: false,
             col_gap: 0., col_count: 0., col_width: 600., orig_col_width: 600.,
             size: Size::default(), point: Point::default(), start_offset_y: 0., end_offset_y: 0.,
-            render_forward: true, scale: 1.0,
+            scale: 1.0,
             glyph_cache, pages,
             read_current_url, get_go_on, at_ends,
             line_reader_assist_x_index: -1, line_reader_assist_y_index: -1,
-            click_location: None, press_location: None, move_location: Point::default(),
-            copy: false, selection_active: false, drag_in_progress: false, key_press: false,
+            click_location: None, press_location: None,
+            copy: false, selection: None, text_map: TextMap::default(), key_press: false,
+            open_table: RwSignal::new(None), table_pan: Point::default(), table_zoom: 1.0, table_drag: None, table_bounds: Rect::ZERO,
+            open_image: RwSignal::new(None), image_pan: Point::default(), image_zoom: 1.0, image_fit: true, image_drag: None, image_bounds: Rect::ZERO,
+            toggle_details: RwSignal::new(None),
         };
         html_renderer = html_renderer.keyboard_navigable();
         html_renderer
     }
 
-    pub(crate) fn get_col_index(&self, x: f64) -> usize {
-        ((x) / (self.col_width + self.col_gap)).floor() as usize
-    }
-
 
     pub fn next(&mut self) {
-        self.render_forward = true;
-        let current_url     = self.read_current_url.get();
-        let root_elem       = &self.pages.get(&current_url).unwrap().root;
-        let last_index      = root_elem.get_last_index();
-        //if self.end_index.len() != 0 && self.end_index[0] == 1 {
-        println!("Last index: {:#?}\n End index {:#?}", last_index, self.end_index);
-        if self.end_index.eq(&last_index) || (self.end_index.len() != 0 && self.end_index[0] == 1)  {
+        if let Some(spread) = self.current_spread() {
+            let last = spread.iter().flatten().max().copied().unwrap_or_default();
+            if let Some(url) = self.sections.get(last + 1).cloned() { self.turn_to(url, 0) }
+            return
+        }
+        if self.scroll_mode.get_untracked() {
+            self.scroll_top += self.size.height * SCROLL_PAGE;
+            return
+        }
+        let Some(pagination) = &self.pagination else { return };
+        if self.page + 1 >= pagination.pages.len() {
             self.at_ends.set(1);
             if !self.get_go_on.get() {return}
+            self.page = 0;
             self.start_index.set(Vec::new());
-            self.start_offset_y = 0.;
-            self.start_elem_index.set(0);
-            self.end_elem_index.set(0);
-            self.end_index = Vec::new();
             return;
         }
         self.at_ends.set(0);
-        self.start_index.set(self.end_index.clone());
-        self.start_elem_index.set(self.end_elem_index.get_untracked());
+        self.page += 1;
+        self.start_index.set(pagination.pages[self.page].start_index.clone());
     }
 
     pub fn prev(&mut self ) {
-        self.render_forward = false;
-        if self.start_index.get().len() == 0 {
+        if let Some(spread) = self.current_spread() {
+            let first = spread.iter().flatten().min().copied().unwrap_or_default();
+            if let Some(url) = first.checked_sub(1).and_then(|position| self.sections.get(position)).cloned() { self.turn_to(url, LAST_PAGE) }
+            return
+        }
+        if self.scroll_mode.get_untracked() {
+            self.scroll_top -= self.size.height * SCROLL_PAGE;
+            return
+        }
+        let Some(pagination) = &self.pagination else { return };
+        if self.page == 0 {
             self.at_ends.set(-1);
             if !self.get_go_on.get() {return}
-            self.start_elem_index.set(0);
-            self.end_elem_index.set(0);
             self.goto_last();
             return
         }
         self.at_ends.set(0);
-        self.end_index      = self.start_index.get_untracked();
-        self.end_elem_index.set(self.start_elem_index.get_untracked());
+        self.page -= 1;
+        self.start_index.set(pagination.pages[self.page].start_index.clone());
     }
 
     pub fn goto(&self, link: &String) {
+        if let Some(table) = link.strip_prefix(TABLE_LINK) {
+            self.open_table.set(table.parse().ok());
+            self.id.request_paint();
+            return;
+        }
+        if let Some(path) = link.strip_prefix(DETAILS_LINK) {
+            self.toggle_details.set(Some(path.split('.').filter_map(|position| position.parse().ok()).collect()));
+            self.id.request_paint();
+            return;
+        }
         if link.contains("www") || link.contains("http") {
             open::that(link).unwrap();
             return;
@@ -168,8 +305,6 @@ impl HtmlRenderer {
 
         let document = &self.pages.get(&new_url).unwrap();
         self.read_current_url.set(new_url);
-        self.start_elem_index.set(0);
-        self.end_elem_index.set(0);
         if parts.len() == 1 {
             self.start_index.set(Vec::new());
             return;
@@ -181,13 +316,58 @@ impl HtmlRenderer {
         }
     }
 
+    // The section changed with it, its last page is picked once it is paginated
     pub fn goto_last(&mut self) {
-        let current_url     = self.read_current_url.get();
-        let root_elem       = &self.pages.get(&current_url).unwrap().root;
-        let last_index      = root_elem.get_last_index();
-        self.end_index      = last_index;
-        self.end_elem_index.set(99999999);
-        self.render_forward = false;
+        self.page = LAST_PAGE;
+    }
+
+    // Leaves a spread for the section next to it, at `page` if that one reflows
+    fn turn_to(&mut self, url: String, page: usize) {
+        self.page = page;
+        self.read_current_url.set(url);
+        self.start_index.set(Vec::new());
+    }
+
+    /// Fixed-layout sections grouped into the spreads shown at once, each spread's slots from left to right.
+    /// A page waits for the one after it unless its side or spread mode keeps it alone, the empty slot keeps it on its side.
+    fn spreads(&self, landscape: bool) -> Vec<Vec<Option<usize>>> {
+        let (first_side, second_side) = match self.right_to_left {
+            true    => (SpreadSide::Right, SpreadSide::Left),
+            false   => (SpreadSide::Left, SpreadSide::Right),
+        };
+        let mut spreads = Vec::new();
+        let mut pending = None;
+        for (position, url) in self.sections.iter().enumerate() {
+            let spread = self.pages.get(url).and_then(|page| page.fixed.as_ref()).map(|fixed| fixed.spread);
+            match spread {
+                Some(spread) if spread.mode.spreads(landscape) && spread.side == second_side => spreads.push(vec![pending.take(), Some(position)]),
+                Some(spread) if spread.mode.spreads(landscape) => {
+                    if let Some(first) = pending.take() {
+                        match spread.side == first_side {
+                            true    => spreads.push(vec![Some(first), None]),
+                            false   => { spreads.push(vec![Some(first), Some(position)]); continue }
+                        }
+                    }
+                    pending = Some(position);
+                }
+                _ => {
+                    if let Some(first) = pending.take() { spreads.push(vec![Some(first), None]) }
+                    if spread.is_some() { spreads.push(vec![Some(position)]) }
+                }
+            }
+        }
+        if let Some(first) = pending { spreads.push(vec![Some(first), None]) }
+        if self.right_to_left { spreads.iter_mut().for_each(|spread| spread.reverse()) }
+        spreads
+    }
+
+    // The spread holding the current section, None when it reflows
+    fn current_spread(&self) -> Option<Vec<Option<usize>>> {
+        let url         = self.read_current_url.get_untracked();
+        self.pages.get(&url)?.fixed.as_ref()?;
+        let position    = self.sections.iter().position(|section| *section == url)?;
+        let view        = self.id.get_size().unwrap_or_default();
+        self.spreads(view.width > view.height).into_iter().find(|spread| spread.contains(&Some(position)))
     }
 
     fn resolve_point(&self, point: Point, elem_height: f64, mut render_state: RenderState) -> (RenderState, Point) {
@@ -196,19 +376,12 @@ impl HtmlRenderer {
         y = y - col_index * self.size.height;
         if y + elem_height > self.size.height {
             render_state.col_index += 1.0;
-            if self.render_forward {
-                col_index += 1.0;
-                render_state.y += self.size.height - y;
-                y = 0.;
-            }
-            else {
-                render_state.y -= y - self.size.height + elem_height;
-                y = self.size.height as f64 - elem_height;
-            }
+            col_index += 1.0;
+            render_state.y += self.size.height - y;
+            y = 0.;
         }
         let x = (self.col_gap + col_index * (self.col_width+ self.col_gap)) as f64 + point.x;
-        if self.render_forward && x + 1.0  >= self.size.width { render_state.terminate = true; }
-        if !self.render_forward && x < 0.               {render_state.terminate = true; };
+        if x + 1.0  >= self.size.width { render_state.terminate = true; }
         (render_state, Point::new(x, y))
     }
 
@@ -216,72 +389,94 @@ impl HtmlRenderer {
 
     fn paint_line(&self, cx: &mut PaintCx, elem: &Elem, line: &ElemLine, line_offset_y: f64, mut render_state: RenderState, render: bool) -> RenderState {
         let mut reader_assist_y = false;
-        let mut x_index = 0;
         if self.line_reader_assist_y_index == render_state.line_index {
             reader_assist_y = true;
             render_state.y += 20.
         }
+        // Lines taller than a column, in practice full page images, are scaled down to fit one
+        let line_height             = line.height.min(self.size.height);
+        let excess                  = line.height - line_height;
+        let fit                     = if excess > 0. { line_height / line.height } else { 1. };
         let mut line_point          = Point::new(elem.point.x, elem.point.y + line_offset_y);
-        (render_state, line_point)  = self.resolve_point(line_point, line.height, render_state);
+        (render_state, line_point)  = self.resolve_point(line_point, line_height, render_state);
+        render_state.y              -= excess;
         if render_state.terminate {return render_state}
+        if let Some(placements) = &mut render_state.placements {
+            // The line belongs to the box of every decorated block around it, their indexes are prefixes of its own
+            let placement = LinePlacement { col_x: line_point.x - elem.point.x, y: line_point.y, height: line_height, doc_y: elem.point.y + line_offset_y };
+            for depth in 0..=render_state.position.index.len() {
+                if let Some(column) = placements.get_mut(&render_state.position.index[..depth]) { column.push(placement.clone()) }
+            }
+        }
         if !render {return render_state}
 
-            for elem in line.inline_elems.iter() {
-                let elem_point = Point::new(line_point.x + elem.x, line_point.y);
-                let mut elem_width = 0.;
-                match &elem.inline_content {
-                    InlineContent::Text(text) => {
-                        for char_glyph in text {
-                            let glyph = self.glyph_cache.get(char_glyph.char);
-                            let ascent = glyph.lines().first().unwrap().layout_opt().as_ref().unwrap().first().as_ref().unwrap().max_ascent as f64;
-                            let descent = glyph.lines().first().unwrap().layout_opt().as_ref().unwrap().first().as_ref().unwrap().max_descent as f64;
-
-                            elem_width = char_glyph.x;
-                            let gx0 = elem_point.x + char_glyph.x as f64;
-                            let gy0 = elem_point.y;
-                            let gx1 = gx0 + glyph.size().width + 1.0;
-                            let gy1 = gy0 + line.height + 1.0;
-                            if self.selection_active && self.hit(&render_state, gx0, gy0, gx1, gy1){
-                                let rect = Rect::new(gx0, gy0, gx1, gy1);
-                                let text = glyph.lines().first().unwrap().text();
-                                render_state.selected_text.push_str(text);
-                                cx.fill(&rect, Color::LIGHT_BLUE, 0.);
-                            }
+        render_state = self.draw_line(cx, line, line_point, line_height, fit, reader_assist_y, render_state);
+        //println!("{}, {}", self.line_reader_assist_y_index, render_state.line_index);
+        if self.line_reader_assist_y_index == render_state.line_index {
+            let rect = Rect::new(line_point.x, line_point.y + line_height, line_point.x + self.col_width, line_point.y + line_height + 2.0);
+            cx.fill(&rect, Color::BLACK, 0.);
 
-                            //cx.draw_text(glyph, Point::new(elem_point.x + char_glyph.x as f64, elem_point.y + line.height - glyph.size().height))
-                            cx.draw_text(glyph, Point::new(elem_point.x + char_glyph.x as f64, elem_point.y + line.height / 1.6 - ascent - descent));
-                        }
+            render_state.y += 20.;
+        }
+        render_state.line_index += 1;
+        render_state
+    }
+
+    // Draws a line's inline elems with its top left at `line_point`, `fit` scaling images of lines shrunk to a column
+    fn draw_line(&self, cx: &mut PaintCx, line: &ElemLine, line_point: Point, line_height: f64, fit: f64, reader_assist_y: bool, mut render_state: RenderState) -> RenderState {
+        let mut x_index = 0;
+        for elem in line.inline_elems.iter() {
+            let elem_point = Point::new(line_point.x + elem.x, line_point.y);
+            let mut elem_width = 0.;
+            match &elem.inline_content {
+                InlineContent::Text(text) | InlineContent::Link((text, _)) => {
+                    if let Some(background) = elem.style.as_ref().and_then(|style| style.background) {
+                        let rect = Rect::new(elem_point.x, elem_point.y, elem_point.x + self.text_width(text), elem_point.y + line_height);
+                        cx.fill(&rect, background, 0.);
                     }
-                    InlineContent::Link((text, link)) => {
-                        for char_glyph in text {
-                            let glyph = self.glyph_cache.get(char_glyph.char);
-                            elem_width = char_glyph.x;
-                            let x = elem_point.x + char_glyph.x as f64;
-                            cx.draw_text(glyph, Point::new(x, elem_point.y));
-                            let descent = glyph.lines().first().unwrap().layout_opt().as_ref().unwrap().first().as_ref().unwrap().max_descent as f64;
-                            let y = elem_point.y + glyph.size().height - descent;
-                            let x0 = x;
-                            let x1 = x0 + glyph.size().width + 1.0;
-                            let rect = Rect::new(x0 - 1., y, x1 + 1., y + 2.0);
-                            cx.fill(&rect, Color::DARK_GREEN, 0.);
-                            if let Some(location) = self.click_location {
-                                if x <= location.x && location.x <= x1 && elem_point.y <= location.y
-                                    && location.y <= elem_point.y + glyph.size().height {
-                                    self.goto(link);
-
-                                }
-                                self.id.get_combined_style().cursor(CursorStyle::Pointer) ;
-                                self.id.request_style();
+                    for char_glyph in text {
+                        let glyph = self.glyph_cache.get(char_glyph.char);
+
+                        elem_width = char_glyph.x;
+                        let gx0 = elem_point.x + char_glyph.x as f64;
+                        let gy0 = elem_point.y;
+                        let gx1 = gx0 + glyph.size().width + 1.0;
+                        let gy1 = gy0 + line_height + 1.0;
+                        if render_state.selection.as_ref().is_some_and(|selection| selection.contains(&render_state.position)) {
+                            cx.fill(&Rect::new(gx0, gy0, gx1, gy1), Color::LIGHT_BLUE, 0.);
+                        }
+                        render_state.map_glyph(Rect::new(gx0, gy0, gx0 + glyph.size().width, gy0 + line_height));
+
+                        //cx.draw_text(glyph, Point::new(elem_point.x + char_glyph.x as f64, elem_point.y + line_height - glyph.size().height))
+                        cx.draw_text(glyph, Point::new(gx0, aligned_origin_y(glyph, elem_point.y, line_height, elem.style.as_deref())));
+                        if let (InlineContent::Link((_, link)), Some(location)) = (&elem.inline_content, self.click_location) {
+                            if gx0 <= location.x && location.x <= gx1 && gy0 <= location.y && location.y <= gy1 {
+                                self.goto(link);
                             }
+                            self.id.get_combined_style().cursor(CursorStyle::Pointer) ;
+                            self.id.request_style();
                         }
                     }
-                    InlineContent::Image(image_elem) => {
+                    if let Some(style) = &elem.style {
+                        self.paint_text_decoration(cx, style, text, elem_point, line_height);
+                    }
+                }
+                InlineContent::Image(image_elem) => {
+                    let height  = image_elem.height as f64 * fit;
+                    let y       = image_y(line_point.y, line_height, height, elem.style.as_deref());
+                    let rect    = Rect::new(line_point.x + elem.x, y, line_point.x + elem.x + image_elem.width as f64 * fit, y + height);
+                    if self.click_location.is_some_and(|location| rect.contains(location)) {
+                        self.open_image.set(Some(image_elem.clone()));
+                        self.id.request_paint();
+                    }
+                    if let Some(svg) = &image_elem.svg {
+                        cx.draw_svg(Svg {tree: &svg.tree, hash: &svg.hash}, rect, None::<Color>);
+                    }
+                    else {
                         let image_promise = image_elem.image_promise.read().unwrap();
                         match image_promise.deref() {
                             None => {println!("Found no image")}
                             Some(image) => {
-
-                                let rect = Rect::new(line_point.x + elem.x, line_point.y, line_point.x + elem.x + image_elem.width as f64, line_point.y + image_elem.height as f64);
                                 let img = Img {img: image.0.clone(), hash: &image.1};
                                 cx.draw_img(img, rect);
                                // println!("Rendered image: {}", line_point.x);
@@ -289,72 +484,630 @@ impl HtmlRenderer {
                         }
                     }
                 }
-                if reader_assist_y && self.line_reader_assist_x_index == x_index {
-                    let rect = Rect::new(elem_point.x, line_point.y, elem_point.x + elem_width as f64, line_point.y + 2.0);
-                    cx.fill(&rect, Color::BLACK, 0.);
+                InlineContent::Math(math) => {
+                    let top     = image_y(line_point.y, line_height, math.height, elem.style.as_deref());
+                    let color   = math.color.unwrap_or(Color::BLACK);
+                    for glyph in &math.glyphs {
+                        cx.draw_text(self.glyph_cache.get(glyph.char), Point::new(elem_point.x + glyph.x as f64, top + glyph.y as f64));
+                    }
+                    for rule in &math.rules {
+                        cx.fill(&(*rule + Vec2::new(elem_point.x, top)), color, 0.);
+                    }
                 }
-                if reader_assist_y {println!("{}, {}", self.line_reader_assist_x_index, x_index);}
-                if elem_width == 0. {x_index -= 1}
-                x_index += 1;
+                InlineContent::Ruby(ruby) => {
+                    for char_glyph in &ruby.base {
+                        let glyph = self.glyph_cache.get(char_glyph.char);
+                        let x = elem_point.x + (ruby.base_x + char_glyph.x) as f64;
+                        cx.draw_text(glyph, Point::new(x, aligned_origin_y(glyph, elem_point.y, line_height, elem.style.as_deref())));
+                    }
+                    for char_glyph in &ruby.annotation {
+                        let glyph = self.glyph_cache.get(char_glyph.char);
+                        let x = elem_point.x + (ruby.annotation_x + char_glyph.x) as f64;
+                        cx.draw_text(glyph, Point::new(x, aligned_origin_y(glyph, elem_point.y, line_height, elem.style.as_deref()) - ruby.raise as f64));
+                    }
+                    if let Some(style) = &elem.style {
+                        self.paint_text_decoration(cx, style, &ruby.base, elem_point + Vec2::new(ruby.base_x as f64, 0.), line_height);
+                    }
+                }
+                // Breaks end their line during layout and are never part of one
+                InlineContent::Break | InlineContent::Float(_) => {}
+            }
+            if reader_assist_y && self.line_reader_assist_x_index == x_index {
+                let rect = Rect::new(elem_point.x, line_point.y, elem_point.x + elem_width as f64, line_point.y + 2.0);
+                cx.fill(&rect, Color::BLACK, 0.);
+            }
+            if reader_assist_y {println!("{}, {}", self.line_reader_assist_x_index, x_index);}
+            if elem_width == 0. {x_index -= 1}
+            x_index += 1;
         }
-        //println!("{}, {}", self.line_reader_assist_y_index, render_state.line_index);
-        if self.line_reader_assist_y_index == render_state.line_index {
-            let rect = Rect::new(line_point.x, line_point.y + line.height, line_point.x + self.col_width, line_point.y + line.height + 2.0);
-            cx.fill(&rect, Color::BLACK, 0.);
+        render_state
+    }
 
-            render_state.y += 20.;
+    fn text_width(&self, text: &[CharGlyph]) -> f64 {
+        text.last().map_or(0., |last| last.x as f64 + self.glyph_cache.get(last.char).size().width)
+    }
+
+    fn paint_text_decoration(&self, cx: &mut PaintCx, style: &InlineStyle, text: &[CharGlyph], point: Point, line_height: f64) {
+        if style.decoration.is_empty() { return }
+        let Some(first) = text.first() else { return };
+        let glyph = self.glyph_cache.get(first.char);
+        let Some(run) = glyph.layout_runs().next() else { return };
+        let Some(layout_glyph) = run.glyphs.first() else { return };
+        let color = style.decoration_color
+            .or(layout_glyph.color_opt.map(|color| Color::rgba8(color.r(), color.g(), color.b(), color.a())))
+            .unwrap_or(Color::BLACK);
+        let font_size   = layout_glyph.font_size as f64;
+        let thickness   = (font_size / 14.).max(1.);
+        let baseline    = aligned_origin_y(glyph, point.y, line_height, Some(style)) + run.line_y as f64;
+        let x1          = point.x + self.text_width(text);
+        let mut offsets = Vec::with_capacity(3);
+        if style.decoration.contains(TextDecorationLine::Underline)     { offsets.push(font_size * 0.1) }
+        if style.decoration.contains(TextDecorationLine::LineThrough)   { offsets.push(-font_size * 0.3) }
+        if style.decoration.contains(TextDecorationLine::Overline)      { offsets.push(-font_size * 0.8) }
+        for offset in offsets {
+            let rect = Rect::new(point.x, baseline + offset, x1, baseline + offset + thickness);
+            cx.fill(&rect, color, 0.);
         }
-        render_state.line_index += 1;
+    }
+
+    // Fills one box per column around where pagination found the block's lines on this page,
+    // the table view being a single column the whole block is in
+    fn paint_block_decoration(&self, cx: &mut PaintCx, elem: &Elem, decoration: &BlockDecoration, render_state: &RenderState, index: &[usize]) {
+        let mut placements = match &self.page_boxes {
+            Some(boxes) => boxes.get(index).cloned().unwrap_or_default(),
+            None        => vec![LinePlacement { col_x: self.col_gap, y: self.column_y(elem.point.y, render_state), height: elem.size.height, doc_y: elem.point.y }],
+        };
+        placements.sort_by(|a, b| a.col_x.total_cmp(&b.col_x).then(a.y.total_cmp(&b.y)));
+
+        for column in placements.chunk_by(|a, b| a.col_x == b.col_x) {
+            self.paint_box(cx, elem, decoration, column);
+            for cell in &decoration.cells {
+                self.paint_box(cx, elem, cell, column);
+            }
+        }
+    }
+
+    // One column's segment of a decoration box, top and bottom borders only where the box starts and ends
+    fn paint_box(&self, cx: &mut PaintCx, elem: &Elem, decoration: &BlockDecoration, column: &[LinePlacement]) {
+        let box_top     = elem.point.y + decoration.margin_top;
+        let box_bottom  = elem.point.y + elem.size.height - decoration.margin_bottom;
+        let [top_border, right_border, bottom_border, left_border] = decoration.borders;
+        let first   = &column[0];
+        let last    = &column[column.len() - 1];
+        let top     = first.y - (first.doc_y - box_top);
+        let bottom  = last.y + last.height + (box_bottom - last.doc_y - last.height);
+        let starts  = top >= 0.;
+        let ends    = bottom <= self.size.height;
+        let rect    = Rect::new(first.col_x + decoration.x, top.max(0.), first.col_x + decoration.x + decoration.width, bottom.min(self.size.height));
+
+        // Corners are only rounded where the box starts and ends, not where a column cuts it
+        let [top_left, top_right, bottom_right, bottom_left] = decoration.radii;
+        let radii   = RoundedRectRadii::new(
+            if starts { top_left } else { 0. },
+            if starts { top_right } else { 0. },
+            if ends { bottom_right } else { 0. },
+            if ends { bottom_left } else { 0. },
+        );
+        let rounded = radii.top_left > 0. || radii.top_right > 0. || radii.bottom_right > 0. || radii.bottom_left > 0.;
+        if let Some(background) = decoration.background {
+            match rounded {
+                true    => cx.fill(&RoundedRect::from_rect(rect, radii), background, 0.),
+                false   => cx.fill(&rect, background, 0.),
+            }
+        }
+        // A uniform border follows the rounded corners, others are drawn square
+        let uniform = decoration.borders.iter().all(|border| border.width == left_border.width && border.color == left_border.color);
+        if rounded && uniform && starts && ends && left_border.width > 0. {
+            let inset = left_border.width / 2.;
+            let shape = RoundedRect::from_rect(rect.inset(-inset), radii);
+            cx.stroke(&shape, left_border.color, &Stroke::new(left_border.width));
+            return
+        }
+        if left_border.width > 0.   { cx.fill(&Rect::new(rect.x0, rect.y0, rect.x0 + left_border.width, rect.y1), left_border.color, 0.) }
+        if right_border.width > 0.  { cx.fill(&Rect::new(rect.x1 - right_border.width, rect.y0, rect.x1, rect.y1), right_border.color, 0.) }
+        if starts && top_border.width > 0.      { cx.fill(&Rect::new(rect.x0, rect.y0, rect.x1, rect.y0 + top_border.width), top_border.color, 0.) }
+        if ends && bottom_border.width > 0.     { cx.fill(&Rect::new(rect.x0, rect.y1 - bottom_border.width, rect.x1, rect.y1), bottom_border.color, 0.) }
+    }
+
+    fn close_table(&mut self) {
+        self.open_table.set(None);
+        self.table_pan  = Point::default();
+        self.table_zoom = 1.0;
+        self.table_drag = None;
+    }
+
+    // Dragging and scrolling pan, ctrl with the wheel or +/- zooms, escape or a click beside the table closes it
+    fn table_event(&mut self, event: &Event) {
+        let zoom = self.scale * self.table_zoom;
+        match event {
+            Event::KeyDown(event) => match &event.key.logical_key {
+                Key::Named(NamedKey::Escape)        => self.close_table(),
+                Key::Named(NamedKey::ArrowRight)    => self.table_pan.x -= TABLE_PAN_STEP / zoom,
+                Key::Named(NamedKey::ArrowLeft)     => self.table_pan.x += TABLE_PAN_STEP / zoom,
+                Key::Named(NamedKey::ArrowDown)     => self.table_pan.y -= TABLE_PAN_STEP / zoom,
+                Key::Named(NamedKey::ArrowUp)       => self.table_pan.y += TABLE_PAN_STEP / zoom,
+                Key::Character(str) if str.eq("+")  => self.zoom_table(TABLE_ZOOM_STEP),
+                Key::Character(str) if str.eq("-")  => self.zoom_table(-TABLE_ZOOM_STEP),
+                _ => ()
+            },
+            Event::PointerWheel(event) => {
+                if event.modifiers.control() {
+                    self.zoom_table(if event.delta.y < 0. { TABLE_ZOOM_STEP } else { -TABLE_ZOOM_STEP })
+                }
+                else if event.modifiers.shift() {
+                    self.table_pan.x -= event.delta.y / zoom;
+                }
+                else {
+                    self.table_pan.x -= event.delta.x / zoom;
+                    self.table_pan.y -= event.delta.y / zoom;
+                }
+            }
+            Event::PointerDown(event) => {
+                self.table_drag     = Some(event.pos);
+                self.press_location = Some(event.pos);
+            }
+            Event::PointerMove(event) => {
+                if let Some(last) = self.table_drag {
+                    self.table_pan  += (event.pos - last) / zoom;
+                    self.table_drag = Some(event.pos);
+                }
+            }
+            Event::PointerUp(event) => {
+                let clicked = self.press_location.map_or(false, |press| press.distance(event.pos) < 4.);
+                self.table_drag = None;
+                self.press_location = None;
+                if clicked && !self.table_bounds.contains(event.pos) { self.close_table() }
+            }
+            _ => ()
+        }
+    }
+
+    fn zoom_table(&mut self, step: f64) {
+        self.table_zoom = (self.table_zoom + step).clamp(MIN_TABLE_ZOOM, MAX_TABLE_ZOOM);
+    }
+
+    fn close_image(&mut self) {
+        self.open_image.set(None);
+        self.image_fit  = true;
+        self.image_drag = None;
+    }
+
+    // The wheel zooms around the pointer and dragging pans, f fits the image to the view and 1 shows it at actual size.
+    // A click on the image switches between the two, escape or a click beside it closes it.
+    fn image_event(&mut self, event: &Event) {
+        match event {
+            Event::KeyDown(event) => match &event.key.logical_key {
+                Key::Named(NamedKey::Escape)        => self.close_image(),
+                Key::Named(NamedKey::ArrowRight)    => self.image_pan.x -= TABLE_PAN_STEP,
+                Key::Named(NamedKey::ArrowLeft)     => self.image_pan.x += TABLE_PAN_STEP,
+                Key::Named(NamedKey::ArrowDown)     => self.image_pan.y -= TABLE_PAN_STEP,
+                Key::Named(NamedKey::ArrowUp)       => self.image_pan.y += TABLE_PAN_STEP,
+                Key::Character(str) if str.eq("+")  => self.zoom_image(self.image_zoom * IMAGE_ZOOM_STEP, self.image_bounds.center()),
+                Key::Character(str) if str.eq("-")  => self.zoom_image(self.image_zoom / IMAGE_ZOOM_STEP, self.image_bounds.center()),
+                Key::Character(str) if str.eq("f")  => self.image_fit = true,
+                Key::Character(str) if str.eq("1")  => self.zoom_image(1., self.image_bounds.center()),
+                _ => ()
+            },
+            Event::PointerWheel(event) => {
+                let zoom = if event.delta.y < 0. { self.image_zoom * IMAGE_ZOOM_STEP } else { self.image_zoom / IMAGE_ZOOM_STEP };
+                self.zoom_image(zoom, event.pos);
+            }
+            Event::PointerDown(event) => {
+                self.image_drag     = Some(event.pos);
+                self.press_location = Some(event.pos);
+            }
+            Event::PointerMove(event) => {
+                if let Some(last) = self.image_drag {
+                    self.image_pan  += event.pos - last;
+                    self.image_drag = Some(event.pos);
+                }
+            }
+            Event::PointerUp(event) => {
+                let clicked = self.press_location.map_or(false, |press| press.distance(event.pos) < 4.);
+                self.image_drag = None;
+                self.press_location = None;
+                if !clicked { return }
+                match (self.image_bounds.contains(event.pos), self.image_fit) {
+                    (false, _)      => self.close_image(),
+                    (true, true)    => self.zoom_image(1., event.pos),
+                    (true, false)   => self.image_fit = true,
+                }
+            }
+            _ => ()
+        }
+    }
+
+    // Keeps the point of the image under `anchor` where it is
+    fn zoom_image(&mut self, zoom: f64, anchor: Point) {
+        let zoom        = zoom.clamp(MIN_IMAGE_ZOOM, MAX_IMAGE_ZOOM);
+        let image_point = (anchor - self.image_pan) / self.image_zoom;
+        self.image_pan  = anchor - image_point * zoom;
+        self.image_zoom = zoom;
+        self.image_fit  = false;
+    }
+
+    // The decoded image at its own size, not the size it was laid out to, on a dark backdrop over the page
+    fn paint_image_view(&mut self, cx: &mut PaintCx, image: ImageElem) {
+        let view_size   = self.id.get_size().unwrap();
+        let size        = natural_size(&image);
+        if self.image_fit {
+            self.image_zoom = (view_size.width / size.width).min(view_size.height / size.height);
+        }
+        let shown       = size * self.image_zoom;
+        // Images smaller than the view stay centred, larger ones can be panned up to their edges
+        self.image_pan.x = match shown.width < view_size.width {
+            true    => (view_size.width - shown.width) / 2.,
+            false   => self.image_pan.x.clamp(view_size.width - shown.width, 0.),
+        };
+        self.image_pan.y = match shown.height < view_size.height {
+            true    => (view_size.height - shown.height) / 2.,
+            false   => self.image_pan.y.clamp(view_size.height - shown.height, 0.),
+        };
+        self.image_bounds = Rect::from_origin_size(self.image_pan, shown);
+
+        cx.fill(&Rect::from_origin_size(Point::ZERO, view_size), Color::rgb8(32, 32, 32), 0.);
+        cx.clip(&Rect::from_origin_size(Point::ZERO, view_size));
+        if let Some(svg) = &image.svg {
+            cx.draw_svg(Svg {tree: &svg.tree, hash: &svg.hash}, self.image_bounds, None::<Color>);
+        }
+        else if let Some(decoded) = image.image_promise.read().unwrap().deref() {
+            cx.draw_img(Img {img: decoded.0.clone(), hash: &decoded.1}, self.image_bounds);
+        }
+        cx.clear_clip();
+    }
+
+    // The table is painted as one tall column wider than the view, so the page's line painting carries over unchanged
+    fn paint_table_view(&mut self, cx: &mut PaintCx, table_index: usize) {
+        let url         = self.read_current_url.get();
+        let view_size   = self.id.get_size().unwrap();
+        let Some(table_size) = self.pages.get(&url).unwrap().tables.get(table_index).map(|table| table.size) else {
+            self.close_table();
+            return
+        };
+        let zoom        = self.scale * self.table_zoom;
+        let visible     = Size::new(view_size.width / zoom, view_size.height / zoom);
+        // Tables smaller than the view stay centred, larger ones can be panned up to their edges
+        self.table_pan.x = match table_size.width < visible.width {
+            true    => (visible.width - table_size.width) / 2.,
+            false   => self.table_pan.x.clamp(visible.width - table_size.width, 0.),
+        };
+        self.table_pan.y = match table_size.height < visible.height {
+            true    => (visible.height - table_size.height) / 2.,
+            false   => self.table_pan.y.clamp(visible.height - table_size.height, 0.),
+        };
+        self.table_bounds = Rect::from_origin_size(self.table_pan, table_size).scale_from_origin(zoom);
+
+        self.size               = Size::new(f64::MAX, f64::MAX);
+        self.col_gap            = 0.;
+        self.start_offset_y     = 0.;
+        self.page_boxes         = None;
+        // A line index the reading guide never reaches keeps it on the page
+        let mut render_state            = RenderState::new(None);
+        render_state.line_index         = isize::MIN;
+        render_state.first_line_rendered = true;
+
+        cx.fill(&Rect::from_origin_size(Point::ZERO, view_size), Color::WHITE, 0.);
+        cx.clip(&Rect::from_origin_size(Point::ZERO, view_size));
+        cx.set_scale(zoom);
+        cx.offset((self.point.x / zoom - self.point.x + self.table_pan.x, self.point.y / zoom - self.point.y + self.table_pan.y));
+        let table = &self.pages.get(&url).unwrap().tables[table_index];
+        self.paint_recursive(cx, table, render_state, 0, Vec::new(), true);
+        cx.set_scale(1.0);
+        cx.clear_clip();
+    }
+
+    // The current page, the section paginated first when it or the view changed
+    fn paint_paged(&mut self, cx: &mut PaintCx) -> RenderState {
+        let url                 = self.read_current_url.get();
+        self.size               = self.id.get_size().unwrap();
+        self.size.width         /= self.scale;
+        self.size.height        /= self.scale;
+        self.col_count          = (self.size.width / self.col_width).floor();
+        self.col_gap            = (self.size.width - self.col_count * self.col_width) / (self.col_count + 1.);
+        if self.pagination.as_ref().map_or(true, |pagination| pagination.url != url || pagination.size != self.size) {
+            self.pagination = Some(self.paginate(cx, url.clone()));
+        }
+        let root_elem           = &self.pages.get(&url).unwrap().root;
+        let pagination          = self.pagination.as_ref().unwrap();
+        // Positions set from outside, by a link, the table of contents or a restored bookmark, land on the page holding them
+        let target              = self.start_index.get();
+        self.page               = match self.page {
+            LAST_PAGE => pagination.pages.len() - 1,
+            page if pagination.pages.get(page).is_some_and(|page| page.start_index == target) => page,
+            _ => locate_page(pagination, root_elem, &target),
+        };
+        let page                = pagination.pages[self.page].clone();
+        if page.start_index != target { self.start_index.set(page.start_index.clone()) }
+        self.start_elem_index   = page.start_elem_index;
+        self.start_offset_y     = page.start_offset_y;
+        self.page_boxes         = Some(page.boxes);
+        let mut render_state    = RenderState::new(self.selection.clone());
+        render_state.position.section = self.sections.iter().position(|section| *section == url).unwrap_or_default();
+        let scaling_offset_x    = self.point.x / self.scale - self.point.x;
+        let scaling_offset_y    = self.point.y / self.scale - self.point.y;
+        cx.set_scale(self.scale);
+        cx.offset((scaling_offset_x, scaling_offset_y));
+
+        let (render_state, _, _) = self.paint_recursive(cx, root_elem, render_state, 0, page.start_index, true);
         render_state
     }
 
-    fn paint_backward(&self, cx: &mut PaintCx, elem: &Elem, mut render_state: RenderState, level: usize, mut index: Vec<usize>) -> (RenderState, Vec<usize>, usize){
-        let mut elem_index = 0;
+    // The pages of a spread side by side in the middle of the view, scaled alike from their viewports until the spread fits
+    fn paint_fixed(&mut self, cx: &mut PaintCx, spread: Vec<Option<usize>>) -> RenderState {
+        let view            = self.id.get_size().unwrap();
+        let viewports: Vec<Option<Size>> = spread.iter()
+            .map(|slot| slot.and_then(|position| self.pages.get(&self.sections[position])).and_then(|page| page.fixed.as_ref()).map(|fixed| fixed.viewport))
+            .collect();
+        // An empty slot takes the room of the page beside it
+        let filler          = viewports.iter().flatten().next().copied().unwrap_or(Size::new(1., 1.));
+        let width: f64      = viewports.iter().map(|viewport| viewport.unwrap_or(filler).width).sum();
+        let height          = viewports.iter().map(|viewport| viewport.unwrap_or(filler).height).fold(0., f64::max);
+        let scale           = (view.width / width).min(view.height / height);
+        let mut x           = (view.width - width * scale) / 2.;
+        let mut render_state = RenderState::new(self.selection.clone());
+        for (slot, viewport) in spread.into_iter().zip(viewports) {
+            let viewport = viewport.unwrap_or(filler);
+            if let Some(position) = slot {
+                let origin      = Point::new(x, (view.height - viewport.height * scale) / 2.);
+                render_state    = self.paint_fixed_page(cx, position, viewport, origin, scale, render_state);
+            }
+            x += viewport.width * scale;
+        }
+        self.col_width      = self.orig_col_width;
+        render_state
+    }
+
+    // Paints one page of a spread in its own clip, the click taken over into the page's coordinates while it is painted
+    fn paint_fixed_page(&mut self, cx: &mut PaintCx, position: usize, viewport: Size, origin: Point, scale: f64, mut render_state: RenderState) -> RenderState {
+        let to_pointer      = Affine::scale(1. / self.scale) * Affine::translate(origin.to_vec2()) * Affine::scale(scale);
+        let click_location  = self.click_location;
+        self.click_location = self.click_location.map(|location| to_pointer.inverse() * location);
+        self.size           = viewport;
+        self.col_width      = viewport.width;
+        self.col_gap        = 0.;
+        render_state.to_pointer         = to_pointer;
+        render_state.position.section   = position;
+
+        cx.save();
+        cx.set_scale(scale);
+        cx.offset((self.point.x / scale - self.point.x + origin.x / scale, self.point.y / scale - self.point.y + origin.y / scale));
+        cx.clip(&Rect::from_origin_size(Point::ZERO, viewport));
+        let root            = &self.pages.get(&self.sections[position]).unwrap().root;
+        render_state        = self.paint_scroll(cx, root, 0., &mut Vec::new(), render_state);
+        cx.restore();
+        self.click_location     = click_location;
+        render_state.to_pointer = Affine::IDENTITY;
+        render_state
+    }
+
+    // One column down the middle of the view, the section scrolled to `scroll_top` and those after it below its end
+    fn paint_scrolled(&mut self, cx: &mut PaintCx) -> RenderState {
+        self.size               = self.id.get_size().unwrap();
+        self.size.width         /= self.scale;
+        self.size.height        /= self.scale;
+        self.col_count          = 1.;
+        self.col_gap            = ((self.size.width - self.col_width) / 2.).max(0.);
+        let mut url             = self.read_current_url.get();
+        // Positions set from outside, by a link, the table of contents or the paged view, scroll to their elem
+        let target              = self.start_index.get();
+        if url != self.scroll_url || target != self.scroll_index {
+            self.scroll_top = self.pages.get(&url).unwrap().root.get_elem(&target, 0).get_y(0);
+        }
+        // Scrolled past either end of the section, reading carries on in the one next to it
+        loop {
+            let position    = self.sections.iter().position(|section| *section == url);
+            let height      = section_height(self.pages.get(&url).unwrap());
+            let previous    = position.and_then(|position| position.checked_sub(1)).and_then(|position| self.sections.get(position));
+            let next        = position.and_then(|position| self.sections.get(position + 1));
+            match (previous, next) {
+                (Some(previous), _) if self.scroll_top < 0. => {
+                    url = previous.clone();
+                    self.scroll_top += section_height(self.pages.get(&url).unwrap());
+                }
+                (_, Some(next)) if self.scroll_top >= height => {
+                    self.scroll_top -= height;
+                    url = next.clone();
+                }
+                (_, next) => {
+                    // The view stops at the end of the book
+                    if next.is_none() { self.scroll_top = self.scroll_top.min(height - self.size.height) }
+                    self.scroll_top     = self.scroll_top.max(0.);
+                    self.scroll_height  = height;
+                    break
+                }
+            }
+        }
+        if url != self.read_current_url.get_untracked() { self.read_current_url.set(url.clone()) }
+        let index               = self.pages.get(&url).unwrap().root.index_at(self.scroll_top);
+        if index != target { self.start_index.set(index.clone()) }
+        self.scroll_index       = index;
+        self.scroll_url         = url.clone();
+
+        let mut render_state    = RenderState::new(self.selection.clone());
+        let scaling_offset_x    = self.point.x / self.scale - self.point.x;
+        let scaling_offset_y    = self.point.y / self.scale - self.point.y;
+        cx.set_scale(self.scale);
+        cx.offset((scaling_offset_x, scaling_offset_y));
+        cx.clip(&Rect::from_origin_size(Point::ZERO, self.size));
+        let mut top             = self.scroll_top;
+        let mut position        = self.sections.iter().position(|section| *section == url);
+        let mut page            = self.pages.get(&url);
+        while let Some(section) = page {
+            render_state.position.section = position.unwrap_or_default();
+            render_state    = self.paint_scroll(cx, &section.root, top, &mut Vec::new(), render_state);
+            top             -= section_height(section);
+            if -top >= self.size.height { break }
+            position        = position.map(|position| position + 1);
+            page            = position.and_then(|position| self.sections.get(position)).and_then(|url| self.pages.get(url));
+        }
+        self.paint_scrollbar(cx);
+        cx.clear_clip();
+        render_state
+    }
+
+    // Paints the elems overlapping the view, each line `top` above where it sits in its section
+    fn paint_scroll(&self, cx: &mut PaintCx, elem: &Elem, top: f64, index: &mut Vec<usize>, mut render_state: RenderState) -> RenderState {
+        if elem.point.y - top > self.size.height || elem.point.y + elem.size.height - top < 0. { return render_state }
+        if let Some(decoration) = &elem.decoration {
+            let column = [LinePlacement { col_x: self.col_gap, y: elem.point.y - top, height: elem.size.height, doc_y: elem.point.y }];
+            self.paint_box(cx, elem, decoration, &column);
+            for cell in &decoration.cells {
+                self.paint_box(cx, elem, cell, &column);
+            }
+        }
         match &elem.elem_type {
             ElemType::Block(block) => {
-                if block.children.len() == 0    { return (render_state, index, elem_index) }
-                if index.len() <= level         { index.push(block.children.len() - 1); }
-                for child in block.children.iter().take(index[level] + 1).rev() {
-                    (render_state, index, elem_index) = self.paint_backward(cx, child, render_state, level + 1, index);
-                    if render_state.terminate       {return (render_state, index, elem_index); }
-                    if index[level] != 0            { index[level] -= 1; }
+                for (position, child) in block.visible_children().iter().enumerate() {
+                    index.push(position);
+                    render_state = self.paint_scroll(cx, child, top, index, render_state);
+                    index.pop();
                 }
-                index.pop();
             }
             ElemType::Lines(lines) => {
-                let mut line_offset_y   = elem.size.height;
-                /*let mut dummy_state     = render_state.clone();
-                for line in lines.elem_lines.iter().rev() {
-                    line_offset_y -= line.height;
-                    dummy_state  = self.paint_line(cx, &elem, &line, line_offset_y, dummy_state , false);
-                    if dummy_state.terminate       { return (dummy_state, index); }
-                }*/
-                line_offset_y = elem.size.height;
-                let mut current_elem_index: usize = lines.elem_lines.iter().map(|s| s.inline_elems.len()).sum();
-                for line in lines.elem_lines.iter().rev() {
-                    line_offset_y -= line.height;
-                    if self.end_elem_index.get() == 0 || self.end_elem_index.get() > current_elem_index - line.inline_elems.len() {render_state.first_line_rendered = true;}
-                    if render_state.first_line_rendered {
-
-                        render_state = self.paint_line(cx, &elem, &line, line_offset_y, render_state, true);
-                        if render_state.terminate {return (render_state, index, current_elem_index)}
+                let mut line_offset_y = 0.;
+                let mut glyph_offset = 0;
+                for line in lines.elem_lines.iter() {
+                    let y = elem.point.y + line_offset_y - top;
+                    if y + line.height > 0. && y < self.size.height {
+                        render_state.start_line(index, glyph_offset);
+                        render_state = self.draw_line(cx, line, Point::new(self.col_gap + elem.point.x, y), line.height, 1., false, render_state);
                     }
-                    current_elem_index -= line.inline_elems.len();
-                    
+                    line_offset_y += line.height;
+                    glyph_offset += text_glyphs(line).count();
                 }
             }
         }
-        render_state.first_line_rendered = true;
-        (render_state, index, elem_index)
+        render_state
+    }
+
+    // A track down the right edge, the thumb as long as the share of the section in view
+    fn paint_scrollbar(&self, cx: &mut PaintCx) {
+        let (thumb_top, thumb_height) = self.scrollbar_thumb();
+        let x = self.size.width - SCROLLBAR_WIDTH;
+        cx.fill(&Rect::new(x, 0., self.size.width, self.size.height), Color::rgb8(240, 240, 240), 0.);
+        cx.fill(&RoundedRect::new(x + 1., thumb_top, self.size.width - 1., thumb_top + thumb_height, SCROLLBAR_WIDTH / 2.), Color::rgb8(170, 170, 170), 0.);
     }
 
-    fn paint_recursive(&self, cx: &mut PaintCx, elem: &Elem, mut render_state: RenderState, level: usize, mut index: Vec<usize>) -> (RenderState, Vec<usize>, usize){
+    fn scrollbar_thumb(&self) -> (f64, f64) {
+        let range           = (self.scroll_height - self.size.height).max(0.);
+        let thumb_height    = (self.size.height * self.size.height / self.scroll_height.max(1.)).clamp(MIN_THUMB_HEIGHT.min(self.size.height), self.size.height);
+        let fraction        = if range > 0. { (self.scroll_top / range).min(1.) } else { 0. };
+        (fraction * (self.size.height - thumb_height), thumb_height)
+    }
+
+    // Scrolls so the middle of the thumb is at `y`
+    fn drag_scrollbar(&mut self, y: f64) {
+        let (_, thumb_height) = self.scrollbar_thumb();
+        let fraction        = ((y - thumb_height / 2.) / (self.size.height - thumb_height).max(1.)).clamp(0., 1.);
+        self.scroll_top     = fraction * (self.scroll_height - self.size.height).max(0.);
+    }
+
+    // Wheel and scrollbar input while scrolling, true when the event was used
+    fn scroll_event(&mut self, event: &Event) -> bool {
+        match event {
+            Event::PointerWheel(event) => self.scroll_top += event.delta.y / self.scale,
+            Event::PointerDown(event) if event.pos.x / self.scale >= self.size.width - SCROLLBAR_WIDTH => {
+                self.scrollbar_drag = true;
+                self.drag_scrollbar(event.pos.y / self.scale);
+            }
+            Event::PointerMove(event) if self.scrollbar_drag => self.drag_scrollbar(event.pos.y / self.scale),
+            Event::PointerUp(_) if self.scrollbar_drag => self.scrollbar_drag = false,
+            _ => return false,
+        }
+        true
+    }
+
+    // Lays the section out page after page without drawing, each page starting where the one before it ended
+    fn paginate(&mut self, cx: &mut PaintCx, url: String) -> Pagination {
+        let root_elem           = &self.pages.get(&url).unwrap().root;
+        let mut pages           = Vec::new();
+        let mut start_index     = Vec::new();
+        let mut start_elem_index = 0;
+        loop {
+            self.start_elem_index   = start_elem_index;
+            self.start_offset_y     = root_elem.get_elem(&start_index, 0).get_y(start_elem_index);
+            let mut render_state    = RenderState::new(None);
+            render_state.placements = Some(HashMap::new());
+            let (mut render_state, end_index, end_elem_index) = self.paint_recursive(cx, root_elem, render_state, 0, start_index.clone(), false);
+            // A page that takes nothing would be followed by itself forever
+            let stuck = end_index == start_index && end_elem_index == start_elem_index;
+            let boxes = Rc::new(render_state.placements.take().unwrap_or_default());
+            pages.push(Page { start_index, start_elem_index, start_offset_y: self.start_offset_y, boxes });
+            if !render_state.terminate || stuck { break }
+            (start_index, start_elem_index) = (end_index, end_elem_index);
+        }
+        Pagination { url, size: self.size, pages }
+    }
+
+    // How far down its column a point of the document lands
+    fn column_y(&self, doc_y: f64, render_state: &RenderState) -> f64 {
+        let y = doc_y + render_state.y - self.start_offset_y;
+        y - (y / self.size.height).floor() * self.size.height
+    }
+
+    // Content that fits in a column but would be cut by a column end is moved whole to one side of it
+    fn keep_together(&self, top: f64, height: f64, mut render_state: RenderState) -> RenderState {
+        if height > self.size.height { return render_state }
+        let column_y    = self.column_y(top, &render_state);
+        if column_y + height <= self.size.height { return render_state }
+        render_state.y += self.size.height - column_y;
+        render_state
+    }
+
+    // Moves content starting at `doc_y` to the top of the next column, past the page when it is the last
+    fn next_column(&self, doc_y: f64, mut render_state: RenderState) -> RenderState {
+        let column_y = self.column_y(doc_y, &render_state);
+        if column_y > 0. { render_state.y += self.size.height - column_y }
+        render_state
+    }
+
+    // Lines of a paragraph, painted from `first` down, that start a new column earlier than they have to
+    fn line_breaks(&self, elem: &Elem, lines: &[ElemLine], first: usize, offset_y: f64, render_state: &RenderState) -> Vec<usize> {
+        let column_y    = self.column_y(elem.point.y + offset_y, render_state);
+        let heights: Vec<f64> = lines.iter().map(|line| line.height.min(self.size.height)).collect();
+        forced_breaks(&heights, first, column_y, self.size.height, render_state.orphans, render_state.widows)
+    }
+
+    fn paint_recursive(&self, cx: &mut PaintCx, elem: &Elem, mut render_state: RenderState, level: usize, mut index: Vec<usize>, render: bool) -> (RenderState, Vec<usize>, usize){
+        if let Some(decoration) = &elem.decoration {
+            let key = &index[..level.min(index.len())];
+            match &mut render_state.placements {
+                Some(placements)    => { placements.entry(key.to_vec()).or_default(); }
+                None if render      => self.paint_block_decoration(cx, elem, decoration, &render_state, key),
+                None                => (),
+            }
+        }
         let mut elem_index = 0;
         match &elem.elem_type {
+            ElemType::Block(block) if block.row => return self.paint_row(cx, elem, block, render_state, level, index, render),
             ElemType::Block(block) => {
-                if index.len() <= level { index.push(0);}
-                for child in block.children.iter().skip(index[level]) {
-                    (render_state, index, elem_index) = self.paint_recursive(cx, child, render_state, level + 1, index);
+                if index.len() <= level {
+                    match block.fragmentation.break_before {
+                        // The block starts the next page unless this one is still empty
+                        Break::Page if render_state.line_index > 0 => {
+                            render_state.terminate = true;
+                            return (render_state, index, elem_index)
+                        }
+                        Break::Column => render_state = self.next_column(elem.point.y, render_state),
+                        _ => ()
+                    }
+                    if block.fragmentation.break_inside == Break::Avoid { render_state = self.keep_together(elem.point.y, elem.size.height, render_state) }
+                    index.push(0);
+                }
+                let children = block.visible_children();
+                for (position, child) in children.iter().enumerate().skip(index[level]) {
+                    // A heading moves on with the first lines after it rather than ending a column alone
+                    if let ElemType::Block(child_block) = &child.elem_type {
+                        let next = children.get(position + 1)
+                            .filter(|next| !matches!(&next.elem_type, ElemType::Block(next_block) if next_block.fragmentation.break_before.is_forced()));
+                        if child_block.fragmentation.break_after == Break::Avoid && index.len() <= level + 1 {
+                            if let Some(bottom) = next.and_then(|next| leading_bottom(next, block.fragmentation.orphans)) {
+                                render_state = self.keep_together(child.point.y, bottom - child.point.y, render_state);
+                            }
+                        }
+                    }
+                    (render_state.orphans, render_state.widows) = (block.fragmentation.orphans, block.fragmentation.widows);
+                    (render_state, index, elem_index) = self.paint_recursive(cx, child, render_state, level + 1, index, render);
                     if render_state.terminate           { return (render_state, index, elem_index); }
                     match child.elem_type {
                         ElemType::Block(_) => {index[level] += 1;}
@@ -374,13 +1127,20 @@ impl HtmlRenderer {
                 }*/
                 line_offset_y = 0.;
                 let mut current_elem_index = 0;
-                for line in lines.elem_lines.iter() {
-                    if self.start_elem_index.get() < current_elem_index + line.inline_elems.len() {render_state.first_line_rendered = true}
-                    if render_state.first_line_rendered  {
-                        render_state  = self.paint_line(cx, &elem, &line, line_offset_y, render_state , true);
+                let mut glyph_offset = 0;
+                let mut forced_breaks = None;
+                for (position, line) in lines.elem_lines.iter().enumerate() {
+                    if self.start_elem_index < current_elem_index + line.inline_elems.len() {render_state.first_line_rendered = true}
+                    let painted_before = render_state.skip_above.is_some_and(|top| elem.point.y + line_offset_y + 0.5 < top);
+                    if render_state.first_line_rendered && !painted_before {
+                        let forced = forced_breaks.get_or_insert_with(|| self.line_breaks(elem, &lines.elem_lines, position, line_offset_y, &render_state));
+                        if forced.contains(&position) { render_state = self.next_column(elem.point.y + line_offset_y, render_state) }
+                        if render || render_state.placements.is_some() { render_state.start_line(&index[..level.min(index.len())], glyph_offset) }
+                        render_state  = self.paint_line(cx, &elem, &line, line_offset_y, render_state , render);
                         if render_state .terminate       { return (render_state, index, current_elem_index);}
                     }
                     current_elem_index += line.inline_elems.len();
+                    glyph_offset += text_glyphs(line).count();
                     line_offset_y += line.height;
                 }
             }
@@ -389,11 +1149,62 @@ impl HtmlRenderer {
         (render_state, index, elem_index)
     }
 
+    // The cells of a table row, each painted down from the top of the row so they stand side by side, and what follows
+    // the row goes below the cell that reached furthest. A row cut by the page end is taken up again on the next page
+    // from the highest line left in any of its cells, the lines above it in the others having been painted already.
+    fn paint_row(&self, cx: &mut PaintCx, elem: &Elem, block: &BlockElem, mut render_state: RenderState, level: usize, index: Vec<usize>, render: bool) -> (RenderState, Vec<usize>, usize) {
+        if index.len() > level {
+            render_state.first_line_rendered    = true;
+            render_state.skip_above             = Some(self.start_offset_y);
+        } else {
+            render_state = self.keep_together(elem.point.y, elem.size.height, render_state);
+        }
+        let mut index   = index[..level].to_vec();
+        let top         = (render_state.y, render_state.col_index);
+        let mut bottom  = top;
+        let mut cut: Option<(f64, Vec<usize>, usize)> = None;
+        for (position, cell) in block.children.iter().enumerate() {
+            (render_state.y, render_state.col_index, render_state.terminate) = (top.0, top.1, false);
+            index.push(position);
+            let (cell_state, cell_index, elem_index) = self.paint_recursive(cx, cell, render_state, level + 1, index, render);
+            render_state = cell_state;
+            if render_state.terminate {
+                let y = cell.get_elem(&cell_index, level + 1).get_y(elem_index);
+                if cut.as_ref().map_or(true, |(cut_y, _, _)| y < *cut_y) { cut = Some((y, cell_index.clone(), elem_index)) }
+            } else if render_state.y > bottom.0 {
+                bottom = (render_state.y, render_state.col_index);
+            }
+            index = cell_index[..level].to_vec();
+        }
+        render_state.skip_above = None;
+        if let Some((_, cut_index, elem_index)) = cut {
+            render_state.terminate = true;
+            return (render_state, cut_index, elem_index)
+        }
+        (render_state.y, render_state.col_index) = bottom;
+        render_state.first_line_rendered = true;
+        (render_state, index, 0)
+    }
+
 }
 
 impl View for HtmlRenderer {
     fn id(&self) -> ViewId { self.id }
     fn event_before_children(&mut self, cx: &mut EventCx, event: &Event) -> EventPropagation {
+        if self.open_image.get_untracked().is_some() {
+            self.image_event(event);
+            cx.app_state_mut().request_paint(self.id());
+            return EventPropagation::Stop
+        }
+        if self.open_table.get_untracked().is_some() {
+            self.table_event(event);
+            cx.app_state_mut().request_paint(self.id());
+            return EventPropagation::Stop
+        }
+        if self.scroll_mode.get_untracked() && self.current_spread().is_none() && self.scroll_event(event) {
+            cx.app_state_mut().request_paint(self.id());
+            return EventPropagation::Stop
+        }
         match &event {
             Event::KeyDown(event) => {
 
@@ -405,35 +1216,21 @@ impl View for HtmlRenderer {
                             if str.eq("c") {
                                 self.copy = true;
                             }
-                            if str.eq("+") || str.eq("-") {
-                                let orig_scale = self.scale;
-                                let orig_move_x = self.move_location.x * self.scale;
-                                let orig_move_y = self.move_location.y * self.scale;
-                                let mut orig_press_x = 0.;
-                                let mut orig_press_y = 0.;
-                                if let Some(mut loc) = self.press_location  {
-                                    orig_press_x = loc.x * self.scale;
-                                    orig_press_y = loc.y * self.scale;
-                                }
-                                if str.eq("+") {
-                                    self.scale += 0.1;
-                                }
-                                if str.eq("-") {
-                                    self.scale -= 0.1;
-                                }
-                                let scale_change = orig_scale - self.scale;
-                                if let Some(mut loc) = self.press_location  {
-                                   loc.x = orig_press_x / self.scale;
-                                    loc.y = orig_press_y / self.scale;
-                                }
-                                self.move_location.x = orig_move_x / self.scale;
-                                self.move_location.y = orig_move_y / self.scale;
+                            // The selection is kept as text positions, so it stays on its text at any zoom
+                            if str.eq("+") {
+                                self.scale += 0.1;
+                            }
+                            if str.eq("-") {
+                                self.scale -= 0.1;
                             }
 
                         }
                         _ => ()
                     }
                 }
+                else if event.modifiers.shift() {
+                    if let Key::Named(key) = &event.key.logical_key { self.move_focus(key) }
+                }
                 else {
                     match event.key.logical_key {
                         Key::Named(NamedKey::ArrowRight)    => {self.next()},
@@ -454,23 +1251,17 @@ impl View for HtmlRenderer {
             }
             Event::PointerUp(event) => {
                 self.click_location = Some(Point::new(event.pos.x / self.scale, event.pos.y / self.scale));
-                self.drag_in_progress = false;
                 self.key_press = false;
-                //self.press_location = None;
                 cx.app_state_mut().request_paint(self.id());
             }
             Event::PointerDown(event) => {
-                if self.selection_active {
-                    self.selection_active = false;
-                }
                 self.key_press = true;
-                self.press_location = Some(Point::new(event.pos.x / self.scale, event.pos.y / self.scale));
+                self.select_from(Point::new(event.pos.x / self.scale, event.pos.y / self.scale), event.count, event.modifiers.shift());
+                cx.app_state_mut().request_paint(self.id());
             }
             Event::PointerMove(event) => {
                 if self.key_press {
-                    self.move_location = Point::new(event.pos.x / self.scale, event.pos.y / self.scale);
-                    self.drag_in_progress = true;
-                    self.selection_active = true;
+                    self.drag_selection(Point::new(event.pos.x / self.scale, event.pos.y / self.scale));
                     cx.app_state_mut().request_paint(self.id);
                 }
             }
@@ -484,54 +1275,38 @@ impl View for HtmlRenderer {
         None
     }
     fn paint(&mut self, cx: &mut PaintCx) {
+        // The page's glyphs are hidden under an overlay, a press there must not select them
+        if let Some(image) = self.open_image.get() {
+            self.text_map = TextMap::default();
+            self.paint_image_view(cx, image);
+            return
+        }
+        if let Some(table) = self.open_table.get() {
+            self.text_map = TextMap::default();
+            self.paint_table_view(cx, table);
+            return
+        }
         let now = Instant::now();
-        let root_elem           = &self.pages.get(&self.read_current_url.get()).unwrap().root;
-        self.size               = self.id.get_size().unwrap();
-        self.size.width         /= self.scale;
-        self.size.height        /= self.scale;
-        self.col_count          = (self.size.width / self.col_width).floor();
-        self.col_gap            = (self.size.width - self.col_count * self.col_width) / (self.col_count + 1.);
-        let mut render_state    = RenderState {x: 0., y: 0., col_index: 0., terminate: false, line_index: 0, selected_text: String::new(), first_line_rendered: false, selection: self.get_selection()};
-        let mut start_index     = self.start_index.get();
-        let mut start_elem_index = self.start_elem_index.get_untracked();
-        let mut end_elem_index  = self.end_elem_index.get_untracked();
-        let scaling_offset_x    = self.point.x / self.scale - self.point.x;
-        let scaling_offset_y    = self.point.y / self.scale - self.point.y;
-        cx.set_scale(self.scale);
-        cx.offset((scaling_offset_x, scaling_offset_y));
-
-        if self.render_forward {
-                let first_elem      = root_elem.get_elem(&start_index, 0);
-                self.start_offset_y = first_elem.get_y(self.start_elem_index.get_untracked());
-            (render_state, self.end_index, end_elem_index) = self.paint_recursive(cx, root_elem, render_state, 0, start_index);
-            self.end_elem_index.set(end_elem_index);
-        }
-        else {
-            let last_elem       = root_elem.get_elem(&self.end_index, 0);
-            let content_size    = self.size.height * self.col_count;
-            self.start_offset_y = last_elem.get_y(self.end_elem_index.get_untracked()) - content_size + 1.;
-
-            if self.start_offset_y < 0. {
-                self.start_index.set( Vec::new());
-                start_index = Vec::new();
-                self.start_offset_y = 0.;
-                self.start_elem_index.set(0);
-                self.end_elem_index.set(0);
-                self.render_forward = true;
-                (render_state, self.end_index, end_elem_index) = self.paint_recursive(cx, root_elem, render_state, 0, start_index);
-                self.end_elem_index.set(end_elem_index);
-            }
-            else {
-                (render_state, start_index, start_elem_index) = self.paint_backward(cx, root_elem, render_state, 0, self.end_index.clone());
-                self.start_elem_index.set(start_elem_index);
-                self.start_index.set(start_index);
+        // Everything after the details moves, the page keeps its start and is painted again from there
+        if let Some(path) = self.toggle_details.get_untracked() {
+            self.toggle_details.set(None);
+            if let Some(page) = self.pages.get_mut(&self.read_current_url.get_untracked()) {
+                page.root.toggle_details(&path);
             }
+            self.pagination = None;
         }
+        // Fixed-layout sections are shown a spread at a time in either mode
+        let render_state = match (self.current_spread(), self.scroll_mode.get()) {
+            (Some(spread), _)   => self.paint_fixed(cx, spread),
+            (None, true)        => self.paint_scrolled(cx),
+            (None, false)       => self.paint_paged(cx),
+        };
         if self.copy {
-            println!("Clipboard: {}", render_state.selected_text);
-            Clipboard::set_contents(render_state.selected_text).unwrap();
+            // Without a clipboard to reach the copy is dropped, the selection stays for another try
+            let _ = Clipboard::set_contents(self.selected_text());
             self.copy = false;
         }
+        self.text_map = render_state.text_map;
         cx.set_scale(1.0);
         self.click_location = None;
         //println!("Render time: {}", now.elapsed().as_micros())
@@ -539,3 +1314,155 @@ impl View for HtmlRenderer {
     }
 
 }
+
+/// The glyphs of a line that are text, counted by text positions
+pub(crate) fn text_glyphs(line: &ElemLine) -> impl Iterator<Item = &CharGlyph> {
+    line.inline_elems.iter().flat_map(|inline_elem| match &inline_elem.inline_content {
+        InlineContent::Text(glyphs) | InlineContent::Link((glyphs, _)) => glyphs.as_slice(),
+        _ => &[],
+    })
+}
+
+// Size the image was decoded at, which layout may have shrunk it from, svgs at the size they declare
+fn natural_size(image: &ImageElem) -> Size {
+    if let Some(svg) = &image.svg {
+        let size = svg.tree.size();
+        return Size::new(size.width() as f64, size.height() as f64)
+    }
+    match image.image_promise.read().unwrap().deref() {
+        Some((decoded, _))  => Size::new(decoded.width as f64, decoded.height as f64),
+        None                => Size::new(image.width as f64, image.height as f64),
+    }
+}
+
+// How far down the section's content reaches, its root is never given a size of its own
+fn section_height(page: &HTMLPage) -> f64 {
+    match &page.root.elem_type {
+        ElemType::Block(block)  => block.children.iter().map(|child| child.point.y + child.size.height).fold(0., f64::max),
+        ElemType::Lines(_)      => 0.,
+    }
+}
+
+// Lines of `heights`, laid out from `first` down starting `column_y` into columns `column_height` tall, that start a new column
+// earlier than they have to, so a column end leaves at least `orphans` lines before it and `widows` after it
+fn forced_breaks(heights: &[f64], first: usize, column_y: f64, column_height: f64, orphans: usize, widows: usize) -> Vec<usize> {
+    let mut forced  = Vec::new();
+    let breaks      = column_breaks(heights, first, column_y, column_height, &forced);
+    if first == 0 && breaks.first().is_some_and(|&position| position < orphans) {
+        forced.push(0);
+    }
+    let splits: Vec<usize> = column_breaks(heights, first, column_y, column_height, &forced).into_iter().filter(|&position| position > first).collect();
+    let Some(&last) = splits.last() else { return forced };
+    if heights.len() - last < widows {
+        let earlier = heights.len().saturating_sub(widows);
+        let start   = if splits.len() > 1 { splits[splits.len() - 2] } else { first };
+        if earlier >= start + orphans.max(1) { forced.push(earlier) }
+    }
+    forced
+}
+
+// Where a column end falls between the lines, with the `forced` ones starting a column of their own
+fn column_breaks(heights: &[f64], first: usize, mut column_y: f64, column_height: f64, forced: &[usize]) -> Vec<usize> {
+    let mut breaks = Vec::new();
+    for (position, height) in heights.iter().enumerate().skip(first) {
+        if column_y > 0. && (forced.contains(&position) || column_y + height > column_height) {
+            breaks.push(position);
+            column_y = 0.;
+        }
+        column_y += height;
+    }
+    breaks
+}
+
+// A page starting at `index`, or else the last one starting above it
+fn locate_page(pagination: &Pagination, root_elem: &Elem, index: &Vec<usize>) -> usize {
+    if let Some(page) = pagination.pages.iter().position(|page| page.start_index == *index) { return page }
+    let y = root_elem.get_elem(index, 0).get_y(0);
+    pagination.pages.iter().rposition(|page| page.start_offset_y <= y).unwrap_or(0)
+}
+
+// Where the first `count` lines of an elem end, what a heading before it is kept in a column with
+fn leading_bottom(elem: &Elem, count: usize) -> Option<f64> {
+    match &elem.elem_type {
+        ElemType::Block(block)  => block.visible_children().first().and_then(|child| leading_bottom(child, count)),
+        ElemType::Lines(lines)  => Some(elem.point.y + lines.elem_lines.iter().take(count.max(1)).map(|line| line.height).sum::<f64>()),
+    }
+}
+
+// Glyphs are drawn from the top of their layout, offset so mixed sizes share a baseline
+fn text_origin_y(glyph: &TextLayout, y: f64, line_height: f64) -> f64 {
+    let layout  = glyph.lines().first().unwrap().layout_opt().as_ref().unwrap().first().unwrap();
+    y + line_height * BASELINE_RATIO - layout.max_ascent as f64 - layout.max_descent as f64
+}
+
+// Top and bottom aligned glyphs sit as if their line were only as tall as themselves
+fn aligned_origin_y(glyph: &TextLayout, y: f64, line_height: f64, style: Option<&InlineStyle>) -> f64 {
+    let height = glyph.size().height;
+    match style.map(|style| style.vertical_align).unwrap_or_default() {
+        VerticalAlign::Baseline | VerticalAlign::Middle => text_origin_y(glyph, y, line_height),
+        VerticalAlign::Shift(shift) => text_origin_y(glyph, y, line_height) - shift as f64,
+        VerticalAlign::Top          => text_origin_y(glyph, y, height),
+        VerticalAlign::Bottom       => text_origin_y(glyph, y + line_height - height, height),
+    }
+}
+
+// Unaligned images keep to the top of the line
+fn image_y(y: f64, line_height: f64, height: f64, style: Option<&InlineStyle>) -> f64 {
+    let vertical_align = style.map(|style| style.vertical_align).unwrap_or_default();
+    match baseline_extents(height, true, vertical_align) {
+        Some((above, _))        => y + line_height * BASELINE_RATIO - above,
+        None if vertical_align == VerticalAlign::Bottom => y + line_height - height,
+        None                    => y,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{column_breaks, forced_breaks};
+
+    const COLUMN: f64 = 100.;
+
+    fn lines(count: usize) -> Vec<f64> {
+        vec![10.; count]
+    }
+
+    #[test]
+    fn columns_break_where_lines_no_longer_fit() {
+        assert_eq!(column_breaks(&lines(25), 0, 0., COLUMN, &[]), vec![10, 20]);
+        assert_eq!(column_breaks(&lines(5), 0, 75., COLUMN, &[]), vec![2]);
+        // A forced line starts a column even where more would fit
+        assert_eq!(column_breaks(&lines(5), 0, 0., COLUMN, &[3]), vec![3]);
+        // An empty column takes a line however tall it is
+        assert_eq!(column_breaks(&[150., 10.], 0, 0., COLUMN, &[]), vec![1]);
+    }
+
+    #[test]
+    fn paragraph_that_fits_needs_no_breaks() {
+        assert!(forced_breaks(&lines(5), 0, 0., COLUMN, 2, 2).is_empty());
+        assert!(forced_breaks(&lines(5), 0, 50., COLUMN, 2, 2).is_empty());
+    }
+
+    #[test]
+    fn too_few_lines_before_the_end_move_the_paragraph() {
+        // One line fits before the column ends, two are wanted there
+        assert_eq!(forced_breaks(&lines(5), 0, 85., COLUMN, 2, 1), vec![0]);
+        assert!(forced_breaks(&lines(5), 0, 85., COLUMN, 1, 1).is_empty());
+        // A paragraph taken up in a new column has had its first lines already
+        assert!(forced_breaks(&lines(8), 3, 85., COLUMN, 5, 1).is_empty());
+    }
+
+    #[test]
+    fn too_few_lines_after_the_end_pull_more_along() {
+        // Ten lines fill the column and the eleventh would be alone in the next
+        assert_eq!(forced_breaks(&lines(11), 0, 0., COLUMN, 2, 2), vec![9]);
+        assert!(forced_breaks(&lines(12), 0, 0., COLUMN, 2, 2).is_empty());
+        assert_eq!(forced_breaks(&lines(11), 0, 0., COLUMN, 1, 3), vec![8]);
+    }
+
+    #[test]
+    fn widows_give_way_to_orphans() {
+        // Moving a line on for the widows would leave fewer lines than the orphans ahead of the break
+        assert_eq!(forced_breaks(&lines(4), 0, 70., COLUMN, 2, 2), vec![2]);
+        assert!(forced_breaks(&lines(4), 0, 70., COLUMN, 3, 2).is_empty());
+    }
+}
@@ -1,72 +1,267 @@
-use crate::renderer::html_renderer::{HtmlRenderer, Selection, RenderState};
+use floem::keyboard::NamedKey;
+use floem::kurbo::{Point, Rect};
+use crate::book_elem::{Elem, ElemLines, ElemType};
+use crate::renderer::html_renderer::{text_glyphs, HtmlRenderer, Selection, TextPosition};
 impl HtmlRenderer {
-    pub fn get_selection(&self) -> Option<Selection> {
-        if !self.selection_active {return None}
-        let press_x         = self.press_location.unwrap().x;
-        let press_y         = self.press_location.unwrap().y;
-        let press_col_index = self.get_col_index(press_x);
-        let move_x          = self.move_location.x;
-        let move_y          = self.move_location.y;
-        let move_col_index  = self.get_col_index(move_x);
-
-        if press_col_index < move_col_index {return Some(Selection::new(press_col_index, move_col_index, self.press_location.unwrap(), self.move_location))}
-        if press_col_index > move_col_index {return Some(Selection::new(move_col_index, press_col_index, self.move_location, self.press_location.unwrap()))}
-        if press_y         < move_y         {return Some(Selection::new(press_col_index, move_col_index, self.press_location.unwrap(), self.move_location))}
-        if press_y         > move_y         {return Some(Selection::new(move_col_index, press_col_index, self.move_location, self.press_location.unwrap()))}
-        return Some(Selection::new(move_col_index, press_col_index, self.move_location, self.press_location.unwrap()))
-    }
-    pub fn hit(&self, render_state: &RenderState, gx0: f64, gy0: f64, gx1: f64, gy1: f64,) -> bool{
-        let glyph_col_index = self.get_col_index(gx0);
-        let selection = render_state.selection.as_ref().unwrap();
-        if selection.start_col < glyph_col_index && glyph_col_index < selection.end_col {
-            return true;
+    /// Starts a selection where the pointer went down. A click places it, a double click takes the word and a triple click the paragraph,
+    /// with shift the current selection is extended to the pointer instead.
+    pub fn select_from(&mut self, point: Point, count: u8, extend: bool) {
+        let Some(position) = self.position_at(point) else {
+            self.selection = None;
+            return
+        };
+        match (self.selection.as_mut(), count) {
+            (Some(selection), _) if extend => selection.focus = position,
+            (_, 2)  => self.select_word(position),
+            (_, 3..) => self.select_paragraph(position),
+            _ => self.selection = Some(Selection::new(position.clone(), position)),
         }
-        let is_first_col        = selection.start_col == glyph_col_index;
-        let is_last_col         = selection.end_col == glyph_col_index;
-        let is_first_line       = gy0 < selection.start_selection.y && gy1 > selection.start_selection.y;
-        let is_last_line        = gy0 < selection.end_selection.y && gy1 > selection.end_selection.y;
-        let is_after_first_line = gy0 > selection.start_selection.y;
-        let is_before_last_line = gy0 < selection.end_selection.y;
-        let is_first_x          = gx1 >= selection.start_selection.x;
-        let is_last_x           = gx1 <= selection.end_selection.x;
-        // Single column
-        if is_first_col && is_last_col {
-            // Single line
-            if is_first_line && is_last_line {
-                let x_min = selection.start_selection.x.min(selection.end_selection.x);
-                let x_max = selection.start_selection.x.max(selection.end_selection.x);
-                if gx1 > x_min && gx1 < x_max {return true}
-            }
-            else if is_first_line {
-                if is_first_x {return true};
+    }
+
+    pub fn drag_selection(&mut self, point: Point) {
+        let Some(position) = self.position_at(point) else { return };
+        if let Some(selection) = &mut self.selection { selection.focus = position }
+    }
+
+    /// The text position nearest `point` in the last frame, glyphs on the line under it first.
+    /// It is before or after the glyph by which half of it the point falls in.
+    pub fn position_at(&self, point: Point) -> Option<TextPosition> {
+        let distance = |rect: &Rect| (
+            (rect.y0 - point.y).max(point.y - rect.y1).max(0.),
+            (rect.x0 - point.x).max(point.x - rect.x1).max(0.),
+        );
+        let glyph = self.text_map.glyphs.iter().min_by(|a, b| {
+            let (a, b) = (distance(&a.rect), distance(&b.rect));
+            a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1))
+        })?;
+        let after = point.x > glyph.rect.center().x;
+        Some(TextPosition { offset: glyph.offset + after as usize, ..self.text_map.elems[glyph.elem].clone() })
+    }
+
+    // Letters, digits and the apostrophes inside words, anything else is a word of its own
+    fn select_word(&mut self, position: TextPosition) {
+        let chars   = self.elem_chars(&position);
+        let is_word = |char: char| char.is_alphanumeric() || char == '\'' || char == '’';
+        let Some(mut start) = Some(position.offset).filter(|offset| *offset < chars.len()).or(chars.len().checked_sub(1)) else { return };
+        // Double clicking the right half of a word's last letter puts the position after it
+        if !is_word(chars[start]) && start > 0 && is_word(chars[start - 1]) { start -= 1 }
+        let mut end = start + 1;
+        if is_word(chars[start]) {
+            while start > 0 && is_word(chars[start - 1]) { start -= 1 }
+            while end < chars.len() && is_word(chars[end]) { end += 1 }
+        }
+        self.selection = Some(Selection::new(TextPosition { offset: start, ..position.clone() }, TextPosition { offset: end, ..position }));
+    }
+
+    // All of the lines elem, the text of one block
+    fn select_paragraph(&mut self, position: TextPosition) {
+        let end = self.elem_chars(&position).len();
+        self.selection = Some(Selection::new(TextPosition { offset: 0, ..position.clone() }, TextPosition { offset: end, ..position }));
+    }
+
+    /// Moves the end of the selection being extended a glyph along the text, or a line up or down where it is on screen
+    pub fn move_focus(&mut self, key: &NamedKey) {
+        let Some(selection) = &self.selection else { return };
+        let focus = selection.focus.clone();
+        let focus = match key {
+            NamedKey::ArrowLeft if focus.offset > 0 => TextPosition { offset: focus.offset - 1, ..focus },
+            NamedKey::ArrowRight if focus.offset < self.elem_chars(&focus).len() => TextPosition { offset: focus.offset + 1, ..focus },
+            // Past either end of its lines elem the focus goes on to the next one in the section
+            NamedKey::ArrowLeft | NamedKey::ArrowRight => {
+                let elems       = self.section_elems(focus.section);
+                let Some(current) = elems.iter().position(|(index, _)| *index == focus.index) else { return };
+                let adjacent    = match key {
+                    NamedKey::ArrowLeft => current.checked_sub(1).and_then(|previous| elems.get(previous)),
+                    _                   => elems.get(current + 1),
+                };
+                let Some((index, count)) = adjacent else { return };
+                let offset = if *key == NamedKey::ArrowLeft { *count } else { 0 };
+                TextPosition { index: index.clone(), offset, ..focus }
             }
-            else if is_last_line {
-                if is_last_x {return true}
+            NamedKey::ArrowUp | NamedKey::ArrowDown => {
+                let Some(rect) = self.glyph_rect(&focus) else { return };
+                let step = if *key == NamedKey::ArrowUp { -rect.height() } else { rect.height() };
+                let Some(position) = self.position_at(Point::new(rect.x0, rect.center().y + step)) else { return };
+                position
             }
-            else if is_after_first_line && is_before_last_line {
-                return true;
+            _ => return,
+        };
+        if let Some(selection) = &mut self.selection { selection.focus = focus }
+    }
+
+    // Where the glyph after `position` was painted in the last frame, or the one before it at the end of its lines elem
+    fn glyph_rect(&self, position: &TextPosition) -> Option<Rect> {
+        let elem = self.text_map.elems.iter().position(|elem| elem.section == position.section && elem.index == position.index)?;
+        let glyphs = || self.text_map.glyphs.iter().filter(|glyph| glyph.elem == elem);
+        glyphs().find(|glyph| glyph.offset == position.offset).map(|glyph| glyph.rect)
+            .or_else(|| glyphs().find(|glyph| glyph.offset + 1 == position.offset).map(|glyph| Rect::new(glyph.rect.x1, glyph.rect.y0, glyph.rect.x1, glyph.rect.y1)))
+    }
+
+    /// The selected text as it reads, each lines elem on a line of its own
+    pub fn selected_text(&self) -> String {
+        let Some(selection) = &self.selection else { return String::new() };
+        let (start, end) = selection.range();
+        let mut text = String::new();
+        for section in start.section..=end.section {
+            for (index, _) in self.section_elems(section) {
+                let key = (section, index.as_slice());
+                if key < (start.section, start.index.as_slice()) || key > (end.section, end.index.as_slice()) { continue }
+                let from    = if key == (start.section, start.index.as_slice()) { start.offset } else { 0 };
+                let to      = if key == (end.section, end.index.as_slice()) { end.offset } else { usize::MAX };
+                let Some(lines) = self.elem_lines(section, &index) else { continue };
+                let mut offset = 0;
+                for line in &lines.elem_lines {
+                    for glyph in text_glyphs(line) {
+                        if from <= offset && offset < to { text.push(self.glyph_cache.key(glyph.char).0) }
+                        offset += 1;
+                    }
+                    // The spaces lines were wrapped at are not part of the layout
+                    if from < offset && offset < to && !text.ends_with([' ', '\n']) { text.push(' ') }
+                }
+                if !text.is_empty() && !text.ends_with('\n') {
+                    text.truncate(text.trim_end_matches(' ').len());
+                    text.push('\n');
+                }
             }
         }
+        text.trim_end().to_string()
+    }
 
-        // First column
-        else if is_first_col {
-            if is_first_line {
-                if is_first_x {return true}
-            }
-            else if is_after_first_line {
-                return true;
-            }
+    fn elem_lines(&self, section: usize, index: &Vec<usize>) -> Option<&ElemLines> {
+        let page = self.sections.get(section).and_then(|url| self.pages.get(url))?;
+        let elem = page.root.get_elem(index, 0);
+        match &elem.elem_type {
+            ElemType::Lines(lines) => Some(lines),
+            ElemType::Block(_) => None,
         }
-        //End column
-        else if is_last_col {
-            if is_last_line {
-                if is_last_x {return true}
-            }
-            else if is_before_last_line {
-                return true
+    }
+
+    // The chars of the lines elem at `position`, one for each text position in it
+    fn elem_chars(&self, position: &TextPosition) -> Vec<char> {
+        let Some(lines) = self.elem_lines(position.section, &position.index) else { return Vec::new() };
+        lines.elem_lines.iter().flat_map(text_glyphs).map(|glyph| self.glyph_cache.key(glyph.char).0).collect()
+    }
+
+    // The lines elems of a section in reading order, with the number of text positions in each
+    fn section_elems(&self, section: usize) -> Vec<(Vec<usize>, usize)> {
+        let mut elems = Vec::new();
+        if let Some(page) = self.sections.get(section).and_then(|url| self.pages.get(url)) {
+            collect_elems(&page.root, &mut Vec::new(), &mut elems);
+        }
+        elems
+    }
+}
+
+fn collect_elems(elem: &Elem, index: &mut Vec<usize>, elems: &mut Vec<(Vec<usize>, usize)>) {
+    match &elem.elem_type {
+        ElemType::Block(block) => {
+            for (position, child) in block.visible_children().iter().enumerate() {
+                index.push(position);
+                collect_elems(child, index, elems);
+                index.pop();
             }
         }
+        ElemType::Lines(lines) => elems.push((index.clone(), lines.elem_lines.iter().map(|line| text_glyphs(line).count()).sum())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use floem::keyboard::NamedKey;
+    use floem::peniko::Color;
+    use floem::prelude::RwSignal;
+    use floem::reactive::create_signal;
+    use floem_renderer::text::Attrs;
+    use roxmltree::Document;
+    use crate::book_elem::BookElemFactory;
+    use crate::glyph_interner::GlyphCache;
+    use crate::renderer::html_renderer::{HtmlRenderer, Selection, TextPosition};
+
+    fn renderer(body: &str) -> HtmlRenderer {
+        let html        = format!(r#"<html xmlns="http://www.w3.org/1999/xhtml"><body>{body}</body></html>"#);
+        let document    = Document::parse(&html).unwrap();
+        let font        = Attrs::new().font_size(20.);
+        let mut factory = BookElemFactory::new(GlyphCache::new(), HashMap::new(), HashMap::new(), &font, Color::BLACK);
+        let page        = factory.parse_root(document.root_element(), font, "page.xhtml".to_string(), &Vec::new(), &document, None);
+        let pages       = HashMap::from([("page.xhtml".to_string(), page)]);
+        HtmlRenderer::new(RwSignal::new(Vec::new()), factory.cache, pages, vec!["page.xhtml".to_string()], false,
+            RwSignal::new("page.xhtml".to_string()), create_signal(0).1, create_signal(false).0, RwSignal::new(false))
+    }
+
+    // The position `offset` glyphs into the `paragraph`th lines elem of the section
+    fn position(renderer: &HtmlRenderer, paragraph: usize, offset: usize) -> TextPosition {
+        let (index, _) = renderer.section_elems(0).swap_remove(paragraph);
+        TextPosition { section: 0, index, offset }
+    }
+
+    fn word_at(body: &str, offset: usize) -> String {
+        let mut renderer = renderer(body);
+        let position = position(&renderer, 0, offset);
+        renderer.select_word(position);
+        renderer.selected_text()
+    }
+
+    #[test]
+    fn range_orders_anchor_and_focus() {
+        let at = |section, index: &[usize], offset| TextPosition { section, index: index.to_vec(), offset };
+        let selection = Selection::new(at(0, &[1, 0], 3), at(0, &[0, 2], 5));
+        let (start, end) = selection.range();
+        assert!(*start == at(0, &[0, 2], 5) && *end == at(0, &[1, 0], 3));
+        assert!(selection.contains(&at(0, &[0, 2], 5)));
+        assert!(selection.contains(&at(0, &[0, 3], 0)));
+        assert!(!selection.contains(&at(0, &[1, 0], 3)));
+        // Sections order before the index within them
+        let across = Selection::new(at(1, &[0], 0), at(0, &[5], 9));
+        assert!(across.contains(&at(0, &[7], 0)));
+        assert!(!across.contains(&at(1, &[0], 1)));
+    }
+
+    #[test]
+    fn word_keeps_its_apostrophes() {
+        assert_eq!(word_at("<p>It's a dog’s life.</p>", 1), "It's");
+        assert_eq!(word_at("<p>It's a dog’s life.</p>", 10), "dog’s");
+    }
+
+    #[test]
+    fn word_taken_from_the_right_half_of_its_last_letter() {
+        assert_eq!(word_at("<p>It's a dog’s life.</p>", 4), "It's");
+        assert_eq!(word_at("<p>It's a dog’s life.</p>", 20), "life");
+        assert_eq!(word_at("<p>wait... what</p>", 5), ".");
+    }
+
+    #[test]
+    fn focus_moves_across_lines_elems() {
+        let mut renderer = renderer("<p>ab</p><p>cd</p>");
+        let end = position(&renderer, 0, 2);
+        renderer.selection = Some(Selection::new(position(&renderer, 0, 0), end.clone()));
+        renderer.move_focus(&NamedKey::ArrowRight);
+        assert!(renderer.selection.as_ref().unwrap().focus == position(&renderer, 1, 0));
+        renderer.move_focus(&NamedKey::ArrowRight);
+        assert!(renderer.selection.as_ref().unwrap().focus == position(&renderer, 1, 1));
+        renderer.move_focus(&NamedKey::ArrowLeft);
+        renderer.move_focus(&NamedKey::ArrowLeft);
+        assert!(renderer.selection.as_ref().unwrap().focus == end);
+        // Nothing comes before the first lines elem
+        renderer.selection = Some(Selection::new(end.clone(), position(&renderer, 0, 0)));
+        renderer.move_focus(&NamedKey::ArrowLeft);
+        assert!(renderer.selection.as_ref().unwrap().focus == position(&renderer, 0, 0));
+    }
+
+    #[test]
+    fn selected_text_puts_each_lines_elem_on_its_own_line() {
+        let mut renderer = renderer("<p>one two</p><p>three</p><p>four</p>");
+        renderer.selection = Some(Selection::new(position(&renderer, 2, 2), position(&renderer, 0, 4)));
+        assert_eq!(renderer.selected_text(), "two\nthree\nfo");
+    }
 
-        false
+    #[test]
+    fn selected_text_joins_wrapped_lines_with_a_space() {
+        let text = "the quick brown fox jumps over the lazy dog ".repeat(8);
+        let mut renderer = renderer(&format!("<p>{text}</p>"));
+        let position = position(&renderer, 0, 0);
+        assert!(renderer.elem_lines(0, &position.index).unwrap().elem_lines.len() > 1);
+        renderer.select_paragraph(position);
+        assert_eq!(renderer.selected_text(), text.trim_end());
     }
 }
@@ -0,0 +1,146 @@
+use lightningcss::properties::custom::{Token, TokenList, TokenOrValue};
+use lightningcss::properties::list::{CounterStyle, ListStyleType};
+use lightningcss::properties::Property;
+use lightningcss::traits::Parse;
+use crate::book_elem::{ListType, NumberStyle};
+use crate::styling::style::resolve_list_style_type;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PseudoElement { Before, After, FirstLetter, FirstLine }
+
+/// One piece of a `content` value, resolved against the element and the counters when generated
+#[derive(Clone)]
+pub enum ContentItem {
+    Text(String),
+    Attr(String),
+    Counter(String, ListType),
+    Counters(String, String, ListType),
+    OpenQuote,
+    CloseQuote,
+}
+
+/// Counter instances in document order, an instance stays visible until its parent element is closed
+#[derive(Clone, Default)]
+pub struct Counters {
+    instances: Vec<(String, isize)>,
+}
+
+impl Counters {
+    pub fn mark(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn release(&mut self, mark: usize) {
+        self.instances.truncate(mark);
+    }
+
+    pub fn reset(&mut self, name: &str, value: isize) {
+        self.instances.push((name.to_string(), value));
+    }
+
+    // Incrementing a counter that is not in scope instantiates it at zero
+    pub fn increment(&mut self, name: &str, by: isize) {
+        match self.instances.iter_mut().rev().find(|(counter, _)| counter == name) {
+            Some((_, value)) => *value += by,
+            None => self.instances.push((name.to_string(), by)),
+        }
+    }
+
+    pub fn value(&self, name: &str) -> isize {
+        self.instances.iter().rev().find(|(counter, _)| counter == name).map(|(_, value)| *value).unwrap_or(0)
+    }
+
+    pub fn values(&self, name: &str) -> Vec<isize> {
+        self.instances.iter().filter(|(counter, _)| counter == name).map(|(_, value)| *value).collect()
+    }
+}
+
+/// Reads `content`, `counter-reset` and `counter-increment`, which lightningcss leaves as raw tokens
+pub fn resolve_content_property(property: &Property) -> Option<ContentProperty> {
+    let Property::Custom(custom) = property else { return None };
+    let name = custom.name.as_ref();
+    if name.eq_ignore_ascii_case("content") {
+        Some(ContentProperty::Content(parse_content(&custom.value)))
+    } else if name.eq_ignore_ascii_case("counter-reset") {
+        Some(ContentProperty::CounterReset(parse_counter_list(&custom.value, 0)))
+    } else if name.eq_ignore_ascii_case("counter-increment") {
+        Some(ContentProperty::CounterIncrement(parse_counter_list(&custom.value, 1)))
+    } else {
+        None
+    }
+}
+
+pub enum ContentProperty {
+    /// None for `none` and `normal`, which generate no box
+    Content(Option<Vec<ContentItem>>),
+    CounterReset(Vec<(String, isize)>),
+    CounterIncrement(Vec<(String, isize)>),
+}
+
+fn parse_content(tokens: &TokenList) -> Option<Vec<ContentItem>> {
+    let mut items = Vec::new();
+    for token in tokens.0.iter().filter(|token| !token.is_whitespace()) {
+        match token {
+            TokenOrValue::Token(Token::String(text)) => items.push(ContentItem::Text(text.to_string())),
+            TokenOrValue::Token(Token::Ident(ident)) => match ident.as_ref() {
+                "none" | "normal"   => return None,
+                "open-quote"        => items.push(ContentItem::OpenQuote),
+                "close-quote"       => items.push(ContentItem::CloseQuote),
+                _ => ()
+            },
+            TokenOrValue::Function(function) => {
+                let arguments = arguments(&function.arguments);
+                let name = arguments.first().cloned().unwrap_or_default();
+                match function.name.as_ref() {
+                    "attr"      => items.push(ContentItem::Attr(name)),
+                    "counter"   => items.push(ContentItem::Counter(name, counter_style(arguments.get(1)))),
+                    "counters"  => {
+                        let separator = arguments.get(1).cloned().unwrap_or_default();
+                        items.push(ContentItem::Counters(name, separator, counter_style(arguments.get(2))))
+                    }
+                    _ => ()
+                }
+            }
+            _ => ()
+        }
+    }
+    Some(items)
+}
+
+// `name 2 other` pairs each counter with an optional integer, `none` clears the list
+fn parse_counter_list(tokens: &TokenList, default_value: isize) -> Vec<(String, isize)> {
+    let mut counters: Vec<(String, isize)> = Vec::new();
+    for token in tokens.0.iter().filter(|token| !token.is_whitespace()) {
+        match token {
+            TokenOrValue::Token(Token::Ident(ident)) if ident.as_ref() == "none" => return Vec::new(),
+            TokenOrValue::Token(Token::Ident(ident)) => counters.push((ident.to_string(), default_value)),
+            TokenOrValue::Token(Token::Number { value, .. }) => {
+                if let Some(counter) = counters.last_mut() { counter.1 = *value as isize }
+            }
+            _ => ()
+        }
+    }
+    counters
+}
+
+// Function arguments split on commas, with strings and identifiers unquoted
+fn arguments(tokens: &TokenList) -> Vec<String> {
+    let mut arguments = vec![String::new()];
+    for token in tokens.0.iter() {
+        match token {
+            TokenOrValue::Token(Token::Comma) => arguments.push(String::new()),
+            TokenOrValue::Token(Token::Ident(text)) | TokenOrValue::Token(Token::String(text)) => {
+                arguments.last_mut().unwrap().push_str(text.as_ref())
+            }
+            _ => ()
+        }
+    }
+    arguments
+}
+
+fn counter_style(name: Option<&String>) -> ListType {
+    name.and_then(|name| ListStyleType::parse_string(name).ok())
+        .filter(|style| matches!(style, ListStyleType::None | ListStyleType::CounterStyle(CounterStyle::Predefined(_))))
+        .and_then(|style| resolve_list_style_type(&style))
+        .unwrap_or(ListType::Ordered(NumberStyle::Decimal))
+}
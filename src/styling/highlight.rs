@@ -0,0 +1,420 @@
+use floem::peniko::Color;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TokenKind { Keyword, Type, Constant, String, Number, Comment, Function, Meta }
+
+/// Token colors of the reader theme, text outside any token keeps the block's own color
+pub struct SyntaxTheme {
+    pub keyword: Color,
+    pub type_name: Color,
+    pub constant: Color,
+    pub string: Color,
+    pub number: Color,
+    pub comment: Color,
+    pub function: Color,
+    pub meta: Color,
+}
+
+impl SyntaxTheme {
+    /// Colors for the page the reader's text color is set against, a light page under dark text and a dark one under light text
+    pub fn for_text(text: Color) -> Self {
+        let luminance = 0.299 * text.r as f64 + 0.587 * text.g as f64 + 0.114 * text.b as f64;
+        match luminance < 128. {
+            true => SyntaxTheme {
+                keyword:    Color::rgb8(167, 29, 93),
+                type_name:  Color::rgb8(0, 92, 197),
+                constant:   Color::rgb8(0, 134, 179),
+                string:     Color::rgb8(24, 54, 145),
+                number:     Color::rgb8(0, 134, 179),
+                comment:    Color::rgb8(106, 115, 125),
+                function:   Color::rgb8(111, 66, 193),
+                meta:       Color::rgb8(227, 98, 9),
+            },
+            false => SyntaxTheme {
+                keyword:    Color::rgb8(255, 123, 114),
+                type_name:  Color::rgb8(121, 192, 255),
+                constant:   Color::rgb8(121, 192, 255),
+                string:     Color::rgb8(165, 214, 255),
+                number:     Color::rgb8(121, 192, 255),
+                comment:    Color::rgb8(139, 148, 158),
+                function:   Color::rgb8(210, 168, 255),
+                meta:       Color::rgb8(255, 166, 87),
+            },
+        }
+    }
+
+    pub fn color(&self, kind: TokenKind) -> Color {
+        match kind {
+            TokenKind::Keyword  => self.keyword,
+            TokenKind::Type     => self.type_name,
+            TokenKind::Constant => self.constant,
+            TokenKind::String   => self.string,
+            TokenKind::Number   => self.number,
+            TokenKind::Comment  => self.comment,
+            TokenKind::Function => self.function,
+            TokenKind::Meta     => self.meta,
+        }
+    }
+}
+
+/// What a language looks like to the lexer. Grammars are built in so that code renders the same offline.
+pub struct Grammar {
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    constants: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    /// `#[attr]` and `name!` in Rust, `#include` in C, `@decorator` elsewhere
+    meta: Meta,
+    /// Capitalized identifiers are types, as in most languages with that convention
+    capitalized_types: bool,
+    case_sensitive: bool,
+    markup: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Meta { None, Rust, Preprocessor, At }
+
+const DEFAULT: Grammar = Grammar {
+    keywords: &[], types: &[], constants: &[], line_comments: &[], block_comment: None, quotes: &['"', '\''],
+    meta: Meta::None, capitalized_types: false, case_sensitive: true, markup: false,
+};
+
+const RUST: Grammar = Grammar {
+    keywords: &["as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "fn", "for", "if", "impl", "in",
+        "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait", "type",
+        "unsafe", "use", "where", "while", "yield"],
+    types: &["bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize", "f32", "f64"],
+    constants: &["true", "false", "None", "Some", "Ok", "Err"],
+    line_comments: &["//"], block_comment: Some(("/*", "*/")), quotes: &['"'],
+    meta: Meta::Rust, capitalized_types: true, ..DEFAULT
+};
+
+const C: Grammar = Grammar {
+    keywords: &["auto", "break", "case", "const", "continue", "default", "do", "else", "enum", "extern", "for", "goto", "if", "inline",
+        "register", "restrict", "return", "sizeof", "static", "struct", "switch", "typedef", "union", "volatile", "while",
+        "class", "namespace", "template", "typename", "public", "private", "protected", "virtual", "override", "new", "delete",
+        "using", "try", "catch", "throw", "constexpr", "noexcept", "operator", "this", "friend", "explicit", "mutable"],
+    types: &["void", "char", "short", "int", "long", "float", "double", "signed", "unsigned", "bool", "size_t", "int8_t", "int16_t",
+        "int32_t", "int64_t", "uint8_t", "uint16_t", "uint32_t", "uint64_t", "auto", "wchar_t"],
+    constants: &["true", "false", "NULL", "nullptr"],
+    line_comments: &["//"], block_comment: Some(("/*", "*/")),
+    meta: Meta::Preprocessor, capitalized_types: true, ..DEFAULT
+};
+
+const JAVA: Grammar = Grammar {
+    keywords: &["abstract", "assert", "break", "case", "catch", "class", "const", "continue", "default", "do", "else", "enum", "extends",
+        "final", "finally", "for", "goto", "if", "implements", "import", "instanceof", "interface", "native", "new", "package", "private",
+        "protected", "public", "return", "static", "strictfp", "super", "switch", "synchronized", "this", "throw", "throws", "transient",
+        "try", "volatile", "while", "var", "record", "yield", "fun", "val", "when", "object", "companion", "override", "open", "data",
+        "sealed", "internal", "is", "in", "as", "namespace", "using", "readonly", "async", "await", "get", "set", "struct", "out", "ref"],
+    types: &["boolean", "byte", "char", "short", "int", "long", "float", "double", "void", "string", "object", "decimal"],
+    constants: &["true", "false", "null"],
+    line_comments: &["//"], block_comment: Some(("/*", "*/")),
+    meta: Meta::At, capitalized_types: true, ..DEFAULT
+};
+
+const JAVASCRIPT: Grammar = Grammar {
+    keywords: &["async", "await", "break", "case", "catch", "class", "const", "continue", "debugger", "default", "delete", "do", "else",
+        "export", "extends", "finally", "for", "from", "function", "if", "import", "in", "instanceof", "let", "new", "of", "return",
+        "static", "super", "switch", "this", "throw", "try", "typeof", "var", "void", "while", "with", "yield", "interface", "type",
+        "enum", "implements", "private", "protected", "public", "readonly", "declare", "namespace", "abstract", "as"],
+    types: &["string", "number", "boolean", "any", "unknown", "never", "object", "symbol", "bigint"],
+    constants: &["true", "false", "null", "undefined", "NaN", "Infinity"],
+    line_comments: &["//"], block_comment: Some(("/*", "*/")), quotes: &['"', '\'', '`'],
+    meta: Meta::At, capitalized_types: true, ..DEFAULT
+};
+
+const GO: Grammar = Grammar {
+    keywords: &["break", "case", "chan", "const", "continue", "default", "defer", "else", "fallthrough", "for", "func", "go", "goto", "if",
+        "import", "interface", "map", "package", "range", "return", "select", "struct", "switch", "type", "var"],
+    types: &["bool", "byte", "complex64", "complex128", "error", "float32", "float64", "int", "int8", "int16", "int32", "int64", "rune",
+        "string", "uint", "uint8", "uint16", "uint32", "uint64", "uintptr", "any"],
+    constants: &["true", "false", "nil", "iota"],
+    line_comments: &["//"], block_comment: Some(("/*", "*/")), quotes: &['"', '\'', '`'],
+    ..DEFAULT
+};
+
+const SWIFT: Grammar = Grammar {
+    keywords: &["associatedtype", "break", "case", "catch", "class", "continue", "default", "defer", "deinit", "do", "else", "enum",
+        "extension", "fallthrough", "fileprivate", "for", "func", "guard", "if", "import", "in", "init", "inout", "internal", "is", "let",
+        "open", "operator", "private", "protocol", "public", "repeat", "rethrows", "return", "self", "Self", "static", "struct",
+        "subscript", "super", "switch", "throw", "throws", "try", "typealias", "var", "where", "while", "async", "await", "some", "any"],
+    constants: &["true", "false", "nil"],
+    line_comments: &["//"], block_comment: Some(("/*", "*/")), quotes: &['"'],
+    meta: Meta::At, capitalized_types: true, ..DEFAULT
+};
+
+const PYTHON: Grammar = Grammar {
+    keywords: &["and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif", "else", "except", "finally",
+        "for", "from", "global", "if", "import", "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+        "with", "yield", "match", "case", "self"],
+    types: &["int", "float", "str", "bool", "list", "dict", "set", "tuple", "bytes", "object", "type"],
+    constants: &["True", "False", "None"],
+    line_comments: &["#"],
+    meta: Meta::At, capitalized_types: true, ..DEFAULT
+};
+
+const RUBY: Grammar = Grammar {
+    keywords: &["alias", "and", "begin", "break", "case", "class", "def", "defined?", "do", "else", "elsif", "end", "ensure", "for", "if",
+        "in", "module", "next", "not", "or", "redo", "rescue", "retry", "return", "self", "super", "then", "undef", "unless", "until",
+        "when", "while", "yield", "require", "attr_accessor", "attr_reader", "puts"],
+    constants: &["true", "false", "nil"],
+    line_comments: &["#"],
+    capitalized_types: true, ..DEFAULT
+};
+
+const SHELL: Grammar = Grammar {
+    keywords: &["if", "then", "else", "elif", "fi", "case", "esac", "for", "while", "until", "do", "done", "in", "function", "return",
+        "export", "local", "readonly", "echo", "cd", "exit", "source", "sudo"],
+    line_comments: &["#"],
+    ..DEFAULT
+};
+
+const SQL: Grammar = Grammar {
+    keywords: &["select", "from", "where", "and", "or", "not", "insert", "into", "values", "update", "set", "delete", "create", "table",
+        "drop", "alter", "add", "index", "join", "inner", "left", "right", "outer", "full", "on", "as", "group", "by", "order", "having",
+        "limit", "offset", "distinct", "union", "all", "primary", "key", "foreign", "references", "null", "is", "in", "like", "between",
+        "exists", "case", "when", "then", "else", "end", "asc", "desc", "view", "with", "default", "constraint", "unique", "count", "sum",
+        "avg", "min", "max"],
+    types: &["int", "integer", "bigint", "smallint", "varchar", "char", "text", "date", "timestamp", "boolean", "decimal", "numeric",
+        "real", "float", "serial", "blob"],
+    constants: &["true", "false"],
+    line_comments: &["--"], block_comment: Some(("/*", "*/")), quotes: &['\'', '"'],
+    case_sensitive: false, ..DEFAULT
+};
+
+const CSS: Grammar = Grammar {
+    keywords: &["important", "inherit", "initial", "unset", "auto", "none"],
+    block_comment: Some(("/*", "*/")),
+    meta: Meta::At, ..DEFAULT
+};
+
+const DATA: Grammar = Grammar {
+    constants: &["true", "false", "null", "yes", "no", "on", "off", "~"],
+    line_comments: &["#"],
+    ..DEFAULT
+};
+
+const HASKELL: Grammar = Grammar {
+    keywords: &["case", "class", "data", "deriving", "do", "else", "if", "import", "in", "infix", "infixl", "infixr", "instance", "let",
+        "module", "newtype", "of", "then", "type", "where", "qualified", "as", "hiding"],
+    constants: &["True", "False", "Nothing", "Just"],
+    line_comments: &["--"], block_comment: Some(("{-", "-}")),
+    capitalized_types: true, ..DEFAULT
+};
+
+const MARKUP: Grammar = Grammar { markup: true, block_comment: Some(("<!--", "-->")), ..DEFAULT };
+
+/// The grammar for a `language-*` or `lang-*` class name
+pub fn grammar(language: &str) -> Option<&'static Grammar> {
+    Some(match language.to_ascii_lowercase().as_str() {
+        "rust" | "rs"                                       => &RUST,
+        "c" | "h" | "cpp" | "c++" | "cc" | "hpp" | "cxx" | "objc" | "objectivec" => &C,
+        "java" | "kotlin" | "kt" | "scala" | "csharp" | "cs" | "c#" | "dart" | "groovy" => &JAVA,
+        "javascript" | "js" | "jsx" | "typescript" | "ts" | "tsx" | "node" => &JAVASCRIPT,
+        "go" | "golang"                                     => &GO,
+        "swift"                                             => &SWIFT,
+        "python" | "py" | "python3" | "ipython"             => &PYTHON,
+        "ruby" | "rb"                                       => &RUBY,
+        "bash" | "sh" | "shell" | "zsh" | "console" | "shell-session" => &SHELL,
+        "sql" | "mysql" | "postgresql" | "sqlite"           => &SQL,
+        "css" | "scss" | "less"                             => &CSS,
+        "json" | "yaml" | "yml" | "toml" | "ini"            => &DATA,
+        "haskell" | "hs"                                    => &HASKELL,
+        "html" | "xml" | "xhtml" | "svg" | "markup"         => &MARKUP,
+        _ => return None,
+    })
+}
+
+/// Byte ranges of the tokens in `source`, in order. Text between them is plain.
+pub fn highlight(source: &str, grammar: &Grammar) -> Vec<(usize, usize, TokenKind)> {
+    if grammar.markup { return highlight_markup(source) }
+    let mut tokens  = Vec::new();
+    let mut line_start = true;
+    let mut i       = 0;
+    while let Some(ch) = source[i..].chars().next() {
+        let rest = &source[i..];
+        let start = i;
+        if let Some(prefix) = grammar.line_comments.iter().find(|prefix| rest.starts_with(**prefix)) {
+            i += rest[prefix.len()..].find('\n').map_or(rest.len(), |end| prefix.len() + end);
+            tokens.push((start, i, TokenKind::Comment));
+        }
+        else if let Some((open, close)) = grammar.block_comment.filter(|(open, _)| rest.starts_with(open)) {
+            i += rest[open.len()..].find(close).map_or(rest.len(), |end| open.len() + end + close.len());
+            tokens.push((start, i, TokenKind::Comment));
+        }
+        else if grammar.quotes.contains(&ch) {
+            i += string_length(rest, ch);
+            tokens.push((start, i, TokenKind::String));
+        }
+        else if ch.is_ascii_digit() {
+            i += rest.find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_' || ch == '.')).unwrap_or(rest.len());
+            tokens.push((start, i, TokenKind::Number));
+        }
+        else if ch.is_alphabetic() || ch == '_' || ch == '$' {
+            i += rest.find(|ch: char| !(ch.is_alphanumeric() || ch == '_' || ch == '$')).unwrap_or(rest.len());
+            let word = &source[start..i];
+            let next = source[i..].chars().next();
+            if let Some(kind) = word_kind(word, next, grammar) {
+                if grammar.meta == Meta::Rust && next == Some('!') { i += 1 }
+                tokens.push((start, i, kind));
+            }
+        }
+        else if let Some(length) = meta_length(rest, ch, line_start, grammar.meta) {
+            i += length;
+            tokens.push((start, i, TokenKind::Meta));
+        }
+        else {
+            i += ch.len_utf8();
+        }
+        if ch == '\n' { line_start = true }
+        else if !ch.is_whitespace() { line_start = false }
+    }
+    tokens
+}
+
+fn word_kind(word: &str, next: Option<char>, grammar: &Grammar) -> Option<TokenKind> {
+    let is = |words: &[&str]| match grammar.case_sensitive {
+        true    => words.contains(&word),
+        false   => words.iter().any(|candidate| candidate.eq_ignore_ascii_case(word)),
+    };
+    if grammar.meta == Meta::Rust && next == Some('!') { return Some(TokenKind::Meta) }
+    if is(grammar.keywords)     { return Some(TokenKind::Keyword) }
+    if is(grammar.constants)    { return Some(TokenKind::Constant) }
+    if is(grammar.types)        { return Some(TokenKind::Type) }
+    if next == Some('(')        { return Some(TokenKind::Function) }
+    if grammar.capitalized_types && word.starts_with(|ch: char| ch.is_uppercase()) && word.chars().any(|ch| ch.is_lowercase()) {
+        return Some(TokenKind::Type)
+    }
+    None
+}
+
+// A string runs to its closing quote or the end of the line, a tripled quote to the closing triple
+fn string_length(rest: &str, quote: char) -> usize {
+    let triple: String = std::iter::repeat_n(quote, 3).collect();
+    if rest.starts_with(&triple) {
+        return rest[3..].find(&triple).map_or(rest.len(), |end| 3 + end + 3)
+    }
+    let mut escaped = false;
+    for (i, ch) in rest.char_indices().skip(1) {
+        match ch {
+            _ if escaped            => escaped = false,
+            '\\'                    => escaped = true,
+            '\n' if quote != '`'    => return i,
+            _ if ch == quote        => return i + 1,
+            _ => ()
+        }
+    }
+    rest.len()
+}
+
+fn meta_length(rest: &str, ch: char, line_start: bool, meta: Meta) -> Option<usize> {
+    match (meta, ch) {
+        (Meta::Rust, '#') if rest[1..].starts_with('[') || rest[1..].starts_with("![") => rest.find(']').map(|end| end + 1),
+        (Meta::Preprocessor, '#') if line_start => Some(rest.find('\n').unwrap_or(rest.len())),
+        (Meta::At, '@') => Some(1 + rest[1..].find(|ch: char| !(ch.is_alphanumeric() || ch == '_' || ch == '.' || ch == '-')).unwrap_or(rest.len() - 1)),
+        _ => None,
+    }
+}
+
+// Tag names are keywords, attribute names types and their values strings
+fn highlight_markup(source: &str) -> Vec<(usize, usize, TokenKind)> {
+    let mut tokens  = Vec::new();
+    let mut i       = 0;
+    while let Some(open) = source[i..].find('<') {
+        let start = i + open;
+        if source[start..].starts_with("<!--") {
+            i = source[start..].find("-->").map_or(source.len(), |end| start + end + 3);
+            tokens.push((start, i, TokenKind::Comment));
+            continue
+        }
+        let end = source[start..].find('>').map_or(source.len(), |end| start + end + 1);
+        let tag = &source[start..end];
+        // The name follows `<`, `</`, `<?` or `<!` directly, a `<` without one is an operator in the text
+        let name_start  = 1 + tag[1..].starts_with(['/', '?', '!']) as usize;
+        let name_end    = tag[name_start..].find(|ch: char| !(ch.is_alphanumeric() || ch == '-' || ch == ':' || ch == '_' || ch == '.')).map_or(tag.len(), |end| name_start + end);
+        if name_start == name_end || !tag[name_start..].starts_with(char::is_alphabetic) {
+            i = start + 1;
+            continue
+        }
+        tokens.push((start + name_start, start + name_end, TokenKind::Keyword));
+        let mut j = name_end;
+        while j < tag.len() {
+            let ch = tag[j..].chars().next().unwrap();
+            if ch == '"' || ch == '\'' {
+                let length = tag[j + 1..].find(ch).map_or(tag.len() - j, |close| close + 2);
+                tokens.push((start + j, start + j + length, TokenKind::String));
+                j += length;
+            } else if ch.is_alphabetic() {
+                let length = tag[j..].find(|ch: char| !(ch.is_alphanumeric() || ch == '-' || ch == ':' || ch == '_')).unwrap_or(tag.len() - j);
+                tokens.push((start + j, start + j + length, TokenKind::Type));
+                j += length;
+            } else {
+                j += ch.len_utf8();
+            }
+        }
+        i = end;
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens<'a>(source: &'a str, language: &str) -> Vec<(&'a str, TokenKind)> {
+        highlight(source, grammar(language).unwrap()).into_iter().map(|(start, end, kind)| (&source[start..end], kind)).collect()
+    }
+
+    #[test]
+    fn comments_run_to_the_line_end_or_the_closing_marker() {
+        assert_eq!(tokens("x // y\nz", "rust"), vec![("// y", TokenKind::Comment)]);
+        assert_eq!(tokens("a /* b\n c */ d", "c"), vec![("/* b\n c */", TokenKind::Comment)]);
+        assert_eq!(tokens("a /* open", "js"), vec![("/* open", TokenKind::Comment)]);
+        assert_eq!(tokens("x = 1 # note", "python"), vec![("1", TokenKind::Number), ("# note", TokenKind::Comment)]);
+    }
+
+    #[test]
+    fn strings_skip_escaped_quotes_and_triples_span_lines() {
+        assert_eq!(tokens(r#"s = "a \" b" + c"#, "js"), vec![(r#""a \" b""#, TokenKind::String)]);
+        assert_eq!(tokens("\"\"\"doc\n\"quoted\"\n\"\"\" x", "python"), vec![("\"\"\"doc\n\"quoted\"\n\"\"\"", TokenKind::String)]);
+        assert_eq!(tokens("\"open\nx", "go"), vec![("\"open", TokenKind::String)]);
+    }
+
+    #[test]
+    fn rust_macros_and_attributes_are_meta() {
+        assert_eq!(tokens("#[derive(Debug)]", "rust"), vec![("#[derive(Debug)]", TokenKind::Meta)]);
+        assert_eq!(tokens("#![allow(x)]", "rust"), vec![("#![allow(x)]", TokenKind::Meta)]);
+        assert_eq!(tokens("println!(x)", "rust"), vec![("println!", TokenKind::Meta)]);
+        assert_eq!(tokens("let v: Vec<u8>", "rust"), vec![("let", TokenKind::Keyword), ("Vec", TokenKind::Type), ("u8", TokenKind::Type)]);
+    }
+
+    #[test]
+    fn preprocessor_lines_only_start_a_line() {
+        assert_eq!(tokens("  #include <stdio.h>\nint x;", "c"), vec![("#include <stdio.h>", TokenKind::Meta), ("int", TokenKind::Type)]);
+        assert_eq!(tokens("x # y", "c"), vec![]);
+    }
+
+    #[test]
+    fn sql_keywords_ignore_case() {
+        assert_eq!(tokens("SELECT name From users", "sql"), vec![("SELECT", TokenKind::Keyword), ("From", TokenKind::Keyword)]);
+        assert_eq!(tokens("select", "rust"), vec![]);
+    }
+
+    #[test]
+    fn markup_tags_and_attributes() {
+        assert_eq!(tokens(r#"<a href="x">t</a>"#, "html"), vec![
+            ("a", TokenKind::Keyword), ("href", TokenKind::Type), (r#""x""#, TokenKind::String), ("a", TokenKind::Keyword),
+        ]);
+        assert_eq!(tokens("<!-- c --><br/>", "xml"), vec![("<!-- c -->", TokenKind::Comment), ("br", TokenKind::Keyword)]);
+    }
+
+    #[test]
+    fn a_bare_angle_bracket_is_not_a_tag() {
+        assert_eq!(tokens("a < b", "html"), vec![]);
+        assert_eq!(tokens("a <", "html"), vec![]);
+        assert_eq!(tokens("1 < 2 <p>", "html"), vec![("p", TokenKind::Keyword)]);
+    }
+}
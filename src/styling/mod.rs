@@ -1,2 +1,4 @@
 pub mod style;
+pub mod content;
+pub mod highlight;
 mod selector_matching;
\ No newline at end of file
@@ -1,7 +1,8 @@
-use lightningcss::selector::{Combinator, Component, Selector};
+use lightningcss::selector::{Combinator, Component, PseudoElement as CssPseudoElement, Selector};
 use roxmltree::{Document, Node};
 use scraper::{Element, ElementRef};
 use crate::book_elem::ParseState;
+use crate::styling::content::PseudoElement;
 
 // Fast pre-filtering to reject selectors that can't possibly match
 pub fn can_selector_match(selector: &Selector, node_tag: &str, node_id: Option<&str>, node_class: Option<&str>) -> bool {
@@ -65,6 +66,11 @@ pub fn sequence_matches(component: &Component, node: &Node) -> bool {
             println!("No namespace");
             node.attribute(local_name.0.as_ref()).is_some()
         }
+        // Only :first-child and :first-of-type, which chapter openings are styled with
+        Component::Nth(nth) if !nth.is_function() && !nth.ty.is_only() && !nth.ty.is_from_end() => match nth.ty.is_of_type() {
+            true    => is_first_of_type(node),
+            false   => nth.ty.allows_of_selector() && is_first_child(node),
+        }
         // Add other component types as needed
         _ => {
            // println!("Unsopported: {:#?}", component);
@@ -73,8 +79,26 @@ pub fn sequence_matches(component: &Component, node: &Node) -> bool {
     }
 }
 
-pub fn selector_matches2(selector: &Selector, node: &Node, parse_state: &ParseState, document: &Document) -> bool{
+pub fn is_first_child(node: &Node) -> bool {
+    node.prev_sibling_element().is_none()
+}
+
+pub fn is_first_of_type(node: &Node) -> bool {
+    !node.prev_siblings().skip(1).any(|sibling| sibling.is_element() && sibling.tag_name() == node.tag_name())
+}
+
+pub fn selector_matches2(selector: &Selector, pseudo: Option<PseudoElement>, node: &Node, parse_state: &ParseState, document: &Document) -> bool{
     let mut iter = selector.iter();
+    // A pseudo-element selector only applies to that pseudo-element, which is matched through its originating element
+    match (selector.pseudo_element(), pseudo) {
+        (None, None) => (),
+        (Some(CssPseudoElement::Before), Some(PseudoElement::Before)) | (Some(CssPseudoElement::After), Some(PseudoElement::After))
+        | (Some(CssPseudoElement::FirstLetter), Some(PseudoElement::FirstLetter)) | (Some(CssPseudoElement::FirstLine), Some(PseudoElement::FirstLine)) => {
+            for _ in iter.by_ref() {}
+            iter.next_sequence();
+        }
+        _ => return false,
+    }
     let sequences = iter.by_ref();
     for sequence in sequences {
         if !sequence_matches(sequence, node) {
@@ -1,18 +1,27 @@
-use floem_renderer::text::Attrs;
-use lightningcss::properties::font::{AbsoluteFontSize, AbsoluteFontWeight, FontSize, FontStyle, FontWeight, RelativeFontSize};
-use lightningcss::properties::text::TextAlign;
+use std::rc::Rc;
+use floem::peniko::Color;
+use floem_renderer::text::{Attrs, FamilyOwned};
+use lightningcss::properties::font::{AbsoluteFontSize, AbsoluteFontWeight, FontFamily, FontSize, FontStyle, FontVariantCaps, FontWeight, GenericFontFamily, LineHeight as CssLineHeight, RelativeFontSize, VerticalAlign as CssVerticalAlign, VerticalAlignKeyword};
+use lightningcss::properties::border::{BorderSideWidth, LineStyle};
+use lightningcss::properties::list::{CounterStyle, ListStylePosition, ListStyleType, PredefinedCounterStyle};
+use lightningcss::properties::text::{Spacing, TextAlign, TextDecorationLine, TextTransformCase, WhiteSpace};
+use lightningcss::properties::position::Position as CssPosition;
+use lightningcss::properties::size::{MaxSize, Size as CssSize};
 use lightningcss::properties::Property;
 use lightningcss::rules::CssRule;
-use lightningcss::stylesheet::StyleSheet;
-use lightningcss::values::length::{LengthPercentage, LengthPercentageOrAuto, LengthValue};
+use lightningcss::stylesheet::{PrinterOptions, StyleSheet};
+use lightningcss::traits::{Parse, ToCss};
+use lightningcss::values::color::{CssColor, RGBA};
+use lightningcss::values::length::{Length, LengthPercentage, LengthPercentageOrAuto, LengthValue};
 use roxmltree::{Document, Node};
 use scraper::{ElementRef, Html};
 use std::fmt::Pointer;
 use std::ops::Deref;
 use std::hash::{Hash, Hasher};
 use rustc_data_structures::fx::FxHashMap;
-use crate::book_elem::ParseState;
-use crate::styling::selector_matching::{can_selector_match, selector_matches2, selector_matches_scraper};
+use crate::book_elem::{BlockDecoration, BorderSide, BoxSize, Break, Clear, FloatSide, InitialLetter, Insets, LineHeight, BulletStyle, ListType, NumberStyle, ParseState, Position, VerticalAlign};
+use crate::styling::content::{resolve_content_property, ContentItem, ContentProperty, PseudoElement};
+use crate::styling::selector_matching::{can_selector_match, is_first_child, is_first_of_type, selector_matches2, selector_matches_scraper};
 
 // Pre-computed font size scaling factors for performance
 const FONT_SIZE_SCALES: [f64; 8] = [
@@ -40,6 +49,32 @@ pub enum CSSValue {
     FontWeight(FontWeight),
     TextAlign(TextAlign),
     TextStyle(floem_renderer::text::Style),
+    FontFamily(Rc<[FamilyOwned]>),
+    FontVariant(FontVariantCaps),
+    TextTransform(TextTransformCase),
+    Color(Option<Color>),
+    TextDecoration(TextDecorationLine),
+    BorderWidth(BorderSideWidth),
+    BorderStyle(LineStyle),
+    VerticalAlign(CssVerticalAlign),
+    ListStyleType(ListType),
+    ListStylePosition(ListStylePosition),
+    Content(Option<Rc<[ContentItem]>>),
+    Counters(Rc<[(String, isize)]>),
+    BorderCollapse(bool),
+    BorderSpacing(Length),
+    Size(Option<LengthPercentage>),
+    Float(Option<FloatSide>),
+    Clear(Clear),
+    InitialLetter(Option<InitialLetter>),
+    LineHeight(CssLineHeight),
+    Spacing(Spacing),
+    TextIndent(LengthPercentage),
+    WhiteSpace(WhiteSpace),
+    Radius(LengthPercentage),
+    Break(Break),
+    LineCount(usize),
+    Position(Position),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -55,8 +90,72 @@ pub enum PropertyKey {
     FontWeight,
     TextAlign,
     FontStyle,
+    FontFamily,
+    FontVariant,
+    TextTransform,
+    Color,
+    BackgroundColor,
+    TextDecorationLine,
+    TextDecorationColor,
+    BorderTopWidth,
+    BorderRightWidth,
+    BorderBottomWidth,
+    BorderLeftWidth,
+    BorderTopStyle,
+    BorderRightStyle,
+    BorderBottomStyle,
+    BorderLeftStyle,
+    BorderTopColor,
+    BorderRightColor,
+    BorderBottomColor,
+    BorderLeftColor,
+    VerticalAlign,
+    ListStyleType,
+    ListStylePosition,
+    Content,
+    CounterReset,
+    CounterIncrement,
+    BorderCollapse,
+    BorderSpacing,
+    Width,
+    Height,
+    MaxWidth,
+    MaxHeight,
+    Float,
+    Clear,
+    InitialLetter,
+    LineHeight,
+    LetterSpacing,
+    WordSpacing,
+    TextIndent,
+    WhiteSpace,
+    BorderTopLeftRadius,
+    BorderTopRightRadius,
+    BorderBottomRightRadius,
+    BorderBottomLeftRadius,
+    BreakBefore,
+    BreakAfter,
+    BreakInside,
+    Orphans,
+    Widows,
+    Position,
+    Top,
+    Right,
+    Bottom,
+    Left,
 }
 
+// Border keys in top, right, bottom, left order
+const BORDER_WIDTH_KEYS: [PropertyKey; 4] = [PropertyKey::BorderTopWidth, PropertyKey::BorderRightWidth, PropertyKey::BorderBottomWidth, PropertyKey::BorderLeftWidth];
+const BORDER_STYLE_KEYS: [PropertyKey; 4] = [PropertyKey::BorderTopStyle, PropertyKey::BorderRightStyle, PropertyKey::BorderBottomStyle, PropertyKey::BorderLeftStyle];
+const BORDER_COLOR_KEYS: [PropertyKey; 4] = [PropertyKey::BorderTopColor, PropertyKey::BorderRightColor, PropertyKey::BorderBottomColor, PropertyKey::BorderLeftColor];
+// Corner keys clockwise from the top left
+const BORDER_RADIUS_KEYS: [PropertyKey; 4] = [PropertyKey::BorderTopLeftRadius, PropertyKey::BorderTopRightRadius, PropertyKey::BorderBottomRightRadius, PropertyKey::BorderBottomLeftRadius];
+const MEDIUM_BORDER_WIDTH: f64 = 3.;
+// Baseline shifts of super and sub, relative to the parent font size
+const SUPER_SHIFT: f64  = 0.33;
+const SUB_SHIFT: f64    = 0.2;
+
 impl PropertyKey {
     pub fn from_str(s: &str) -> Option<Self> {
         match s {
@@ -71,6 +170,59 @@ impl PropertyKey {
             "font-weight" => Some(Self::FontWeight),
             "text-align" => Some(Self::TextAlign),
             "font-style" => Some(Self::FontStyle),
+            "font-family" => Some(Self::FontFamily),
+            "font-variant" | "font-variant-caps" => Some(Self::FontVariant),
+            "text-transform" => Some(Self::TextTransform),
+            "color" => Some(Self::Color),
+            "background-color" => Some(Self::BackgroundColor),
+            "text-decoration-line" => Some(Self::TextDecorationLine),
+            "text-decoration-color" => Some(Self::TextDecorationColor),
+            "border-top-width" => Some(Self::BorderTopWidth),
+            "border-right-width" => Some(Self::BorderRightWidth),
+            "border-bottom-width" => Some(Self::BorderBottomWidth),
+            "border-left-width" => Some(Self::BorderLeftWidth),
+            "border-top-style" => Some(Self::BorderTopStyle),
+            "border-right-style" => Some(Self::BorderRightStyle),
+            "border-bottom-style" => Some(Self::BorderBottomStyle),
+            "border-left-style" => Some(Self::BorderLeftStyle),
+            "border-top-color" => Some(Self::BorderTopColor),
+            "border-right-color" => Some(Self::BorderRightColor),
+            "border-bottom-color" => Some(Self::BorderBottomColor),
+            "border-left-color" => Some(Self::BorderLeftColor),
+            "vertical-align" => Some(Self::VerticalAlign),
+            "list-style-type" => Some(Self::ListStyleType),
+            "list-style-position" => Some(Self::ListStylePosition),
+            "content" => Some(Self::Content),
+            "counter-reset" => Some(Self::CounterReset),
+            "counter-increment" => Some(Self::CounterIncrement),
+            "border-collapse" => Some(Self::BorderCollapse),
+            "border-spacing" => Some(Self::BorderSpacing),
+            "width" => Some(Self::Width),
+            "height" => Some(Self::Height),
+            "max-width" => Some(Self::MaxWidth),
+            "max-height" => Some(Self::MaxHeight),
+            "float" => Some(Self::Float),
+            "clear" => Some(Self::Clear),
+            "initial-letter" => Some(Self::InitialLetter),
+            "line-height" => Some(Self::LineHeight),
+            "letter-spacing" => Some(Self::LetterSpacing),
+            "word-spacing" => Some(Self::WordSpacing),
+            "text-indent" => Some(Self::TextIndent),
+            "white-space" => Some(Self::WhiteSpace),
+            "border-top-left-radius" => Some(Self::BorderTopLeftRadius),
+            "border-top-right-radius" => Some(Self::BorderTopRightRadius),
+            "border-bottom-right-radius" => Some(Self::BorderBottomRightRadius),
+            "border-bottom-left-radius" => Some(Self::BorderBottomLeftRadius),
+            "break-before" | "page-break-before" => Some(Self::BreakBefore),
+            "break-after" | "page-break-after" => Some(Self::BreakAfter),
+            "break-inside" | "page-break-inside" => Some(Self::BreakInside),
+            "orphans" => Some(Self::Orphans),
+            "widows" => Some(Self::Widows),
+            "position" => Some(Self::Position),
+            "top" => Some(Self::Top),
+            "right" => Some(Self::Right),
+            "bottom" => Some(Self::Bottom),
+            "left" => Some(Self::Left),
             _ => None,
         }
     }
@@ -88,6 +240,9 @@ pub struct StyleCacheKey {
     pub id: Option<String>,
     pub class: Option<String>,
     pub ancestor_tags: Vec<String>,
+    pub pseudo: Option<PseudoElement>,
+    pub first_child: bool,
+    pub first_of_type: bool,
 }
 
 pub struct StyleCache {
@@ -163,15 +318,28 @@ impl Style {
             "dd" => {
                 style.insert(PropertyKey::MarginLeft, create_px(40.))
             }
+            // A figure is kept whole with its caption rather than split across columns
+            "figure" => {
+                style.insert(PropertyKey::BreakInside, CSSValue::Break(Break::Avoid))
+            }
             "th" => {
                 style.insert(PropertyKey::FontWeight, CSSValue::FontWeight(FontWeight::Absolute(AbsoluteFontWeight::Bold)));
                 style.insert(PropertyKey::TextAlign, CSSValue::TextAlign(TextAlign::Center))
             }
+            // A quotation is set off by a rule down its left side
             "blockquote" => {
                 style.insert(PropertyKey::MarginTop, create_em(1.));
                 style.insert(PropertyKey::MarginBottom, create_em(1.));
-                style.insert(PropertyKey::MarginLeft, create_px(40.));
+                style.insert(PropertyKey::MarginLeft, create_px(28.));
                 style.insert(PropertyKey::MarginRight, create_px(40.));
+                style.insert(PropertyKey::PaddingLeft, create_px(9.));
+                style.insert_rule(3, 3., Color::rgb8(200, 200, 200));
+            }
+            // A scene break, drawn as its top border
+            "hr" => {
+                style.insert(PropertyKey::MarginTop, create_em(0.5));
+                style.insert(PropertyKey::MarginBottom, create_em(0.5));
+                style.insert_rule(0, 1., Color::rgb8(160, 160, 160));
             }
             "em" => {
                 println!("In em");
@@ -180,8 +348,45 @@ impl Style {
             "strong" => {
                 style.insert(PropertyKey::FontWeight, CSSValue::FontWeight(FontWeight::Absolute(AbsoluteFontWeight::Bold)))
             }
+            "a" | "u" | "ins" => {
+                style.insert(PropertyKey::TextDecorationLine, CSSValue::TextDecoration(TextDecorationLine::Underline))
+            }
+            "s" | "strike" | "del" => {
+                style.insert(PropertyKey::TextDecorationLine, CSSValue::TextDecoration(TextDecorationLine::LineThrough))
+            }
+            "pre" => {
+                style.insert(PropertyKey::WhiteSpace, CSSValue::WhiteSpace(WhiteSpace::Pre));
+                style.insert(PropertyKey::FontFamily, CSSValue::FontFamily(Rc::from([FamilyOwned::Monospace])))
+            }
+            "code" | "kbd" | "samp" | "tt" => {
+                style.insert(PropertyKey::FontFamily, CSSValue::FontFamily(Rc::from([FamilyOwned::Monospace])))
+            }
+            "nobr" => {
+                style.insert(PropertyKey::WhiteSpace, CSSValue::WhiteSpace(WhiteSpace::NoWrap))
+            }
+            "caption" => {
+                style.insert(PropertyKey::TextAlign, CSSValue::TextAlign(TextAlign::Center))
+            }
+            "ul" | "ol" | "menu" => {
+                style.insert(PropertyKey::PaddingLeft, create_px(40.))
+            }
+            "rt" => {
+                style.font_size = Some(create_font_size(0.5))
+            }
+            "sup" => {
+                style.font_size = Some(FontSize::Relative(RelativeFontSize::Smaller));
+                style.insert(PropertyKey::VerticalAlign, CSSValue::VerticalAlign(CssVerticalAlign::Keyword(VerticalAlignKeyword::Super)))
+            }
+            "sub" => {
+                style.font_size = Some(FontSize::Relative(RelativeFontSize::Smaller));
+                style.insert(PropertyKey::VerticalAlign, CSSValue::VerticalAlign(CssVerticalAlign::Keyword(VerticalAlignKeyword::Sub)))
+            }
             _ => ()
         }
+        // Headings stay with the text they introduce
+        if matches!(node_tag, "h1" | "h2" | "h3" | "h4" | "h5" | "h6") {
+            style.insert(PropertyKey::BreakAfter, CSSValue::Break(Break::Avoid));
+        }
         style
     }
     pub fn insert(&mut self, key: PropertyKey, value: CSSValue) {
@@ -203,6 +408,26 @@ impl Style {
     
     pub fn apply_property(&mut self, property: &Property) {
         match property {
+            Property::Margin(margins) => {
+                self.insert(PropertyKey::MarginTop, CSSValue::Length(margins.top.clone()));
+                self.insert(PropertyKey::MarginRight, CSSValue::Length(margins.right.clone()));
+                self.insert(PropertyKey::MarginBottom, CSSValue::Length(margins.bottom.clone()));
+                self.insert(PropertyKey::MarginLeft, CSSValue::Length(margins.left.clone()));
+            }
+            Property::Padding(paddings) => {
+                self.insert(PropertyKey::PaddingTop, CSSValue::Length(paddings.top.clone()));
+                self.insert(PropertyKey::PaddingRight, CSSValue::Length(paddings.right.clone()));
+                self.insert(PropertyKey::PaddingBottom, CSSValue::Length(paddings.bottom.clone()));
+                self.insert(PropertyKey::PaddingLeft, CSSValue::Length(paddings.left.clone()));
+            }
+            Property::Font(font) => {
+                self.font_size = Some(font.size.clone());
+                self.insert(PropertyKey::FontWeight, CSSValue::FontWeight(font.weight.clone()));
+                self.insert(PropertyKey::FontStyle, CSSValue::TextStyle(resolve_text_style(&font.style)));
+                self.insert(PropertyKey::FontVariant, CSSValue::FontVariant(font.variant_caps));
+                self.insert(PropertyKey::LineHeight, CSSValue::LineHeight(font.line_height.clone()));
+                if let Some(families) = resolve_font_family(&font.family) { self.insert(PropertyKey::FontFamily, CSSValue::FontFamily(families)) }
+            }
             Property::MarginTop(value) => self.insert(PropertyKey::MarginTop, CSSValue::Length(value.clone())),
             Property::MarginRight(value) => self.insert(PropertyKey::MarginRight, CSSValue::Length(value.clone())),
             Property::MarginBottom(value) => self.insert(PropertyKey::MarginBottom, CSSValue::Length(value.clone())),
@@ -213,17 +438,134 @@ impl Style {
             Property::PaddingLeft(value) => self.insert(PropertyKey::PaddingLeft, CSSValue::Length(value.clone())),
             Property::FontWeight(value) => self.insert(PropertyKey::FontWeight, CSSValue::FontWeight(value.clone())),
             Property::TextAlign(value) => self.insert(PropertyKey::TextAlign, CSSValue::TextAlign(value.clone())),
-            Property::FontStyle(value) => self.insert(PropertyKey::FontStyle, CSSValue::TextStyle(
-                match value {
-                    FontStyle::Normal => floem_renderer::text::Style::Normal,
-                    FontStyle::Italic => floem_renderer::text::Style::Italic,
-                    FontStyle::Oblique(_) => floem_renderer::text::Style::Italic,
-                }
-            )),
+            Property::FontStyle(value) => self.insert(PropertyKey::FontStyle, CSSValue::TextStyle(resolve_text_style(value))),
             Property::FontSize(value) => self.font_size = Some(value.clone()),
+            Property::FontFamily(families) => {
+                if let Some(families) = resolve_font_family(families) { self.insert(PropertyKey::FontFamily, CSSValue::FontFamily(families)) }
+            }
+            Property::FontVariantCaps(value) => self.insert(PropertyKey::FontVariant, CSSValue::FontVariant(*value)),
+            Property::TextTransform(value) => self.insert(PropertyKey::TextTransform, CSSValue::TextTransform(value.case)),
+            Property::Custom(_) => {
+                if let Some(variant) = resolve_font_variant(property) { self.insert(PropertyKey::FontVariant, CSSValue::FontVariant(variant)) }
+                if let Some(collapse) = resolve_border_collapse(property) { self.insert(PropertyKey::BorderCollapse, CSSValue::BorderCollapse(collapse)) }
+                if let Some(float) = resolve_float(property) { self.insert(PropertyKey::Float, CSSValue::Float(float)) }
+                if let Some(clear) = resolve_clear(property) { self.insert(PropertyKey::Clear, CSSValue::Clear(clear)) }
+                if let Some(initial) = resolve_initial_letter(property) { self.insert(PropertyKey::InitialLetter, CSSValue::InitialLetter(initial)) }
+                if let Some((key, value)) = resolve_break(property) { self.insert(key, CSSValue::Break(value)) }
+                if let Some((key, count)) = resolve_line_count(property) { self.insert(key, CSSValue::LineCount(count)) }
+                match resolve_content_property(property) {
+                    Some(ContentProperty::Content(items))           => self.insert(PropertyKey::Content, CSSValue::Content(items.map(Rc::from))),
+                    Some(ContentProperty::CounterReset(counters))   => self.insert(PropertyKey::CounterReset, CSSValue::Counters(counters.into())),
+                    Some(ContentProperty::CounterIncrement(counters)) => self.insert(PropertyKey::CounterIncrement, CSSValue::Counters(counters.into())),
+                    None => ()
+                }
+            }
+
+            Property::Color(color) => self.insert_color(PropertyKey::Color, color),
+            Property::BackgroundColor(color) => self.insert_color(PropertyKey::BackgroundColor, color),
+            Property::Background(backgrounds) => {
+                if let Some(background) = backgrounds.last() { self.insert_color(PropertyKey::BackgroundColor, &background.color) }
+            }
+            Property::TextDecorationLine(line, _) => self.insert(PropertyKey::TextDecorationLine, CSSValue::TextDecoration(*line)),
+            Property::TextDecorationColor(color, _) => self.insert_color(PropertyKey::TextDecorationColor, color),
+            Property::TextDecoration(decoration, _) => {
+                self.insert(PropertyKey::TextDecorationLine, CSSValue::TextDecoration(decoration.line));
+                self.insert_color(PropertyKey::TextDecorationColor, &decoration.color);
+            }
+
+            Property::Border(border) => {
+                for side in 0..4 { self.insert_border(side, &border.width, &border.style, &border.color) }
+            }
+            Property::BorderTop(border)     => self.insert_border(0, &border.width, &border.style, &border.color),
+            Property::BorderRight(border)   => self.insert_border(1, &border.width, &border.style, &border.color),
+            Property::BorderBottom(border)  => self.insert_border(2, &border.width, &border.style, &border.color),
+            Property::BorderLeft(border)    => self.insert_border(3, &border.width, &border.style, &border.color),
+            Property::BorderWidth(widths) => {
+                for (side, width) in [&widths.top, &widths.right, &widths.bottom, &widths.left].into_iter().enumerate() {
+                    self.insert(BORDER_WIDTH_KEYS[side], CSSValue::BorderWidth(width.clone()))
+                }
+            }
+            Property::BorderStyle(styles) => {
+                for (side, style) in [&styles.top, &styles.right, &styles.bottom, &styles.left].into_iter().enumerate() {
+                    self.insert(BORDER_STYLE_KEYS[side], CSSValue::BorderStyle(*style))
+                }
+            }
+            Property::BorderColor(colors) => {
+                for (side, color) in [&colors.top, &colors.right, &colors.bottom, &colors.left].into_iter().enumerate() {
+                    self.insert_color(BORDER_COLOR_KEYS[side], color)
+                }
+            }
+            Property::BorderRadius(radius, _) => {
+                for (corner, size) in [&radius.top_left, &radius.top_right, &radius.bottom_right, &radius.bottom_left].into_iter().enumerate() {
+                    self.insert(BORDER_RADIUS_KEYS[corner], CSSValue::Radius(size.0.clone()))
+                }
+            }
+            // Elliptical corners are drawn circular, with the horizontal radius
+            Property::BorderTopLeftRadius(size, _)      => self.insert(PropertyKey::BorderTopLeftRadius, CSSValue::Radius(size.0.clone())),
+            Property::BorderTopRightRadius(size, _)     => self.insert(PropertyKey::BorderTopRightRadius, CSSValue::Radius(size.0.clone())),
+            Property::BorderBottomRightRadius(size, _)  => self.insert(PropertyKey::BorderBottomRightRadius, CSSValue::Radius(size.0.clone())),
+            Property::BorderBottomLeftRadius(size, _)   => self.insert(PropertyKey::BorderBottomLeftRadius, CSSValue::Radius(size.0.clone())),
+            Property::ListStyleType(value) => {
+                if let Some(list_type) = resolve_list_style_type(value) { self.insert(PropertyKey::ListStyleType, CSSValue::ListStyleType(list_type)) }
+            }
+            Property::ListStylePosition(value) => self.insert(PropertyKey::ListStylePosition, CSSValue::ListStylePosition(*value)),
+            Property::ListStyle(list_style) => {
+                if let Some(list_type) = resolve_list_style_type(&list_style.list_style_type) { self.insert(PropertyKey::ListStyleType, CSSValue::ListStyleType(list_type)) }
+                self.insert(PropertyKey::ListStylePosition, CSSValue::ListStylePosition(list_style.position));
+            }
+            Property::Width(size)       => self.insert(PropertyKey::Width, CSSValue::Size(resolve_size(size))),
+            Property::Height(size)      => self.insert(PropertyKey::Height, CSSValue::Size(resolve_size(size))),
+            Property::MaxWidth(size)    => self.insert(PropertyKey::MaxWidth, CSSValue::Size(resolve_max_size(size))),
+            Property::MaxHeight(size)   => self.insert(PropertyKey::MaxHeight, CSSValue::Size(resolve_max_size(size))),
+            Property::LineHeight(value) => self.insert(PropertyKey::LineHeight, CSSValue::LineHeight(value.clone())),
+            Property::Position(position) => self.insert(PropertyKey::Position, CSSValue::Position(resolve_position(position))),
+            Property::Inset(insets) => {
+                self.insert(PropertyKey::Top, CSSValue::Length(insets.top.clone()));
+                self.insert(PropertyKey::Right, CSSValue::Length(insets.right.clone()));
+                self.insert(PropertyKey::Bottom, CSSValue::Length(insets.bottom.clone()));
+                self.insert(PropertyKey::Left, CSSValue::Length(insets.left.clone()));
+            }
+            Property::Top(value)    => self.insert(PropertyKey::Top, CSSValue::Length(value.clone())),
+            Property::Right(value)  => self.insert(PropertyKey::Right, CSSValue::Length(value.clone())),
+            Property::Bottom(value) => self.insert(PropertyKey::Bottom, CSSValue::Length(value.clone())),
+            Property::Left(value)   => self.insert(PropertyKey::Left, CSSValue::Length(value.clone())),
+            Property::LetterSpacing(value) => self.insert(PropertyKey::LetterSpacing, CSSValue::Spacing(value.clone())),
+            Property::WordSpacing(value) => self.insert(PropertyKey::WordSpacing, CSSValue::Spacing(value.clone())),
+            Property::WhiteSpace(value) => self.insert(PropertyKey::WhiteSpace, CSSValue::WhiteSpace(*value)),
+            Property::TextIndent(value) => self.insert(PropertyKey::TextIndent, CSSValue::TextIndent(value.value.clone())),
+            Property::BorderSpacing(spacing) => self.insert(PropertyKey::BorderSpacing, CSSValue::BorderSpacing(spacing.0.clone())),
+            Property::VerticalAlign(value) => self.insert(PropertyKey::VerticalAlign, CSSValue::VerticalAlign(value.clone())),
+            Property::BorderTopWidth(width)     => self.insert(PropertyKey::BorderTopWidth, CSSValue::BorderWidth(width.clone())),
+            Property::BorderRightWidth(width)   => self.insert(PropertyKey::BorderRightWidth, CSSValue::BorderWidth(width.clone())),
+            Property::BorderBottomWidth(width)  => self.insert(PropertyKey::BorderBottomWidth, CSSValue::BorderWidth(width.clone())),
+            Property::BorderLeftWidth(width)    => self.insert(PropertyKey::BorderLeftWidth, CSSValue::BorderWidth(width.clone())),
+            Property::BorderTopStyle(style)     => self.insert(PropertyKey::BorderTopStyle, CSSValue::BorderStyle(*style)),
+            Property::BorderRightStyle(style)   => self.insert(PropertyKey::BorderRightStyle, CSSValue::BorderStyle(*style)),
+            Property::BorderBottomStyle(style)  => self.insert(PropertyKey::BorderBottomStyle, CSSValue::BorderStyle(*style)),
+            Property::BorderLeftStyle(style)    => self.insert(PropertyKey::BorderLeftStyle, CSSValue::BorderStyle(*style)),
+            Property::BorderTopColor(color)     => self.insert_color(PropertyKey::BorderTopColor, color),
+            Property::BorderRightColor(color)   => self.insert_color(PropertyKey::BorderRightColor, color),
+            Property::BorderBottomColor(color)  => self.insert_color(PropertyKey::BorderBottomColor, color),
+            Property::BorderLeftColor(color)    => self.insert_color(PropertyKey::BorderLeftColor, color),
             _ => {} // Ignore unsupported properties
         }
     }
+
+    fn insert_color(&mut self, key: PropertyKey, color: &CssColor) {
+        if let Some(value) = resolve_color(color) { self.insert(key, value) }
+    }
+
+    fn insert_rule(&mut self, side: usize, width: f32, color: Color) {
+        self.insert(BORDER_WIDTH_KEYS[side], CSSValue::BorderWidth(BorderSideWidth::Length(Length::Value(LengthValue::Px(width)))));
+        self.insert(BORDER_STYLE_KEYS[side], CSSValue::BorderStyle(LineStyle::Solid));
+        self.insert(BORDER_COLOR_KEYS[side], CSSValue::Color(Some(color)));
+    }
+
+    fn insert_border(&mut self, side: usize, width: &BorderSideWidth, style: &LineStyle, color: &CssColor) {
+        self.insert(BORDER_WIDTH_KEYS[side], CSSValue::BorderWidth(width.clone()));
+        self.insert(BORDER_STYLE_KEYS[side], CSSValue::BorderStyle(*style));
+        self.insert_color(BORDER_COLOR_KEYS[side], color);
+    }
 }
 
 struct MatchedRule<'a,'b> {
@@ -231,7 +573,7 @@ struct MatchedRule<'a,'b> {
     source_order: usize,
     declarations: &'a [Property<'b>]
 }
-pub fn apply_style_sheet(style_sheet: & StyleSheet, node: &Node, style: &mut Style, parse_state: &ParseState, document: &Document) {
+pub fn apply_style_sheet(style_sheet: & StyleSheet, node: &Node, pseudo: Option<PseudoElement>, style: &mut Style, parse_state: &ParseState, document: &Document) {
     let mut matched_rules: Vec<MatchedRule> = Vec::with_capacity(16);
     let node_tag = node.tag_name().name();
     let node_id = node.attribute("id");
@@ -243,7 +585,7 @@ pub fn apply_style_sheet(style_sheet: & StyleSheet, node: &Node, style: &mut Sty
                 for selector in &style_rule.selectors.0 {
                     // Fast pre-filtering: check if selector could possibly match this node
                     if can_selector_match(selector, node_tag, node_id, node_class) {
-                        if selector_matches2(selector, node, parse_state, document) {
+                        if selector_matches2(selector, pseudo, node, parse_state, document) {
                             matched_rules.push(MatchedRule {
                                 specificity: selector.specificity(),
                                 source_order: index,
@@ -260,52 +602,7 @@ pub fn apply_style_sheet(style_sheet: & StyleSheet, node: &Node, style: &mut Sty
         a.specificity.cmp(&b.specificity)
             .then_with(|| a.source_order.cmp(&b.source_order))
     });
-    for matched_rule in matched_rules {
-        for decl in matched_rule.declarations.iter() {
-            if decl.property_id().is_shorthand() {
-                match decl {
-                    Property::Margin(margins) => {
-                        style.insert(PropertyKey::MarginTop, CSSValue::Length(margins.top.clone()));
-                        style.insert(PropertyKey::MarginRight, CSSValue::Length(margins.right.clone()));
-                        style.insert(PropertyKey::MarginBottom, CSSValue::Length(margins.bottom.clone()));
-                        style.insert(PropertyKey::MarginLeft, CSSValue::Length(margins.left.clone()));
-                    }
-                    Property::Padding(paddings) => {
-                        style.insert(PropertyKey::PaddingTop, CSSValue::Length(paddings.top.clone()));
-                        style.insert(PropertyKey::PaddingRight, CSSValue::Length(paddings.right.clone()));
-                        style.insert(PropertyKey::PaddingBottom, CSSValue::Length(paddings.bottom.clone()));
-                        style.insert(PropertyKey::PaddingLeft, CSSValue::Length(paddings.left.clone()));
-                    }
-                    
-                    _ => ()
-                }
-                continue
-            }
-            match decl {
-                Property::MarginTop(value) => {style.insert(PropertyKey::MarginTop, CSSValue::Length(value.clone()));}
-                Property::MarginRight(value) => {style.insert(PropertyKey::MarginRight, CSSValue::Length(value.clone()));}
-                Property::MarginBottom(value) => {style.insert(PropertyKey::MarginBottom, CSSValue::Length(value.clone()));}
-                Property::MarginLeft(value) => {style.insert(PropertyKey::MarginLeft, CSSValue::Length(value.clone()));}
-                Property::PaddingTop(value) => {style.insert(PropertyKey::PaddingTop, CSSValue::Length(value.clone()));}
-                Property::PaddingRight(value) => {style.insert(PropertyKey::PaddingRight, CSSValue::Length(value.clone()));}
-                Property::PaddingBottom(value) => {style.insert(PropertyKey::PaddingBottom, CSSValue::Length(value.clone()));}
-                Property::PaddingLeft(value) => {style.insert(PropertyKey::PaddingLeft, CSSValue::Length(value.clone()));}
-                Property::FontSize(font_size) => {style.font_size = Some(font_size.to_owned());}
-                Property::FontWeight(font_weight) => {style.insert(PropertyKey::FontWeight, CSSValue::FontWeight(font_weight.clone()))}
-                Property::TextAlign(text_align) => {style.insert(PropertyKey::TextAlign, CSSValue::TextAlign(text_align.clone()))}
-                Property::FontStyle(font_style) => {
-                    let text_style = match font_style {
-                        FontStyle::Normal => floem_renderer::text::Style::Normal,
-                        FontStyle::Italic => floem_renderer::text::Style::Italic,
-                        FontStyle::Oblique(_) => floem_renderer::text::Style::Oblique,
-                    };
-                    style.insert(PropertyKey::FontStyle, CSSValue::TextStyle(text_style));
-                }
-                
-                _ => ()
-            }
-        }
-    }
+    apply_matched_rules(&matched_rules, style);
 }
 
 pub fn resolve_style_cached(style_sheets: &Vec<StyleSheet>, node: &Node, font: &mut Attrs, mut parse_state: ParseState, document: &Document, cache: &mut StyleCache) -> (Margins, ParseState) {
@@ -317,6 +614,9 @@ pub fn resolve_style_cached(style_sheets: &Vec<StyleSheet>, node: &Node, font: &
             .filter_map(|id| document.get_node(*id))
             .map(|n| n.tag_name().name().to_string())
             .collect(),
+        pseudo: None,
+        first_child: is_first_child(node),
+        first_of_type: is_first_of_type(node),
     };
     
     if let Some(cached_style) = cache.get(&cache_key) {
@@ -327,15 +627,47 @@ pub fn resolve_style_cached(style_sheets: &Vec<StyleSheet>, node: &Node, font: &
     let mut margins = Margins {top: 0., right: 0., bottom: 0., left: 0.};
     
     for style_sheet in style_sheets {
-        apply_style_sheet(style_sheet, &node, &mut style, &parse_state, document);
+        apply_style_sheet(style_sheet, &node, None, &mut style, &parse_state, document);
     }
     
     cache.insert(cache_key, style.clone());
     apply_cached_style(&style, font, parse_state)
 }
 
+/// Style of a pseudo-element, None when no rule applies to it or a `::before` or `::after` has no content. `parse_state` is the element's own resolved state.
+pub fn resolve_pseudo_style_cached(style_sheets: &Vec<StyleSheet>, node: &Node, pseudo: PseudoElement, font: &mut Attrs, parse_state: ParseState, document: &Document, cache: &mut StyleCache) -> Option<(Margins, ParseState)> {
+    let cache_key = StyleCacheKey {
+        tag_name: node.tag_name().name().to_string(),
+        id: node.attribute("id").map(|s| s.to_string()),
+        class: node.attribute("class").map(|s| s.to_string()),
+        ancestor_tags: parse_state.ancestors.iter()
+            .filter_map(|id| document.get_node(*id))
+            .map(|n| n.tag_name().name().to_string())
+            .collect(),
+        pseudo: Some(pseudo),
+        first_child: is_first_child(node),
+        first_of_type: is_first_of_type(node),
+    };
+    if cache.get(&cache_key).is_none() {
+        let mut style = Style::with_capacity(0);
+        for style_sheet in style_sheets {
+            apply_style_sheet(style_sheet, &node, Some(pseudo), &mut style, &parse_state, document);
+        }
+        cache.insert(cache_key.clone(), style);
+    }
+    let style = cache.get(&cache_key).unwrap();
+    let generated = match pseudo {
+        PseudoElement::Before | PseudoElement::After => matches!(style.properties.get(&PropertyKey::Content), Some(CSSValue::Content(Some(_)))),
+        PseudoElement::FirstLetter | PseudoElement::FirstLine => style.font_size.is_some() || !style.properties.is_empty(),
+    };
+    if !generated { return None }
+    Some(apply_cached_style(style, font, parse_state))
+}
+
 fn apply_cached_style(style: &Style, font: &mut Attrs, mut parse_state: ParseState) -> (Margins, ParseState) {
     let mut margins = Margins {top: 0., right: 0., bottom: 0., left: 0.};
+    let mut paddings = Margins {top: 0., right: 0., bottom: 0., left: 0.};
+    let parent_font_size = font.font_size as f64;
     
     if let Some(font_size) = &style.font_size {
         let resolved_font_size = resolve_font_size(font_size, &parse_state, (font.font_size as f64)).round();
@@ -343,6 +675,26 @@ fn apply_cached_style(style: &Style, font: &mut Attrs, mut parse_state: ParseSta
     }
     
     let font_size = font.font_size as f64;
+    let mut background          = None;
+    let mut decoration_line     = None;
+    let mut decoration_color    = None;
+    let mut border_widths       = [None; 4];
+    let mut border_styles       = [LineStyle::None; 4];
+    let mut border_colors       = [None; 4];
+    let mut radii               = [0.; 4];
+    let mut vertical_align      = None;
+    parse_state.content             = None;
+    parse_state.counter_reset       = None;
+    parse_state.counter_increment   = None;
+    parse_state.box_size            = BoxSize::default();
+    parse_state.float               = None;
+    parse_state.clear               = Clear::None;
+    parse_state.initial_letter      = None;
+    parse_state.fragmentation.break_before  = Break::Auto;
+    parse_state.fragmentation.break_after   = Break::Auto;
+    parse_state.fragmentation.break_inside  = Break::Auto;
+    parse_state.position            = Position::Static;
+    parse_state.insets              = Insets::default();
     for (key, value) in style.properties.iter() {
         match value {
             CSSValue::Length(value) => {
@@ -351,55 +703,183 @@ fn apply_cached_style(style: &Style, font: &mut Attrs, mut parse_state: ParseSta
                     PropertyKey::MarginRight    => margins.right    += resolve_length(value, &parse_state, font_size),
                     PropertyKey::MarginBottom   => margins.bottom   += resolve_length(value, &parse_state, font_size),
                     PropertyKey::MarginLeft     => margins.left     += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::PaddingTop     => margins.top      += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::PaddingRight   => margins.right    += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::PaddingBottom  => margins.bottom   += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::PaddingLeft    => margins.left     += resolve_length(value, &parse_state, font_size),
+                    PropertyKey::PaddingTop     => paddings.top     += resolve_length(value, &parse_state, font_size),
+                    PropertyKey::PaddingRight   => paddings.right   += resolve_length(value, &parse_state, font_size),
+                    PropertyKey::PaddingBottom  => paddings.bottom  += resolve_length(value, &parse_state, font_size),
+                    PropertyKey::PaddingLeft    => paddings.left    += resolve_length(value, &parse_state, font_size),
+                    PropertyKey::Top            => parse_state.insets.top       = resolve_inset(value, &parse_state, font_size),
+                    PropertyKey::Right          => parse_state.insets.right     = resolve_inset(value, &parse_state, font_size),
+                    PropertyKey::Bottom         => parse_state.insets.bottom    = resolve_inset(value, &parse_state, font_size),
+                    PropertyKey::Left           => parse_state.insets.left      = resolve_inset(value, &parse_state, font_size),
                     _ => ()
                 }
             }
             CSSValue::FontWeight(value) => {parse_state.font_weight = resolve_font_weight(value);}
             CSSValue::TextAlign(value)  => parse_state.text_align = value.clone(),
             CSSValue::TextStyle(text_style)    => parse_state.text_style = *text_style,
+            CSSValue::FontFamily(families)     => parse_state.font_family = Some(families.clone()),
+            CSSValue::FontVariant(variant)     => parse_state.font_variant = *variant,
+            CSSValue::TextTransform(transform) => parse_state.text_transform = *transform,
+            CSSValue::TextDecoration(line)     => decoration_line = Some(*line),
+            CSSValue::Color(color) => {
+                match key {
+                    PropertyKey::Color                  => if color.is_some() { parse_state.color = *color },
+                    PropertyKey::BackgroundColor        => background = *color,
+                    PropertyKey::TextDecorationColor    => decoration_color = *color,
+                    PropertyKey::BorderTopColor         => border_colors[0] = *color,
+                    PropertyKey::BorderRightColor       => border_colors[1] = *color,
+                    PropertyKey::BorderBottomColor      => border_colors[2] = *color,
+                    PropertyKey::BorderLeftColor        => border_colors[3] = *color,
+                    _ => ()
+                }
+            }
+            CSSValue::BorderWidth(width) => {
+                if let Some(side) = BORDER_WIDTH_KEYS.iter().position(|border_key| border_key == key) {
+                    border_widths[side] = Some(resolve_border_width(width, &parse_state, font_size));
+                }
+            }
+            CSSValue::BorderStyle(line_style) => {
+                if let Some(side) = BORDER_STYLE_KEYS.iter().position(|border_key| border_key == key) {
+                    border_styles[side] = *line_style;
+                }
+            }
+            CSSValue::VerticalAlign(value) => vertical_align = Some(value),
+            CSSValue::ListStyleType(list_type) => parse_state.list_context.list_type = *list_type,
+            CSSValue::Content(items) => parse_state.content = items.clone(),
+            CSSValue::Counters(counters) => match key {
+                PropertyKey::CounterReset       => parse_state.counter_reset = Some(counters.clone()),
+                PropertyKey::CounterIncrement   => parse_state.counter_increment = Some(counters.clone()),
+                _ => ()
+            },
+            CSSValue::BorderCollapse(collapse) => parse_state.border_collapse = *collapse,
+            CSSValue::BorderSpacing(length) => parse_state.border_spacing = resolve_length_value(length, &parse_state, font_size),
+            CSSValue::Size(length) => {
+                let is_height = matches!(key, PropertyKey::Height | PropertyKey::MaxHeight);
+                // Percentage heights depend on the column height, which is only known when rendering
+                let value = length.as_ref()
+                    .filter(|length| !(is_height && matches!(length, LengthPercentage::Percentage(_))))
+                    .map(|length| resolve_length_percentage(length, &parse_state, font_size, false));
+                match key {
+                    PropertyKey::Width      => parse_state.box_size.width = value,
+                    PropertyKey::Height     => parse_state.box_size.height = value,
+                    PropertyKey::MaxWidth   => parse_state.box_size.max_width = value,
+                    PropertyKey::MaxHeight  => parse_state.box_size.max_height = value,
+                    _ => ()
+                }
+            }
+            CSSValue::Float(float) => parse_state.float = *float,
+            CSSValue::Clear(clear) => parse_state.clear = *clear,
+            CSSValue::InitialLetter(initial) => parse_state.initial_letter = *initial,
+            // Numbers are inherited as factors and recomputed for each font size, lengths as they resolve here
+            CSSValue::LineHeight(line_height) => parse_state.line_height = match line_height {
+                CssLineHeight::Normal           => LineHeight::Normal,
+                CssLineHeight::Number(factor)   => LineHeight::Factor(*factor as f64),
+                CssLineHeight::Length(length)   => LineHeight::Length(resolve_length_percentage(length, &parse_state, font_size, true)),
+            },
+            CSSValue::Spacing(spacing) => {
+                let value = match spacing {
+                    Spacing::Normal         => 0.,
+                    Spacing::Length(length) => resolve_length_value(length, &parse_state, font_size),
+                };
+                match key {
+                    PropertyKey::LetterSpacing  => parse_state.letter_spacing = value,
+                    PropertyKey::WordSpacing    => parse_state.word_spacing = value,
+                    _ => ()
+                }
+            }
+            CSSValue::WhiteSpace(white_space) => parse_state.white_space = *white_space,
+            CSSValue::TextIndent(indent) => parse_state.text_indent = resolve_length_percentage(indent, &parse_state, font_size, false),
+            CSSValue::ListStylePosition(position) => parse_state.list_context.marker_inside = *position == ListStylePosition::Inside,
+            CSSValue::Radius(radius) => {
+                if let Some(corner) = BORDER_RADIUS_KEYS.iter().position(|radius_key| radius_key == key) {
+                    radii[corner] = resolve_length_percentage(radius, &parse_state, font_size, false);
+                }
+            }
+            CSSValue::Break(value) => match key {
+                PropertyKey::BreakBefore    => parse_state.fragmentation.break_before = *value,
+                PropertyKey::BreakAfter     => parse_state.fragmentation.break_after = *value,
+                // Only avoiding means anything inside a block, forced breaks there are ignored
+                PropertyKey::BreakInside    => parse_state.fragmentation.break_inside = if *value == Break::Avoid { Break::Avoid } else { Break::Auto },
+                _ => ()
+            },
+            CSSValue::LineCount(count) => match key {
+                PropertyKey::Orphans    => parse_state.fragmentation.orphans = *count,
+                PropertyKey::Widows     => parse_state.fragmentation.widows = *count,
+                _ => ()
+            },
+            CSSValue::Position(position) => parse_state.position = *position,
         }
     }
-    (margins, parse_state)
-}
 
-pub fn resolve_style(style_sheets: &Vec<StyleSheet>, node: &Node, font: &mut Attrs, mut parse_state: ParseState, document: &Document) -> (Margins, ParseState){
-    let mut style = Style::new(node.tag_name().name());
-    let mut margins = Margins {top: 0., right: 0., bottom: 0., left: 0.};
-    for style_sheet in style_sheets {
-        apply_style_sheet(style_sheet, &node, &mut style, &parse_state, document);
+    // Decorations propagate to every inline descendant, backgrounds only to the element's own box
+    if let Some(line) = decoration_line.filter(|line| !line.is_empty()) {
+        let mut inline_style = parse_state.inline_style.as_deref().cloned().unwrap_or_default();
+        inline_style.decoration |= line;
+        inline_style.decoration_color = decoration_color.or(parse_state.color);
+        parse_state.inline_style = Some(Rc::new(inline_style));
     }
-    if let Some(font_size) = &style.font_size {
-        let resolved_font_size = resolve_font_size(font_size, &parse_state, (font.font_size as f64)).round();
-        if resolved_font_size != 0. { *font = font.font_size(resolved_font_size as f32); }
-    }
-    let font_size = font.font_size as f64;
-    for (key, value) in style.properties.iter() {
-        match value {
-            CSSValue::Length(value) => {
-                match key {
-                    PropertyKey::MarginTop      => margins.top      += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::MarginRight    => margins.right    += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::MarginBottom   => margins.bottom   += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::MarginLeft     => margins.left     += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::PaddingTop     => margins.top      += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::PaddingRight   => margins.right    += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::PaddingBottom  => margins.bottom   += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::PaddingLeft    => margins.left     += resolve_length(value, &parse_state, font_size),
-                    _ => ()
-                }
+    // Shifts accumulate, a sup inside a sup sits above its parent's raised baseline
+    if let Some(value) = vertical_align {
+        let parent_align = parse_state.inline_style.as_ref().map(|style| style.vertical_align).unwrap_or_default();
+        let parent_shift = match parent_align { VerticalAlign::Shift(shift) => shift, _ => 0. };
+        let vertical_align = match value {
+            CssVerticalAlign::Keyword(keyword) => match keyword {
+                VerticalAlignKeyword::Baseline  => parent_align,
+                VerticalAlignKeyword::Super     => VerticalAlign::Shift(parent_shift + (parent_font_size * SUPER_SHIFT) as f32),
+                VerticalAlignKeyword::Sub       => VerticalAlign::Shift(parent_shift - (parent_font_size * SUB_SHIFT) as f32),
+                VerticalAlignKeyword::Middle    => VerticalAlign::Middle,
+                VerticalAlignKeyword::Top | VerticalAlignKeyword::TextTop          => VerticalAlign::Top,
+                VerticalAlignKeyword::Bottom | VerticalAlignKeyword::TextBottom    => VerticalAlign::Bottom,
             }
-            CSSValue::FontWeight(value) => {parse_state.font_weight = resolve_font_weight(value);}
-            CSSValue::TextAlign(value)  => parse_state.text_align = value.clone(),
-            CSSValue::TextStyle(text_style)    => parse_state.text_style = *text_style,
+            CssVerticalAlign::Length(length) => {
+                // Percentages refer to the line height, approximated by the font size
+                let shift = resolve_length_percentage(length, &parse_state, font_size, true);
+                VerticalAlign::Shift(parent_shift + shift as f32)
+            }
+        };
+        if vertical_align != parent_align {
+            let mut inline_style = parse_state.inline_style.as_deref().cloned().unwrap_or_default();
+            inline_style.vertical_align = vertical_align;
+            parse_state.inline_style = Some(Rc::new(inline_style));
         }
     }
+    let mut borders = [BorderSide::default(); 4];
+    for side in 0..4 {
+        if matches!(border_styles[side], LineStyle::None | LineStyle::Hidden) { continue }
+        borders[side] = BorderSide {
+            width: border_widths[side].unwrap_or(MEDIUM_BORDER_WIDTH),
+            color: border_colors[side].or(parse_state.color).unwrap_or(Color::BLACK),
+        };
+    }
+    let background = background.filter(|color| color.a != 0);
+    parse_state.block_decoration = None;
+    if background.is_some() || borders.iter().any(|border| border.width > 0.) {
+        parse_state.block_decoration = Some(BlockDecoration {
+            background,
+            borders,
+            x: -(paddings.left + borders[3].width),
+            width: paddings.left + borders[3].width + paddings.right + borders[1].width,
+            margin_top: margins.top,
+            margin_bottom: margins.bottom,
+            radii,
+            cells: Vec::new(),
+        });
+    }
+    margins.top     += paddings.top     + borders[0].width;
+    margins.right   += paddings.right   + borders[1].width;
+    margins.bottom  += paddings.bottom  + borders[2].width;
+    margins.left    += paddings.left    + borders[3].width;
     (margins, parse_state)
 }
 
+pub fn resolve_style(style_sheets: &Vec<StyleSheet>, node: &Node, font: &mut Attrs, parse_state: ParseState, document: &Document) -> (Margins, ParseState){
+    let mut style = Style::new(node.tag_name().name());
+    for style_sheet in style_sheets {
+        apply_style_sheet(style_sheet, &node, None, &mut style, &parse_state, document);
+    }
+    apply_cached_style(&style, font, parse_state)
+}
+
 fn apply_matched_rules(matched_rules: &[MatchedRule], style: &mut Style) {
     for matched_rule in matched_rules {
         for property in matched_rule.declarations {
@@ -444,41 +924,12 @@ pub fn apply_style_sheet_scraper(style_sheet: &StyleSheet, element: &ElementRef,
     apply_matched_rules(&matched_rules, style);
 }
 
-pub fn resolve_style_scraper(style_sheets: &Vec<StyleSheet>, element: &ElementRef, font: &mut Attrs, mut parse_state: ParseState) -> (Margins, ParseState) {
+pub fn resolve_style_scraper(style_sheets: &Vec<StyleSheet>, element: &ElementRef, font: &mut Attrs, parse_state: ParseState) -> (Margins, ParseState) {
     let mut style = Style::new(element.value().name());
-    let mut margins = Margins {top: 0., right: 0., bottom: 0., left: 0.};
-    
     for style_sheet in style_sheets {
         apply_style_sheet_scraper(style_sheet, element, &mut style);
     }
-    
-    if let Some(font_size) = &style.font_size {
-        let resolved_font_size = resolve_font_size(font_size, &parse_state, font.font_size as f64).round();
-        if resolved_font_size != 0. { *font = font.font_size(resolved_font_size as f32); }
-    }
-    
-    let font_size = font.font_size as f64;
-    for (key, value) in style.properties.iter() {
-        match value {
-            CSSValue::Length(value) => {
-                match key {
-                    PropertyKey::MarginTop      => margins.top      += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::MarginRight    => margins.right    += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::MarginBottom   => margins.bottom   += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::MarginLeft     => margins.left     += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::PaddingTop     => margins.top      += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::PaddingRight   => margins.right    += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::PaddingBottom  => margins.bottom   += resolve_length(value, &parse_state, font_size),
-                    PropertyKey::PaddingLeft    => margins.left     += resolve_length(value, &parse_state, font_size),
-                    _ => ()
-                }
-            }
-            CSSValue::FontWeight(value) => {parse_state.font_weight = resolve_font_weight(value);}
-            CSSValue::TextAlign(value)  => parse_state.text_align = value.clone(),
-            CSSValue::TextStyle(text_style)    => parse_state.text_style = *text_style,
-        }
-    }
-    (margins, parse_state)
+    apply_cached_style(&style, font, parse_state)
 }
 
 
@@ -510,6 +961,179 @@ fn resolve_font_size(value: &FontSize, parse_state: &ParseState, font_size: f64)
         }
     }
 }
+// Keeps the declared order so GlyphCache can fall back through the list per glyph
+fn resolve_font_family(families: &[FontFamily]) -> Option<Rc<[FamilyOwned]>> {
+    let families: Vec<FamilyOwned> = families.iter().filter_map(|family| match family {
+        FontFamily::FamilyName(name) => name.to_css_string(PrinterOptions::default()).ok()
+            .map(|name| FamilyOwned::Name(name.trim_matches(|ch| ch == '"' || ch == '\'').to_string())),
+        FontFamily::Generic(generic) => match generic {
+            GenericFontFamily::Serif | GenericFontFamily::UISerif                   => Some(FamilyOwned::Serif),
+            GenericFontFamily::SansSerif | GenericFontFamily::UISansSerif
+            | GenericFontFamily::SystemUI | GenericFontFamily::UIRounded            => Some(FamilyOwned::SansSerif),
+            GenericFontFamily::Monospace | GenericFontFamily::UIMonospace           => Some(FamilyOwned::Monospace),
+            GenericFontFamily::Cursive                                              => Some(FamilyOwned::Cursive),
+            GenericFontFamily::Fantasy                                              => Some(FamilyOwned::Fantasy),
+            _ => None,
+        }
+    }).collect();
+    if families.is_empty() { return None }
+    Some(families.into())
+}
+
+// lightningcss has no `font-variant` shorthand, so it arrives as an unknown property
+fn resolve_font_variant(property: &Property) -> Option<FontVariantCaps> {
+    let Property::Custom(custom) = property else { return None };
+    if !custom.name.as_ref().eq_ignore_ascii_case("font-variant") { return None }
+    let value = property.value_to_css_string(PrinterOptions::default()).ok()?;
+    value.split_whitespace().find_map(|token| FontVariantCaps::parse_string(token).ok())
+}
+
+fn resolve_text_style(font_style: &FontStyle) -> floem_renderer::text::Style {
+    match font_style {
+        FontStyle::Normal => floem_renderer::text::Style::Normal,
+        FontStyle::Italic => floem_renderer::text::Style::Italic,
+        FontStyle::Oblique(_) => floem_renderer::text::Style::Oblique,
+    }
+}
+
+// `currentColor` resolves to None so it can pick up the element's own color later
+fn resolve_color(color: &CssColor) -> Option<CSSValue> {
+    match color {
+        CssColor::CurrentColor => Some(CSSValue::Color(None)),
+        _ => RGBA::try_from(color).ok()
+            .map(|rgba| CSSValue::Color(Some(Color::rgba8(rgba.red, rgba.green, rgba.blue, rgba.alpha)))),
+    }
+}
+
+// Counter styles without a marker of their own fall back to decimal
+pub fn resolve_list_style_type(value: &ListStyleType) -> Option<ListType> {
+    match value {
+        ListStyleType::None => Some(ListType::None),
+        ListStyleType::String(_) => None,
+        ListStyleType::CounterStyle(CounterStyle::Predefined(style)) => Some(match style {
+            PredefinedCounterStyle::Disc        => ListType::Unordered(BulletStyle::Disc),
+            PredefinedCounterStyle::Circle      => ListType::Unordered(BulletStyle::Circle),
+            PredefinedCounterStyle::Square      => ListType::Unordered(BulletStyle::Square),
+            PredefinedCounterStyle::LowerAlpha | PredefinedCounterStyle::LowerLatin => ListType::Ordered(NumberStyle::LowerAlpha),
+            PredefinedCounterStyle::UpperAlpha | PredefinedCounterStyle::UpperLatin => ListType::Ordered(NumberStyle::UpperAlpha),
+            PredefinedCounterStyle::LowerRoman  => ListType::Ordered(NumberStyle::LowerRoman),
+            PredefinedCounterStyle::UpperRoman  => ListType::Ordered(NumberStyle::UpperRoman),
+            _ => ListType::Ordered(NumberStyle::Decimal),
+        }),
+        ListStyleType::CounterStyle(_) => Some(ListType::Ordered(NumberStyle::Decimal)),
+    }
+}
+
+fn resolve_border_width(width: &BorderSideWidth, parse_state: &ParseState, font_size: f64) -> f64 {
+    match width {
+        BorderSideWidth::Thin   => 1.,
+        BorderSideWidth::Medium => MEDIUM_BORDER_WIDTH,
+        BorderSideWidth::Thick  => 5.,
+        BorderSideWidth::Length(length) => resolve_length_value(length, parse_state, font_size),
+    }
+}
+
+fn resolve_length_value(length: &Length, parse_state: &ParseState, font_size: f64) -> f64 {
+    match length {
+        Length::Value(value) => resolve_length_percentage(&LengthPercentage::Dimension(value.clone()), parse_state, font_size, false),
+        Length::Calc(_) => 0.,
+    }
+}
+
+// Only lengths and percentages give a used size, the intrinsic keywords behave like `auto`
+fn resolve_size(size: &CssSize) -> Option<LengthPercentage> {
+    match size {
+        CssSize::LengthPercentage(length) => Some(length.clone()),
+        _ => None,
+    }
+}
+
+fn resolve_max_size(size: &MaxSize) -> Option<LengthPercentage> {
+    match size {
+        MaxSize::LengthPercentage(length) => Some(length.clone()),
+        _ => None,
+    }
+}
+
+fn resolve_border_collapse(property: &Property) -> Option<bool> {
+    let Property::Custom(custom) = property else { return None };
+    if !custom.name.as_ref().eq_ignore_ascii_case("border-collapse") { return None }
+    match property.value_to_css_string(PrinterOptions::default()).ok()?.trim() {
+        "collapse" => Some(true),
+        "separate" => Some(false),
+        _ => None,
+    }
+}
+
+// `float` and `clear` are not parsed by lightningcss either
+fn resolve_float(property: &Property) -> Option<Option<FloatSide>> {
+    let Property::Custom(custom) = property else { return None };
+    if !custom.name.as_ref().eq_ignore_ascii_case("float") { return None }
+    match property.value_to_css_string(PrinterOptions::default()).ok()?.trim() {
+        "left" | "inline-start"     => Some(Some(FloatSide::Left)),
+        "right" | "inline-end"      => Some(Some(FloatSide::Right)),
+        "none"                      => Some(None),
+        _ => None,
+    }
+}
+
+fn resolve_clear(property: &Property) -> Option<Clear> {
+    let Property::Custom(custom) = property else { return None };
+    if !custom.name.as_ref().eq_ignore_ascii_case("clear") { return None }
+    match property.value_to_css_string(PrinterOptions::default()).ok()?.trim() {
+        "left" | "inline-start"     => Some(Clear::Left),
+        "right" | "inline-end"      => Some(Clear::Right),
+        "both"                      => Some(Clear::Both),
+        "none"                      => Some(Clear::None),
+        _ => None,
+    }
+}
+
+// `initial-letter: <size> <sink>?`, the sink defaulting to the size rounded down
+fn resolve_initial_letter(property: &Property) -> Option<Option<InitialLetter>> {
+    let Property::Custom(custom) = property else { return None };
+    let name = custom.name.as_ref();
+    if !name.eq_ignore_ascii_case("initial-letter") && !name.eq_ignore_ascii_case("-webkit-initial-letter") { return None }
+    let value = property.value_to_css_string(PrinterOptions::default()).ok()?;
+    let mut parts = value.split_whitespace();
+    let size = parts.next()?;
+    if size.eq_ignore_ascii_case("normal") { return Some(None) }
+    let size: f64 = size.parse().ok().filter(|size| *size >= 1.)?;
+    let sink = parts.next().and_then(|sink| sink.parse().ok()).unwrap_or(size.floor() as usize).max(1);
+    Some(Some(InitialLetter { size, sink }))
+}
+
+// Fragmentation is not parsed by lightningcss either, the `page-break-*` properties are legacy names for the same
+fn resolve_break(property: &Property) -> Option<(PropertyKey, Break)> {
+    let Property::Custom(custom) = property else { return None };
+    let key = match custom.name.as_ref().to_ascii_lowercase().as_str() {
+        "break-before" | "page-break-before"    => PropertyKey::BreakBefore,
+        "break-after" | "page-break-after"      => PropertyKey::BreakAfter,
+        "break-inside" | "page-break-inside"    => PropertyKey::BreakInside,
+        _ => return None,
+    };
+    let value = match property.value_to_css_string(PrinterOptions::default()).ok()?.trim() {
+        "auto"                                  => Break::Auto,
+        "avoid" | "avoid-page" | "avoid-column" => Break::Avoid,
+        "column"                                => Break::Column,
+        "always" | "page" | "left" | "right" | "recto" | "verso" => Break::Page,
+        _ => return None,
+    };
+    Some((key, value))
+}
+
+// `orphans` and `widows` take a positive number of lines
+fn resolve_line_count(property: &Property) -> Option<(PropertyKey, usize)> {
+    let Property::Custom(custom) = property else { return None };
+    let key = match custom.name.as_ref().to_ascii_lowercase().as_str() {
+        "orphans"   => PropertyKey::Orphans,
+        "widows"    => PropertyKey::Widows,
+        _ => return None,
+    };
+    let count = property.value_to_css_string(PrinterOptions::default()).ok()?.trim().parse().ok().filter(|count| *count >= 1)?;
+    Some((key, count))
+}
+
 fn resolve_font_weight(font_weight: &FontWeight) -> u16{
     match font_weight {
         FontWeight::Absolute(absolute_value) => {
@@ -552,6 +1176,23 @@ fn resolve_length_percentage(length: &LengthPercentage, parse_state: &ParseState
     }
 }
 
+// Percentages refer to the width of the parent, which stands in for the containing block
+fn resolve_inset(value: &LengthPercentageOrAuto, parse_state: &ParseState, font_size: f64) -> Option<f64> {
+    match value {
+        LengthPercentageOrAuto::Auto => None,
+        LengthPercentageOrAuto::LengthPercentage(length) => Some(resolve_length_percentage(length, parse_state, font_size, false)),
+    }
+}
+
+// Fixed boxes are placed against the page like absolute ones, sticky boxes stay in the flow
+fn resolve_position(position: &CssPosition) -> Position {
+    match position {
+        CssPosition::Static | CssPosition::Sticky(_)    => Position::Static,
+        CssPosition::Relative                           => Position::Relative,
+        CssPosition::Absolute | CssPosition::Fixed      => Position::Absolute,
+    }
+}
+
 fn resolve_length(value: &LengthPercentageOrAuto, parse_state: &ParseState, font_size: f64) -> f64 {
     match value {
         LengthPercentageOrAuto::Auto => {0.}
@@ -566,9 +1207,54 @@ fn resolve_length(value: &LengthPercentageOrAuto, parse_state: &ParseState, font
 
 
 mod tests {
+    use lightningcss::properties::Property;
     use lightningcss::rules::CssRule;
     use lightningcss::selector::{Combinator, Component};
     use lightningcss::stylesheet::{ParserOptions, StyleSheet};
+    use crate::book_elem::Break;
+    use super::{resolve_break, resolve_line_count, PropertyKey};
+
+    fn declarations(css: &str, mut resolve: impl FnMut(&Property)) {
+        let css         = format!("p {{ {css} }}");
+        let style_sheet = StyleSheet::parse(&css, ParserOptions::default()).unwrap();
+        let CssRule::Style(style_rule) = &style_sheet.rules.0[0] else { panic!("not a style rule") };
+        style_rule.declarations.declarations.iter().for_each(|property| resolve(property));
+    }
+
+    fn breaks(css: &str) -> Vec<(PropertyKey, Break)> {
+        let mut breaks = Vec::new();
+        declarations(css, |property| breaks.extend(resolve_break(property)));
+        breaks
+    }
+
+    fn line_counts(css: &str) -> Vec<(PropertyKey, usize)> {
+        let mut counts = Vec::new();
+        declarations(css, |property| counts.extend(resolve_line_count(property)));
+        counts
+    }
+
+    #[test]
+    fn breaks_resolve_with_their_legacy_names() {
+        assert!(breaks("break-before: page; break-after: column; break-inside: avoid") == vec![
+            (PropertyKey::BreakBefore, Break::Page), (PropertyKey::BreakAfter, Break::Column), (PropertyKey::BreakInside, Break::Avoid)]);
+        assert!(breaks("page-break-before: always; page-break-after: avoid; page-break-inside: auto") == vec![
+            (PropertyKey::BreakBefore, Break::Page), (PropertyKey::BreakAfter, Break::Avoid), (PropertyKey::BreakInside, Break::Auto)]);
+    }
+
+    #[test]
+    fn break_values_fold_into_what_the_reader_does() {
+        for value in ["left", "right", "recto", "verso"] {
+            assert!(breaks(&format!("break-before: {value}")) == vec![(PropertyKey::BreakBefore, Break::Page)]);
+        }
+        assert!(breaks("break-inside: avoid-page; break-after: avoid-column") == vec![(PropertyKey::BreakInside, Break::Avoid), (PropertyKey::BreakAfter, Break::Avoid)]);
+        assert!(breaks("break-before: region; color: red").is_empty());
+    }
+
+    #[test]
+    fn line_counts_are_positive() {
+        assert!(line_counts("orphans: 3; widows: 1") == vec![(PropertyKey::Orphans, 3), (PropertyKey::Widows, 1)]);
+        assert!(line_counts("orphans: 0; widows: -2; orphans: 1.5; widows: auto").is_empty());
+    }
 
     fn print_selectors(css: &str) {
         let style_sheet = StyleSheet::parse(css, ParserOptions::default()).unwrap();
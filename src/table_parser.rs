@@ -1,159 +1,310 @@
 use floem::kurbo::{Point, Size};
+use floem::peniko::Color;
 use floem_renderer::text::Attrs;
 use lightningcss::properties::text::TextAlign;
 use lightningcss::stylesheet::StyleSheet;
 use roxmltree::{Document, Node};
 use scraper::ElementRef;
-use crate::book_elem::{BookElemFactory, CharGlyph, Elem, ElemLine, ElemLines, ElemType, InlineContent, InlineElem, ParseState};
+use crate::book_elem::{BlockDecoration, BlockElem, BookElemFactory, CharGlyph, Elem, ElemType, Fragmentation, InlineContent, InlineElem, InlineItem, ParseState};
 use crate::layout::layout_elem_lines;
-use crate::styling::style::resolve_style_scraper;
+use crate::styling::style::resolve_style_cached;
 const CELL_PAD_X: f64 = 10.0;   // px on the left *and* right
 const CELL_PAD_Y: f64 = 2.0;   // px on the top *and* bottom
-const ROW_GAP   : f64 = 6.0;   // empty space *between* rows
+pub(crate) const MEASURE_WIDTH: f64 = 100_000.;   // wide enough that nothing wraps while measuring
+const MAX_SPAN  : usize = 1000;
+const MIN_COLUMN_WIDTH: f64 = 50.;   // narrower than this on average and a table opens in the table view
+/// Link target of a wide table's placeholder, followed by the table's index in its page
+pub const TABLE_LINK: &str = "table:";
+// Laid out cell content, placed at the cell's column with its top at 0 until its row is placed
+struct TableCell<'a> {
+    node: Node<'a, 'a>,
+    row: usize,
+    col: usize,
+    row_span: usize,
+    col_span: usize,
+    min_width: f64,
+    max_width: f64,
+    decoration: Option<Box<BlockDecoration>>,
+    elem: Option<Elem>,
+    height: f64,
+    top: f64,
+}
+
+struct TableRow<'a> {
+    node: Node<'a, 'a>,
+    font: Attrs<'a>,
+    parse_state: ParseState,
+    background: Option<Color>,
+}
+
 impl BookElemFactory {
-    pub fn parse_table(
+    pub fn parse_table<'a>(
         &mut self,
-        node: Node,
-        font: Attrs,
+        node: Node<'a, 'a>,
+        mut font: Attrs<'a>,
         style_sheets: &Vec<StyleSheet>,
-        mut parse_state: ParseState,
+        parse_state: ParseState,
         mut index: Vec<usize>,
-        document: &Document,
+        document: &'a Document,
     ) -> Elem {
-        use crate::book_elem::InlineItem;
-
         let init_point = Point::new(self.curr_x, self.curr_y);
-
-        struct TableCell {
-            lines: ElemLines,
-            width: f64,
-            height: f64,
+        let (margins, mut parse_state) = resolve_style_cached(style_sheets, &node, &mut font, parse_state, document, &mut self.style_cache);
+        parse_state.ancestors.push(node.id());
+        parse_state.width   -= margins.left + margins.right;
+        parse_state.x       += margins.left / 2.;
+        self.curr_x         = parse_state.x;
+        self.curr_y         += margins.top;
+        let mut decoration  = parse_state.block_decoration.take();
+        if let Some(id) = node.attribute("id") {
+            self.locations.insert(id.to_string(), index.clone());
         }
+        index.push(0);
 
-        struct ParsedTableCell {
-            inline_items: Vec<InlineItem>,
-            text_align: TextAlign
+        let mut block_elem = BlockElem { children: Vec::new(), total_child_count: 0, details: None, fragmentation: Fragmentation::default(), row: false };
+        for caption in node.children().filter(|child| child.has_tag_name("caption")) {
+            block_elem.add_child(self.parse(caption, font, style_sheets, parse_state.clone(), index.clone(), document));
+            *index.last_mut().unwrap() += 1;
         }
-        
 
-        let mut parsed_table_rows: Vec<Vec<ParsedTableCell>> = Vec::new();
+        let rows        = self.table_rows(node, style_sheets, font, &parse_state, document);
+        let mut cells   = table_cells(&rows);
+        let col_count   = cells.iter().map(|cell| cell.col + cell.col_span).max().unwrap_or(0);
+
+        // Floats outside the table do not narrow its cells, and floats inside a cell stay in it
+        let outer_floats = std::mem::take(&mut self.floats);
+        // Measure every cell at its preferred width without committing anything it parses
+        for cell in cells.iter_mut() {
+            let row = &rows[cell.row];
+            let (curr_x, curr_y, counters) = (self.curr_x, self.curr_y, self.counters.clone());
+            let mut measure_state = row.parse_state.clone();
+            measure_state.x     = 0.;
+            measure_state.width = MEASURE_WIDTH;
+            let elem = self.parse(cell.node, row.font, style_sheets, measure_state, index.clone(), document);
+            (self.curr_x, self.curr_y, self.counters) = (curr_x, curr_y, counters);
+            self.floats.clear();
+            (cell.min_width, cell.max_width) = self.content_widths(&elem);
+        }
 
-        let mut table_rows: Vec<Vec<TableCell>> = Vec::new();
-        let mut max_cols = 0;
-        //parse_state.width = 200. - CELL_PAD_X;
-        for row_node in node.children().filter(|n| n.has_tag_name("tr")) {
-            let col_count = row_node.children().filter(|n| n.has_tag_name("td") || n.has_tag_name("th")).count();
-            max_cols = max_cols.max(col_count);
+        let mut min_widths: Vec<f64> = vec![0.0; col_count];
+        let mut max_widths: Vec<f64> = vec![0.0; col_count];
+        for cell in cells.iter().filter(|cell| cell.col_span == 1) {
+            min_widths[cell.col] = min_widths[cell.col].max(cell.min_width);
+            max_widths[cell.col] = max_widths[cell.col].max(cell.max_width);
         }
-        let mut min_widths: Vec<f64> = vec![0.0; max_cols];
-        let mut max_widths: Vec<f64> = vec![0.0; max_cols];
-        for row_node in node.children().filter(|n| n.has_tag_name("tr")) {
-            let mut parsed_row_cells: Vec<ParsedTableCell> = Vec::new();
-            for (idx, cell_node) in row_node.children().filter(|n| n.has_tag_name("td") || n.has_tag_name("th")).enumerate() {
-                let (inline_items, text_align) = self.parse_inline(cell_node, style_sheets, font, parse_state.clone(), None, &index, document);
-                let mut min_width: f64 = 0.;
-                let mut max_width = 0.;
-                for inline_item in inline_items.iter() {
-                    let inline_width = inline_item.size.width;
-                    min_width = min_width.max(inline_width + CELL_PAD_X);
-                    max_width += inline_width;
+        // Spanning cells share whatever their columns are missing evenly
+        for cell in cells.iter().filter(|cell| cell.col_span > 1) {
+            let span = cell.col..cell.col + cell.col_span;
+            let gaps = CELL_PAD_X * (cell.col_span - 1) as f64;
+            for (widths, wanted) in [(&mut min_widths, cell.min_width), (&mut max_widths, cell.max_width)] {
+                let missing = wanted - gaps - widths[span.clone()].iter().sum::<f64>();
+                if missing > 0. {
+                    widths[span.clone()].iter_mut().for_each(|width| *width += missing / cell.col_span as f64);
                 }
-                min_widths[idx] = min_widths[idx].max(min_width);
-                max_widths[idx] = max_widths[idx].max(max_width);
-                let parsed_table_cell = ParsedTableCell {inline_items, text_align};
-                parsed_row_cells.push(parsed_table_cell);
             }
-            parsed_table_rows.push(parsed_row_cells);
         }
 
-        //parse_state.width = (parse_state.width / max_cols as f64);
-        //let col_widths = resolve_auto_widths(&min_widths, &max_widths, parse_state.width);
-        let max_width = parse_state.width - CELL_PAD_X * (max_cols - 1) as f64;
-        let col_widths = resolve_auto_widths(&min_widths, &max_widths, max_width);
-        for row in parsed_table_rows {
-            let mut col_idx = 0;
-            let mut row_cells: Vec<TableCell> = Vec::new();
-
-            for cell in row {
-                parse_state.width = *col_widths.get(col_idx).unwrap();
-                parse_state.text_align = cell.text_align;
-                let elem = layout_elem_lines(self, cell.inline_items, &parse_state);
-                self.curr_y -= elem.size.height;
-                match elem.elem_type {
-                    ElemType::Lines(lines) => {
-                        row_cells.push(TableCell {
-                            width: elem.size.width,
-                            height: elem.size.height,
-                            lines,
-                        });
-                    }
-                    _ => panic!("Expected table cell to yield ElemType::Lines"),
+        let max_width   = parse_state.width - CELL_PAD_X * col_count as f64;
+        // Squashing a table this far leaves one word per line, it is laid out at its natural width for the table view instead
+        let wide        = min_widths.iter().sum::<f64>() > max_width
+            || (max_widths.iter().sum::<f64>() > max_width && max_width / (col_count as f64) < MIN_COLUMN_WIDTH);
+        let col_widths  = match wide {
+            true    => max_widths.clone(),
+            false   => resolve_auto_widths(&min_widths, &max_widths, max_width),
+        };
+        let (flow_x, flow_y) = (parse_state.x, self.curr_y);
+        if wide {
+            parse_state.x   = 0.;
+            self.curr_y     = 0.;
+        }
+        let mut row_elems = Vec::with_capacity(rows.len());
+        let mut col_x   = Vec::with_capacity(col_count);
+        let mut x       = CELL_PAD_X / 2.;
+        for width in &col_widths {
+            col_x.push(x);
+            x += width + CELL_PAD_X;
+        }
+        let table_width = x - CELL_PAD_X / 2.;
+
+        for cell in cells.iter_mut() {
+            let row = &rows[cell.row];
+            let mut cell_state  = row.parse_state.clone();
+            cell_state.x        = 0.;
+            cell_state.width    = col_widths[cell.col..cell.col + cell.col_span].iter().sum::<f64>() + CELL_PAD_X * (cell.col_span - 1) as f64;
+            let curr_y          = self.curr_y;
+            let mut elem        = self.parse(cell.node, row.font, style_sheets, cell_state, index.clone(), document);
+            self.curr_y         = curr_y;
+            self.floats.clear();
+            cell.decoration     = elem.decoration.take();
+            cell.height         = content_bottom(&elem) - elem.point.y;
+            // Content starts at twice the table's x, the cell's column is added on top
+            elem.translate(2. * parse_state.x + col_x[cell.col], -elem.point.y);
+            cell.elem           = Some(elem);
+        }
+        collapse_borders(&mut cells, parse_state.border_collapse);
+
+        let content_x   = 2. * parse_state.x;
+        let inset       = if parse_state.border_collapse { 0. } else { parse_state.border_spacing / 2. };
+        let mut pending: Vec<usize> = Vec::new();
+        for (row_idx, row) in rows.iter().enumerate() {
+            pending.extend((0..cells.len()).filter(|&i| cells[i].row == row_idx));
+            let row_cells: Vec<usize> = pending.iter().copied().filter(|&i| cells[i].row + cells[i].row_span > row_idx).collect();
+            let pad_top     = CELL_PAD_Y + inset + row_cells.iter().map(|&i| border_width(&cells[i], 0)).fold(0., f64::max);
+            let pad_bottom  = CELL_PAD_Y + inset + row_cells.iter().map(|&i| border_width(&cells[i], 2)).fold(0., f64::max);
+            let row_top     = self.curr_y;
+
+            // Each cell keeps its own lines, starting below the padding of the row it starts in
+            let mut row_block = BlockElem { children: Vec::new(), total_child_count: 0, details: None, fragmentation: Fragmentation::default(), row: true };
+            for &i in &row_cells {
+                if cells[i].row != row_idx { continue }
+                cells[i].top = row_top + pad_top;
+                if let Some(mut elem) = cells[i].elem.take() {
+                    elem.translate(0., cells[i].top);
+                    row_block.add_child(elem);
                 }
-                col_idx += 1;
             }
-            table_rows.push(row_cells);
-
+            // Spanning cells only force the row taller on their last row
+            let height = row_cells.iter()
+                .filter(|&&i| cells[i].row + cells[i].row_span - 1 == row_idx)
+                .map(|&i| cells[i].top + cells[i].height + pad_bottom - row_top)
+                .fold(pad_top + pad_bottom, f64::max);
+
+            let cell_boxes = row_cells.iter().map(|&i| {
+                let cell        = &cells[i];
+                let first_row   = cell.row == row_idx;
+                let last_row    = cell.row + cell.row_span - 1 == row_idx;
+                let mut cell_box = cell.decoration.as_deref().cloned().unwrap_or_default();
+                if !first_row { cell_box.borders[0].width = 0. }
+                if !last_row  { cell_box.borders[2].width = 0. }
+                let span_width  = col_widths[cell.col..cell.col + cell.col_span].iter().sum::<f64>() + CELL_PAD_X * (cell.col_span - 1) as f64;
+                cell_box.x              = content_x + col_x[cell.col] - CELL_PAD_X / 2. + inset;
+                cell_box.width          = span_width + CELL_PAD_X - 2. * inset;
+                cell_box.margin_top     = if first_row { inset } else { 0. };
+                cell_box.margin_bottom  = if last_row { inset } else { 0. };
+                cell_box
+            }).collect();
+            let row_box = BlockDecoration { background: row.background, x: content_x, width: table_width, cells: cell_boxes, ..Default::default() };
+
+            row_elems.push(Elem {
+                size: Size::new(table_width, height),
+                point: Point::new(parse_state.x, row_top),
+                elem_type: ElemType::Block(row_block),
+                decoration: Some(Box::new(row_box)),
+            });
+            self.curr_y += height;
+            for id in row.node.descendants().filter_map(|descendant| descendant.attribute("id")) {
+                self.locations.insert(id.to_string(), index.clone());
+            }
+            if !wide { *index.last_mut().unwrap() += 1 }
         }
 
-        // Step 2: Compute natural widths and row heights
-        //let mut col_widths: Vec<f64> = vec![0.0; max_cols];
-        let mut row_heights: Vec<f64> = Vec::with_capacity(table_rows.len());
-
-        // Step 4: Lay out rows
-        let mut lines: Vec<ElemLine> = Vec::new();
-        let mut y_cursor               = 0.0;
-        let mut tallest_row_idx: usize = 0;
-        let mut tallest_row_height     = 0.0;
-
-        let mut total_height = 0.;
-        for (row_idx, row) in table_rows.iter().enumerate() {
-            // ───────────── 1. how many display‑lines does this row span? ───────────
-            let max_lines = row
-                .iter()
-                .map(|cell| cell.lines.elem_lines.len())
-                .max()                     // longest column decides
-                .unwrap_or(0);             // row might be empty
-
-            let mut row_height = 0.0;      // total (visual) height of this row so far
-
-            // ───────────── 2. build every logical line in this row ─────────────────
-            for line_idx in 0..max_lines {
-                let mut x_cursor      = 0.0;
-                let mut inline_elems  = Vec::<InlineElem>::new();
-
-                let mut line_height: f64   = 0.0;
-                for (col_idx, cell) in row.iter().enumerate() {
-
-                    if let Some(elem_line) = cell.lines.elem_lines.get(line_idx) {
-                        line_height = line_height.max(elem_line.height);
-
-                        // Copy inline elements and shift them horizontally
-                        for mut inline in elem_line.inline_elems.clone() {
-                            inline.x += x_cursor;
-                            inline_elems.push(inline);
-                        }
-                    }
+        self.floats = outer_floats;
+
+        let mut decoration = decoration.take().map(|mut decoration| {
+            decoration.x        += 2. * parse_state.x;
+            decoration.width    += table_width;
+            Box::new(decoration)
+        });
+        if wide {
+            let height = self.curr_y;
+            let table  = BlockElem { children: row_elems, total_child_count: 0, details: None, fragmentation: Fragmentation::default(), row: false };
+            self.tables.push(Elem { size: Size::new(table_width, height), point: Point::new(0., 0.), elem_type: ElemType::Block(table), decoration: decoration.take() });
+            (parse_state.x, self.curr_y) = (flow_x, flow_y);
+            let label = format!("View table ({} × {})", rows.len(), col_count);
+            let href  = format!("{TABLE_LINK}{}", self.tables.len() - 1);
+            block_elem.add_child(self.link_line(&label, href, font, &parse_state));
+        } else {
+            row_elems.into_iter().for_each(|row_elem| block_elem.add_child(row_elem));
+        }
+        self.curr_y += margins.bottom;
+        let block_height = block_elem.children.iter().fold(0., |acc, elem| acc + elem.size.height);
+        Elem { size: Size::new(600., block_height + margins.top + margins.bottom), point: init_point, elem_type: ElemType::Block(block_elem), decoration }
+    }
 
-                    x_cursor += col_widths[col_idx] + CELL_PAD_X;
-                }
+    // A line holding a single link, standing in for content shown elsewhere
+    fn link_line(&mut self, text: &str, href: String, font: Attrs, parse_state: &ParseState) -> Elem {
+        let mut char_x = 0.;
+        let mut height: f64 = 0.;
+        let mut char_glyphs = Vec::with_capacity(text.len());
+        for ch in text.chars() {
+            let (text_layout, index) = self.cache.get_or_insert(ch, font, parse_state);
+            char_glyphs.push(CharGlyph { char: index, x: char_x });
+            char_x += text_layout.size().width as f32;
+            height = height.max(text_layout.size().height);
+        }
+        let item = InlineItem { size: Size::new(char_x as f64, height), inline_content: InlineContent::Link((char_glyphs, href)), style: parse_state.inline_style.clone() };
+        self.curr_x = parse_state.x;
+        layout_elem_lines(self, vec![item], parse_state)
+    }
 
-                row_height += line_height;     // accumulate row’s height
-                lines.push(ElemLine { height: line_height, inline_elems });
+    // Header rows first and footer rows last whatever their source order, each resolved through its row group
+    fn table_rows<'a>(&mut self, node: Node<'a, 'a>, style_sheets: &Vec<StyleSheet>, font: Attrs<'a>, parse_state: &ParseState, document: &'a Document) -> Vec<TableRow<'a>> {
+        let mut groups: [Vec<TableRow>; 3] = [Vec::new(), Vec::new(), Vec::new()];
+        for child in node.children().filter(|child| child.is_element()) {
+            let group = match child.tag_name().name() {
+                "thead" => 0,
+                "tbody" | "tr" => 1,
+                "tfoot" => 2,
+                _ => continue,
+            };
+            if child.has_tag_name("tr") {
+                groups[group].push(self.table_row(child, style_sheets, font, parse_state.clone(), None, document));
+                continue
+            }
+            let mut group_font = font;
+            let (_, mut group_state) = resolve_style_cached(style_sheets, &child, &mut group_font, parse_state.clone(), document, &mut self.style_cache);
+            let background = group_state.block_decoration.take().and_then(|decoration| decoration.background);
+            group_state.ancestors.push(child.id());
+            for row in child.children().filter(|row| row.has_tag_name("tr")) {
+                groups[group].push(self.table_row(row, style_sheets, group_font, group_state.clone(), background, document));
             }
-            total_height += row_height;
         }
+        groups.into_iter().flatten().collect()
+    }
 
-        let total_width = col_widths.iter().sum::<f64>();
-        //let total_height = row_heights.iter().sum::<f64>();
-        self.curr_y += total_height;
+    fn table_row<'a>(&mut self, node: Node<'a, 'a>, style_sheets: &Vec<StyleSheet>, mut font: Attrs<'a>, parse_state: ParseState, group_background: Option<Color>, document: &'a Document) -> TableRow<'a> {
+        let (_, mut parse_state) = resolve_style_cached(style_sheets, &node, &mut font, parse_state, document, &mut self.style_cache);
+        let background = parse_state.block_decoration.take().and_then(|decoration| decoration.background).or(group_background);
+        parse_state.ancestors.push(node.id());
+        TableRow { node, font, parse_state, background }
+    }
 
-        Elem {
-            size: Size::new(total_width, total_height),
-            point: init_point,
-            elem_type: ElemType::Lines(ElemLines {
-                height: 10.,
-                elem_lines: lines,
-            }),
+    // Minimum is the widest unbreakable item, maximum the widest line, both with the cell's own insets
+    pub(crate) fn content_widths(&self, elem: &Elem) -> (f64, f64) {
+        match &elem.elem_type {
+            // Cells side by side take the row's whole width
+            ElemType::Block(block) if block.row => (elem.size.width, elem.size.width),
+            ElemType::Block(block) => block.children.iter()
+                .map(|child| self.content_widths(child))
+                .fold((0., 0.), |(min, max), (child_min, child_max)| (f64::max(min, child_min), f64::max(max, child_max))),
+            ElemType::Lines(lines) => {
+                let inset = 4. * elem.point.x;
+                let mut min_width: f64 = 0.;
+                let mut max_width: f64 = 0.;
+                for line in &lines.elem_lines {
+                    let mut left    = f64::MAX;
+                    let mut right   = f64::MIN;
+                    for inline_elem in &line.inline_elems {
+                        let width   = self.inline_width(inline_elem);
+                        min_width   = min_width.max(width + inset);
+                        left        = left.min(inline_elem.x);
+                        right       = right.max(inline_elem.x + width);
+                    }
+                    if right > left { max_width = max_width.max(right - left + inset) }
+                }
+                (min_width, max_width)
+            }
+        }
+    }
+
+    fn inline_width(&self, inline_elem: &InlineElem) -> f64 {
+        match &inline_elem.inline_content {
+            InlineContent::Text(glyphs) | InlineContent::Link((glyphs, _)) => glyphs.last()
+                .map(|glyph| glyph.x as f64 + self.cache.get(glyph.char).size().width)
+                .unwrap_or_default(),
+            InlineContent::Image(image) => image.width as f64,
+            InlineContent::Math(math) => math.width,
+            InlineContent::Ruby(ruby) => ruby.width,
+            InlineContent::Break | InlineContent::Float(_) => 0.,
         }
     }
 
@@ -163,17 +314,12 @@ impl BookElemFactory {
         font: Attrs,
         style_sheets: &Vec<StyleSheet>,
         mut parse_state: ParseState,
-        mut index: Vec<usize>,
+        index: Vec<usize>,
     ) -> Elem {
         use crate::book_elem::InlineItem;
 
         let init_point = Point::new(self.curr_x, self.curr_y);
 
-        struct TableCell {
-            lines: ElemLines,
-            width: f64,
-            height: f64,
-        }
 
         struct ParsedTableCell {
             inline_items: Vec<InlineItem>,
@@ -181,18 +327,18 @@ impl BookElemFactory {
         }
 
         let mut parsed_table_rows: Vec<Vec<ParsedTableCell>> = Vec::new();
-        let mut table_rows: Vec<Vec<TableCell>> = Vec::new();
+        let mut table_rows: Vec<Vec<Elem>> = Vec::new();
         let mut max_cols = 0;
 
         // Find all tr elements using scraper
         let tr_elements: Vec<ElementRef> = elem_ref.children()
-            .filter_map(|node| ElementRef::wrap(node))
+            .filter_map(ElementRef::wrap)
             .filter(|elem| elem.value().name() == "tr")
             .collect();
 
         for row_elem in &tr_elements {
             let col_count = row_elem.children()
-                .filter_map(|node| ElementRef::wrap(node))
+                .filter_map(ElementRef::wrap)
                 .filter(|elem| elem.value().name() == "td" || elem.value().name() == "th")
                 .count();
             max_cols = max_cols.max(col_count);
@@ -205,7 +351,7 @@ impl BookElemFactory {
             let mut parsed_row_cells: Vec<ParsedTableCell> = Vec::new();
             
             let cell_elements: Vec<ElementRef> = row_elem.children()
-                .filter_map(|node| ElementRef::wrap(node))
+                .filter_map(ElementRef::wrap)
                 .filter(|elem| elem.value().name() == "td" || elem.value().name() == "th")
                 .collect();
 
@@ -230,82 +376,98 @@ impl BookElemFactory {
         let col_widths = resolve_auto_widths(&min_widths, &max_widths, max_width);
         
         for row in parsed_table_rows {
-            let mut col_idx = 0;
-            let mut row_cells: Vec<TableCell> = Vec::new();
+            let mut row_cells: Vec<Elem> = Vec::new();
 
-            for cell in row {
+            for (col_idx, cell) in row.into_iter().enumerate() {
                 parse_state.width = *col_widths.get(col_idx).unwrap();
                 parse_state.text_align = cell.text_align;
                 let elem = layout_elem_lines(self, cell.inline_items, &parse_state);
                 self.curr_y -= elem.size.height;
-                match elem.elem_type {
-                    ElemType::Lines(lines) => {
-                        row_cells.push(TableCell {
-                            width: elem.size.width,
-                            height: elem.size.height,
-                            lines,
-                        });
-                    }
-                    _ => panic!("Expected table cell to yield ElemType::Lines"),
-                }
-                col_idx += 1;
+                row_cells.push(elem);
             }
             table_rows.push(row_cells);
         }
 
-        let mut row_heights: Vec<f64> = Vec::with_capacity(table_rows.len());
-        let mut lines: Vec<ElemLine> = Vec::new();
-        let mut y_cursor = 0.0;
-        let mut tallest_row_idx: usize = 0;
-        let mut tallest_row_height = 0.0;
-
-        let mut total_height = 0.;
-        for (row_idx, row) in table_rows.iter().enumerate() {
-            let max_lines = row
-                .iter()
-                .map(|cell| cell.lines.elem_lines.len())
-                .max()
-                .unwrap_or(0);
-
-            let mut row_height = 0.0;
-
-            for line_idx in 0..max_lines {
-                let mut x_cursor = 0.0;
-                let mut inline_elems = Vec::<InlineElem>::new();
-                let mut line_height: f64 = 0.0;
-                
-                for (col_idx, cell) in row.iter().enumerate() {
-                    if let Some(elem_line) = cell.lines.elem_lines.get(line_idx) {
-                        line_height = line_height.max(elem_line.height);
-
-                        for mut inline in elem_line.inline_elems.clone() {
-                            inline.x += x_cursor;
-                            inline_elems.push(inline);
-                        }
-                    }
-                    x_cursor += col_widths[col_idx] + CELL_PAD_X;
-                }
-
-                row_height += line_height;
-                lines.push(ElemLine { height: line_height, inline_elems });
+        // Every cell keeps its own lines, side by side in a row as tall as the tallest of them
+        let total_width = col_widths.iter().sum::<f64>() + CELL_PAD_X * max_cols.saturating_sub(1) as f64;
+        let mut block_elem = BlockElem { children: Vec::new(), total_child_count: 0, details: None, fragmentation: Fragmentation::default(), row: false };
+        for row in table_rows {
+            let row_top = self.curr_y;
+            let mut row_block = BlockElem { children: Vec::new(), total_child_count: 0, details: None, fragmentation: Fragmentation::default(), row: true };
+            let mut x_cursor = 0.0;
+            let mut height: f64 = 0.0;
+            for (col_idx, mut cell) in row.into_iter().enumerate() {
+                cell.translate(x_cursor, row_top - cell.point.y);
+                height = height.max(cell.size.height);
+                row_block.add_child(cell);
+                x_cursor += col_widths[col_idx] + CELL_PAD_X;
             }
-            total_height += row_height;
+            block_elem.add_child(Elem { size: Size::new(total_width, height), point: Point::new(parse_state.x, row_top), elem_type: ElemType::Block(row_block), decoration: None });
+            self.curr_y += height;
         }
 
-        let total_width = col_widths.iter().sum::<f64>();
-        self.curr_y += total_height;
-
         Elem {
-            size: Size::new(total_width, total_height),
+            size: Size::new(total_width, self.curr_y - init_point.y),
             point: init_point,
-            elem_type: ElemType::Lines(ElemLines {
-                height: 10.,
-                elem_lines: lines,
-            }),
+            elem_type: ElemType::Block(block_elem),
+            decoration: None,
         }
     }
 }
 
+// Places cells on the grid, skipping slots still covered by a rowspan from above
+fn table_cells<'a>(rows: &[TableRow<'a>]) -> Vec<TableCell<'a>> {
+    let mut cells: Vec<TableCell> = Vec::new();
+    let mut occupied: Vec<Vec<bool>> = vec![Vec::new(); rows.len()];
+    for (row_idx, row) in rows.iter().enumerate() {
+        let mut col = 0;
+        for node in row.node.children().filter(|child| child.has_tag_name("td") || child.has_tag_name("th")) {
+            while occupied[row_idx].get(col).copied().unwrap_or(false) { col += 1 }
+            let col_span = node.attribute("colspan").and_then(|span| span.trim().parse().ok()).unwrap_or(1).clamp(1, MAX_SPAN);
+            // rowspan="0" runs to the end of the table
+            let row_span = match node.attribute("rowspan").and_then(|span| span.trim().parse::<usize>().ok()) {
+                Some(0) => rows.len() - row_idx,
+                Some(span) => span.min(rows.len() - row_idx),
+                None => 1,
+            };
+            for covered in &mut occupied[row_idx..row_idx + row_span] {
+                if covered.len() < col + col_span { covered.resize(col + col_span, false) }
+                covered[col..col + col_span].fill(true);
+            }
+            cells.push(TableCell { node, row: row_idx, col, row_span, col_span, min_width: 0., max_width: 0., decoration: None, elem: None, height: 0., top: 0. });
+            col += col_span;
+        }
+    }
+    cells
+}
+
+// Where the last lines of a laid out cell end, which can be below its box when its blocks overflow it
+fn content_bottom(elem: &Elem) -> f64 {
+    let bottom = elem.point.y + elem.size.height;
+    match &elem.elem_type {
+        ElemType::Block(block) => block.children.iter().map(content_bottom).fold(bottom, f64::max),
+        ElemType::Lines(lines) => bottom.max(elem.point.y + lines.height),
+    }
+}
+
+// Collapsed neighbours share one border, the cell to the left or above draws it
+fn collapse_borders(cells: &mut [TableCell], collapse: bool) {
+    if !collapse { return }
+    for i in 0..cells.len() {
+        let (row, col) = (cells[i].row, cells[i].col);
+        let left_drawn = cells.iter().any(|other| other.col + other.col_span == col && other.row <= row && row < other.row + other.row_span && border_width(other, 1) > 0.);
+        let top_drawn  = cells.iter().any(|other| other.row + other.row_span == row && other.col <= col && col < other.col + other.col_span && border_width(other, 2) > 0.);
+        if let Some(decoration) = &mut cells[i].decoration {
+            if left_drawn   { decoration.borders[3].width = 0. }
+            if top_drawn    { decoration.borders[0].width = 0. }
+        }
+    }
+}
+
+fn border_width(cell: &TableCell, side: usize) -> f64 {
+    cell.decoration.as_ref().map(|decoration| decoration.borders[side].width).unwrap_or(0.)
+}
+
 fn resolve_auto_widths(mins: &[f64], maxs: &[f64], total: f64) -> Vec<f64> {
     assert_eq!(mins.len(), maxs.len());
 